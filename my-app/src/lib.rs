@@ -1,8 +1,15 @@
-use ::puddlejumper::node::{Context, NodeId};
+use ::puddlejumper::node::{Context, Node, NodeId, SourceRange};
 use puddlejumper;
-use std::io::{Error, ErrorKind};
+use puddlejumper::history::History;
+use puddlejumper::node::printer::DEFAULT_INDENT_WIDTH;
+use puddlejumper::parser::{Parser, Update};
+use puddlejumper::projection::{PrioritizedView, ViewChange};
+use puddlejumper::tree_diff::UpdateContext;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::time::Duration;
 use wasm_bindgen::prelude::*;
-use web_sys::console;
 
 // When the `wee_alloc` feature is enabled, this uses `wee_alloc` as the global
 // allocator.
@@ -20,42 +27,276 @@ pub fn main_js() -> Result<(), JsValue> {
     #[cfg(debug_assertions)]
     console_error_panic_hook::set_once();
 
-    let Some((context, foo)) = parse("Hello\n\tP1\n\t\thello") else { return Ok(()) };
-
-    let mut out = Vec::new();
-    context.pretty_print(
-        foo,
-        &mut puddlejumper::node::printer::PrintContext {
-            level: 0,
-            needs_indent: true,
-            out: &mut out,
-        },
-    );
-    console::log_1(&JsValue::from_str(&String::from_utf8(out).unwrap()));
     Ok(())
 }
 
-fn parse(code: &str) -> Option<(Context, NodeId)> {
-    let p = puddlejumper::parser::Parser::new(code.into(), None);
-    p.load_document()
+// Identifies an item of a `Document` across updates, see `Document::item_at`.
+pub type ItemId = usize;
+
+// A puddlejumper document, kept in sync with the editor through `update`. Updates only go through
+// the incremental prioritized view; the whole document is loaded into a `Context` when `search`,
+// `to_json`, `diagnostics` and the like first need it after a change. A reload makes new
+// `NodeId`s (e.g. the ids in `to_json`), so the editor refers to items by the ids of `item_at`
+// instead. Like the ids of the prioritized view, they're carried over every update, undo and
+// redo to wherever the tree diff matched the item's node. Stable ids, i.e. a binding or `^id`
+// marker, are part of the text, so they also hold across sessions. `assign_ids` gives every item
+// one.
+#[wasm_bindgen]
+pub struct Document {
+    parser: Parser,
+    // The loaded document and its root, or `None` if it changed since it was last needed.
+    loaded: Option<(Context, NodeId)>,
+    history: History,
+    view: PrioritizedView,
+    // View changes since the last `view_changes`.
+    changes: Vec<ViewChange>,
+    // The ids handed out by `item_at`, by the range of their node.
+    items: HashMap<Range<usize>, ItemId>,
+    next_item: ItemId,
 }
 
-fn print_prioritized(code: &str) -> Option<String> {
-    let p = puddlejumper::parser::Parser::new(code.into(), None);
-    let mut output = Vec::new();
-    p.load_document()
-        .ok_or(Error::new(ErrorKind::Other, "Error parsing file"))
-        .and_then(|(mut ctx, node)| {
-            let list = ctx.make_prioritized_list(node);
-            return ctx.pretty_print(
-                list,
-                &mut puddlejumper::node::printer::PrintContext {
-                    level: 0,
-                    needs_indent: true,
-                    out: &mut output,
-                },
-            );
+#[wasm_bindgen]
+impl Document {
+    #[wasm_bindgen(constructor)]
+    pub fn new(text: String) -> Result<Document, JsValue> {
+        let parser = Parser::new(text, tree_sitter_puddlejumper::language());
+        let view = PrioritizedView::new(&parser).ok_or_else(parse_error)?;
+        Ok(Document {
+            parser,
+            loaded: None,
+            history: History::new(),
+            view,
+            changes: Vec::new(),
+            items: HashMap::new(),
+            next_item: 0,
         })
-        .ok()
-        .and_then(|_| Some(String::from_utf8(output).unwrap()))
+    }
+
+    // Reparses incrementally against the previous text. Edits in quick succession are undone
+    // together.
+    pub fn update(&mut self, text: String) {
+        let update = self.parser.update(text);
+        self.history
+            .record(&update, Duration::from_millis(js_sys::Date::now() as u64));
+        self.apply(update);
+    }
+
+    // Returns whether there was anything to undo.
+    pub fn undo(&mut self) -> bool {
+        match self.history.undo(&mut self.parser) {
+            Some(update) => {
+                self.apply(update);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn redo(&mut self) -> bool {
+        match self.history.redo(&mut self.parser) {
+            Some(update) => {
+                self.apply(update);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Adds a `^id` marker to every item without a binding or one, as an edit that can be undone.
+    // Returns how many were added.
+    pub fn assign_ids(&mut self) -> Result<usize, JsValue> {
+        let (context, root) = self.context()?;
+        let edits = context.assign_stable_ids(root);
+        if edits.is_empty() {
+            return Ok(0);
+        }
+        let update = self.parser.edit(&edits);
+        self.history
+            .record(&update, Duration::from_millis(js_sys::Date::now() as u64));
+        self.apply(update);
+        Ok(edits.len())
+    }
+
+    // The stable id of the innermost item at `byte` that has one, e.g. to find the item under the
+    // cursor again after an undo with `range_of`.
+    pub fn stable_id_at(&mut self, byte: usize) -> Result<Option<String>, JsValue> {
+        let (context, root) = self.context()?;
+        let mut node = context.node_at(root, byte);
+        while let Some(id) = node {
+            if let Some(stable_id) = context.stable_id(id) {
+                return Ok(Some(stable_id.to_string()));
+            }
+            node = context.parent(id).map(|(parent, _)| parent);
+        }
+        Ok(None)
+    }
+
+    // Where the item with this stable id is, as `{ start, end }` like in `diagnostics`, or `null`.
+    pub fn range_of(&mut self, stable_id: &str) -> Result<JsValue, JsValue> {
+        let (context, root) = self.context()?;
+        let range = context
+            .find_stable_id(root, stable_id)
+            .and_then(|node| context.source(node))
+            .map(|source| range_json(&source.range));
+        js_sys::JSON::parse(&range.unwrap_or(Value::Null).to_string())
+    }
+
+    // The id of the innermost item at `byte`, which stays the same for as long as the item is in
+    // the document, across edits to it and around it.
+    pub fn item_at(&mut self, byte: usize) -> Option<ItemId> {
+        let mut current = self
+            .parser
+            .tree
+            .root_node()
+            .descendant_for_byte_range(byte, byte);
+        while let Some(t_node) = current {
+            if t_node.kind() == "node" {
+                break;
+            }
+            current = t_node.parent();
+        }
+        let next_item = &mut self.next_item;
+        let id = self.items.entry(current?.byte_range()).or_insert_with(|| {
+            *next_item += 1;
+            *next_item
+        });
+        Some(*id)
+    }
+
+    // Where the item with this id is now, as `{ start, end }` like in `diagnostics`, or `null` if
+    // it was removed.
+    pub fn item_range(&self, id: ItemId) -> Result<JsValue, JsValue> {
+        let range = self
+            .items
+            .iter()
+            .find(|(_, item)| **item == id)
+            .and_then(|(range, _)| {
+                let mut current = self
+                    .parser
+                    .tree
+                    .root_node()
+                    .descendant_for_byte_range(range.start, range.end);
+                while let Some(t_node) = current {
+                    if t_node.kind() == "node" && t_node.byte_range() == *range {
+                        return Some(range_json(&SourceRange::from_node(&t_node)));
+                    }
+                    current = t_node.parent();
+                }
+                None
+            });
+        js_sys::JSON::parse(&range.unwrap_or(Value::Null).to_string())
+    }
+
+    fn apply(&mut self, update: Update) {
+        // Items whose node the diff didn't match are gone.
+        let matched = UpdateContext::new(&update).matched_ranges("node");
+        self.items = std::mem::take(&mut self.items)
+            .into_iter()
+            .filter_map(|(range, id)| Some((matched.get(&range)?.clone(), id)))
+            .collect();
+        let changes = self.view.apply(&mut self.parser, update);
+        self.changes.extend(changes);
+        self.loaded = None;
+    }
+
+    // The loaded document, loading it first if it changed since it was last needed.
+    fn context(&mut self) -> Result<(&mut Context, NodeId), JsValue> {
+        if self.loaded.is_none() {
+            self.loaded = Some(Context::from_parser(&self.parser).ok_or_else(parse_error)?);
+        }
+        match &mut self.loaded {
+            Some((context, root)) => Ok((context, *root)),
+            None => Err(parse_error()),
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn text(&self) -> String {
+        self.parser.text.clone()
+    }
+
+    // The prioritized list from the view, each bucket followed by the labels of its items.
+    pub fn print_prioritized(&self) -> String {
+        let indent_width = self.view.settings.indent_width.unwrap_or(DEFAULT_INDENT_WIDTH);
+        let mut out = String::new();
+        for (priority, items) in self.view.buckets() {
+            out.push_str(&format!("{}\n", priority));
+            for item in items {
+                out.push_str(&format!("{}{}\n", " ".repeat(indent_width), item.label));
+            }
+        }
+        out
+    }
+
+    // How the prioritized list changed since the last call, as `{ type, id, ... }` objects to patch
+    // a rendering of it with, see `puddlejumper::projection::ViewChange`.
+    pub fn view_changes(&mut self) -> Result<JsValue, JsValue> {
+        let changes: Vec<Value> = self.changes.drain(..).map(|change| change.to_json()).collect();
+        js_sys::JSON::parse(&Value::Array(changes).to_string())
+    }
+
+    // The best `limit` matches for `query`, as `{ label, breadcrumbs, start, end, ... }` objects,
+    // see `puddlejumper::node::search`.
+    pub fn search(&mut self, query: &str, limit: usize) -> Result<JsValue, JsValue> {
+        let (context, root) = self.context()?;
+        let results: Vec<Value> = context
+            .search(root, query, limit)
+            .iter()
+            .map(|result| result.to_json(context))
+            .collect();
+        js_sys::JSON::parse(&Value::Array(results).to_string())
+    }
+
+    // The document in the versioned schema described in `puddlejumper::node::json`.
+    pub fn to_json(&mut self) -> Result<String, JsValue> {
+        let (context, root) = self.context()?;
+        Ok(context.to_json(root).to_string())
+    }
+
+    // Parse errors as `{ message, start, end }` objects, where `start` and `end` are
+    // `{ byte, row, column }`.
+    pub fn diagnostics(&mut self) -> Result<JsValue, JsValue> {
+        let (context, root) = self.context()?;
+        let diagnostics: Vec<Value> = context
+            .errors(root)
+            .into_iter()
+            .filter_map(|error| match (&context.arena[error], context.source(error)) {
+                (Node::Error { text, .. }, Some(source)) => Some(json!({
+                    "message": format!("Unexpected {}", serde_json::to_string(text).unwrap()),
+                    "start": json!({
+                        "byte": source.range.start_byte,
+                        "row": source.range.start_position.row,
+                        "column": source.range.start_position.column,
+                    }),
+                    "end": json!({
+                        "byte": source.range.end_byte,
+                        "row": source.range.end_position.row,
+                        "column": source.range.end_position.column,
+                    }),
+                })),
+                _ => None,
+            })
+            .collect();
+        js_sys::JSON::parse(&Value::Array(diagnostics).to_string())
+    }
+}
+
+// `{ start, end }` as in `diagnostics`.
+fn range_json(range: &SourceRange) -> Value {
+    json!({
+        "start": json!({
+            "byte": range.start_byte,
+            "row": range.start_position.row,
+            "column": range.start_position.column,
+        }),
+        "end": json!({
+            "byte": range.end_byte,
+            "row": range.end_position.row,
+            "column": range.end_position.column,
+        }),
+    })
+}
+
+fn parse_error() -> JsValue {
+    JsValue::from_str("Error parsing document")
 }
@@ -1,35 +1,91 @@
-use wasm_bindgen_test::{wasm_bindgen_test_configure, wasm_bindgen_test};
-use futures::prelude::*;
+use my_app::Document;
 use wasm_bindgen::JsValue;
-use wasm_bindgen_futures::JsFuture;
-
-wasm_bindgen_test_configure!(run_in_browser);
+use wasm_bindgen_test::wasm_bindgen_test;
 
+// These run under Node (`wasm-pack test --node`), since no `wasm_bindgen_test_configure!` asks
+// for a browser.
 
 // This runs a unit test in native Rust, so it can only use Rust APIs.
 #[test]
 fn rust_test() {
-    assert_eq!(1, 1);
+    let document = Document::new(String::from("hello\n    P1\nworld")).unwrap();
+    assert_eq!(document.print_prioritized(), "P1\n    hello\n");
 }
 
+#[wasm_bindgen_test]
+fn test_print_prioritized() {
+    let document = Document::new(String::from("hello\n    P1\nworld\n    P0")).unwrap();
+    assert_eq!(document.print_prioritized(), "P0\n    world\nP1\n    hello\n");
+}
 
-// This runs a unit test in the browser, so it can use browser APIs.
 #[wasm_bindgen_test]
-fn web_test() {
-    assert_eq!(1, 1);
+fn test_update() {
+    let mut document = Document::new(String::from("hello")).unwrap();
+    document.update(String::from("hello\n    P2"));
+    assert_eq!(document.text(), "hello\n    P2");
+    assert_eq!(document.print_prioritized(), "P2\n    hello\n");
 }
 
+#[wasm_bindgen_test]
+fn test_to_json() {
+    let mut document = Document::new(String::from("@a: hello")).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&document.to_json().unwrap()).unwrap();
+    assert_eq!(json["version"], 1);
+    assert_eq!(json["nodes"][0]["kind"], "document");
+    assert_eq!(json["nodes"][1]["binding"], "a");
+    assert_eq!(json["nodes"][1]["content"]["text"], "hello");
+}
+
+#[wasm_bindgen_test]
+fn test_diagnostics() {
+    let mut document = Document::new(String::from("hello")).unwrap();
+    let diagnostics = document.diagnostics().unwrap();
+    assert_eq!(js_sys::Array::from(&diagnostics).length(), 0);
+
+    let mut document = Document::new(String::from("hello\n@@@ broken")).unwrap();
+    let diagnostics = js_sys::Array::from(&document.diagnostics().unwrap());
+    assert!(diagnostics.length() > 0);
+    let start = js_sys::Reflect::get(&diagnostics.get(0), &JsValue::from_str("start")).unwrap();
+    let row = js_sys::Reflect::get(&start, &JsValue::from_str("row")).unwrap();
+    assert_eq!(row.as_f64(), Some(1.0));
+}
+
+#[wasm_bindgen_test]
+fn test_stable_ids_survive_undo() {
+    let mut document = Document::new(String::from("hello\n    world ^k3x9qa")).unwrap();
+    assert_eq!(document.stable_id_at(12).unwrap(), Some(String::from("k3x9qa")));
+    assert_eq!(document.stable_id_at(2).unwrap(), None);
+
+    document.update(String::from("hi\nhello\n    world ^k3x9qa"));
+    let range = document.range_of("k3x9qa").unwrap();
+    let start = js_sys::Reflect::get(&range, &JsValue::from_str("start")).unwrap();
+    let row = js_sys::Reflect::get(&start, &JsValue::from_str("row")).unwrap();
+    assert_eq!(row.as_f64(), Some(2.0));
+
+    assert!(document.undo());
+    assert_eq!(document.stable_id_at(12).unwrap(), Some(String::from("k3x9qa")));
+    assert!(document.range_of("missing").unwrap().is_null());
+}
+
+#[wasm_bindgen_test]
+fn test_item_ids_survive_updates() {
+    let mut document = Document::new(String::from("hello\n    world")).unwrap();
+    let world = document.item_at(12).unwrap();
+    let hello = document.item_at(2).unwrap();
+    assert_ne!(world, hello);
+    assert_eq!(document.item_at(13), Some(world));
+
+    document.update(String::from("hi\nhello\n    world!"));
+    assert_eq!(document.item_at(15), Some(world));
+    let range = document.item_range(world).unwrap();
+    let start = js_sys::Reflect::get(&range, &JsValue::from_str("start")).unwrap();
+    let row = js_sys::Reflect::get(&start, &JsValue::from_str("row")).unwrap();
+    assert_eq!(row.as_f64(), Some(2.0));
 
-// This runs a unit test in the browser, and in addition it supports asynchronous Future APIs.
-#[wasm_bindgen_test(async)]
-fn async_test() -> impl Future<Item = (), Error = JsValue> {
-    // Creates a JavaScript Promise which will asynchronously resolve with the value 42.
-    let promise = js_sys::Promise::resolve(&JsValue::from(42));
+    assert!(document.undo());
+    assert_eq!(document.item_at(12), Some(world));
+    assert_eq!(document.item_at(2), Some(hello));
 
-    // Converts that Promise into a Future.
-    // The unit test will wait for the Future to resolve.
-    JsFuture::from(promise)
-        .map(|x| {
-            assert_eq!(x, 42);
-        })
+    document.update(String::from("hello"));
+    assert!(document.item_range(world).unwrap().is_null());
 }
@@ -0,0 +1,15 @@
+use std::io::{self, BufReader};
+
+fn main() {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut server = puddlejumper::lsp::Server::new();
+    if let Err(error) = server.run(&mut BufReader::new(stdin.lock()), &mut stdout.lock()) {
+        eprintln!("puddlejumper-lsp: {}", error);
+        std::process::exit(1);
+    }
+    // Per the protocol, exiting without a shutdown request is an error.
+    if !server.shutdown_requested {
+        std::process::exit(1);
+    }
+}
@@ -1,6 +1,8 @@
+use super::node::printer::unescape_content;
 use super::node::*;
 use super::parser::*;
 use id_arena::Arena;
+use indexmap::IndexMap;
 use std::{collections::HashMap, time::SystemTime};
 
 impl Context {
@@ -8,46 +10,90 @@ impl Context {
         Context {
             arena: Arena::new(),
             metadata: HashMap::new(),
+            sources: HashMap::new(),
+            stable_ids: HashMap::new(),
+            document_names: HashMap::new(),
+            parents: HashMap::new(),
+            gc_threshold: gc::MIN_GC_NODES,
         }
     }
 
     pub fn load_document(&mut self, parser: &Parser) -> Option<NodeId> {
-        let id = self.load(&parser.tree.root_node(), parser)?;
+        let root = parser.tree.root_node();
+        let id = if root.kind() == "document" {
+            self.load(&root, parser)?
+        } else {
+            // Recovery can leave an ERROR at the root, so wrap it to keep a document. What the
+            // wrapper has no place for is kept as a parse error rather than dropped.
+            let mut attributes: IndexMap<String, String> = IndexMap::new();
+            let mut children: Vec<NodeId> = Vec::new();
+            for child in root.children(&mut root.walk()) {
+                match child.kind() {
+                    "front_matter" => attributes.extend(load_front_matter(&child, parser)),
+                    "node" | "block" => children.extend(self.load(&child, parser)),
+                    _ if parser.get_text(child).trim().is_empty() => {}
+                    _ => children.push(self.load_error(&child, parser)),
+                }
+            }
+            let node = Node::Document {
+                attributes,
+                children,
+            };
+            self.alloc_loaded(node, &root, None, None)
+        };
         let now = SystemTime::now();
 
+        // Nodes from earlier loads into the same context keep their own.
         for (id, _node) in self.arena.iter() {
-            let metadata = NodeMetadata { created_at: now };
-            self.metadata.insert(id, metadata);
+            self.metadata
+                .entry(id)
+                .or_insert(NodeMetadata { created_at: now });
         }
 
         return Some(id);
     }
 
+    // A new context with the document in `parser` loaded, and the document's root.
+    pub fn from_parser(parser: &Parser) -> Option<(Context, NodeId)> {
+        let mut context = Context::new();
+        let root = context.load_document(parser)?;
+        Some((context, root))
+    }
+
     pub fn load(&mut self, t_node: &tree_sitter::Node, parser: &Parser) -> Option<NodeId> {
         if t_node.kind() == "document" {
-            let mut children: Vec<NodeId> = Vec::new();
-            for child in t_node.children_by_field_name("children", &mut t_node.walk()) {
-                self.load(&child, parser).map(|node_id| {
-                    children.push(node_id);
-                });
-            }
-            return Some(self.arena.alloc(Node::Document { children }));
+            let attributes = t_node
+                .child_by_field_name("front_matter")
+                .map(|front_matter| load_front_matter(&front_matter, parser))
+                .unwrap_or_default();
+            let children = self.load_children(t_node, parser);
+            let node = Node::Document {
+                attributes,
+                children,
+            };
+            return Some(self.alloc_loaded(node, t_node, None, None));
         }
         if t_node.kind() == "node" {
-            let binding: Option<String> = t_node
+            let binding_node: Option<tree_sitter::Node> = t_node
                 .child_by_field_name("binding")
                 .and_then(|binding: tree_sitter::Node| {
                     return binding.child_by_field_name("identifier");
-                })
-                .map(|identifier: tree_sitter::Node| {
-                    return parser.get_text(identifier).to_string();
+                });
+            let binding: Option<String> = binding_node.map(|identifier: tree_sitter::Node| {
+                return parser.get_text(identifier).to_string();
+            });
+            let content_node: Option<tree_sitter::Node> = t_node.child_by_field_name("content");
+            let stable_id: Option<String> = t_node
+                .child_by_field_name("id")
+                .map(|id: tree_sitter::Node| {
+                    return parser.get_text(id).trim_start_matches('^').to_string();
                 });
             let content: Option<Content> =
-                t_node
-                    .child_by_field_name("content")
+                content_node
                     .and_then(|n: tree_sitter::Node| {
                         if n.kind() == "content" {
-                            return Some(Content::Content(parser.get_text(n).to_string()));
+                            let text = unescape_content(parser.get_text(n));
+                            return Some(Content::Content(text));
                         }
                         if n.kind() == "ref" {
                             return Some(Content::Ref(parser.get_text(n).to_string()));
@@ -55,66 +101,351 @@ impl Context {
                         return None;
                     });
             let mut children: Vec<NodeId> = Vec::new();
-            t_node
-                .child_by_field_name("children")
-                .map(|child: tree_sitter::Node| {
-                    let cursor = &mut child.walk();
-                    cursor.goto_first_child();
-                    loop {
-                        let n = cursor.node();
-                        self.load(&n, parser).map(|node| {
-                            children.push(node);
-                        });
-                        if !cursor.goto_next_sibling() {
-                            break;
-                        }
-                    }
-                });
-            return Some(self.arena.alloc(Node::Node {
+            for child in t_node.children(&mut t_node.walk()) {
+                if child.kind() == "children" {
+                    children.extend(self.load_children(&child, parser));
+                } else if child.is_error() {
+                    children.push(self.load_error(&child, parser));
+                }
+            }
+            let node = Node::Node {
                 binding,
                 content,
                 children,
-            }));
+            };
+            let id = self.alloc_loaded(node, t_node, binding_node, content_node);
+            if let Some(stable_id) = stable_id {
+                self.stable_ids.insert(id, stable_id);
+            }
+            return Some(id);
         }
         if t_node.kind() == "block" {
-            let binding: Option<String> = t_node
+            let binding_node: Option<tree_sitter::Node> = t_node
                 .child_by_field_name("binding")
                 .and_then(|binding: tree_sitter::Node| {
                     return binding.child_by_field_name("identifier");
-                })
-                .map(|identifier: tree_sitter::Node| {
-                    return parser.get_text(identifier).to_string();
                 });
+            let binding: Option<String> = binding_node.map(|identifier: tree_sitter::Node| {
+                return parser.get_text(identifier).to_string();
+            });
+            let level: usize = t_node
+                .child_by_field_name("header")
+                .map(|header: tree_sitter::Node| {
+                    return parser
+                        .get_text(header)
+                        .chars()
+                        .take_while(|c| *c == '#')
+                        .count();
+                })
+                .unwrap_or(1);
             let header: Option<NodeId> =
                 t_node
                     .child_by_field_name("header")
+                    .and_then(|header: tree_sitter::Node| {
+                        // The header node follows the `#`s inside `block_header`.
+                        return header.named_child(0);
+                    })
                     .and_then(|child: tree_sitter::Node| {
                         return self.load(&child, parser);
                     });
-            let mut children: Vec<NodeId> = Vec::new();
-            t_node
-                .child_by_field_name("children")
-                .map(|child: tree_sitter::Node| {
-                    let cursor = &mut child.walk();
-                    cursor.goto_first_child();
-                    loop {
-                        let n = cursor.node();
-                        self.load(&n, parser).map(|node_id| {
-                            children.push(node_id);
+            // Keep the block even if its header didn't load, so that its children aren't dropped.
+            let header: NodeId = match header {
+                Some(header) => header,
+                None => match t_node.child_by_field_name("header") {
+                    Some(t_header) => self.load_error(&t_header, parser),
+                    None => {
+                        let start = t_node.start_byte();
+                        let id = self.arena.alloc(Node::Error {
+                            text: String::new(),
+                            range: start..start,
                         });
-                        if !cursor.goto_next_sibling() {
-                            break;
-                        }
+                        let range = SourceRange::empty(start, t_node.start_position());
+                        self.sources.insert(
+                            id,
+                            NodeSource {
+                                range,
+                                binding: None,
+                                content: None,
+                            },
+                        );
+                        id
                     }
-                });
-            return header.map(|header| {
-                return self.arena.alloc(Node::Block {
-                    binding,
-                    header,
-                    children,
-                });
-            });
+                },
+            };
+            let children = self.load_children(t_node, parser);
+            let node = Node::Block {
+                binding,
+                level,
+                header,
+                children,
+            };
+            return Some(self.alloc_loaded(node, t_node, binding_node, None));
+        }
+        if t_node.is_error() {
+            return Some(self.load_error(t_node, parser));
         }
         return None;
     }
+
+    // Loads the nodes, blocks and parse errors directly under `t_node`, in source order.
+    fn load_children(&mut self, t_node: &tree_sitter::Node, parser: &Parser) -> Vec<NodeId> {
+        let mut children: Vec<NodeId> = Vec::new();
+        for child in t_node.children(&mut t_node.walk()) {
+            match child.kind() {
+                "node" | "block" => {
+                    self.load(&child, parser).map(|node_id| {
+                        children.push(node_id);
+                    });
+                }
+                _ if child.is_error() => {
+                    children.push(self.load_error(&child, parser));
+                }
+                _ => {}
+            }
+        }
+        children
+    }
+
+    fn load_error(&mut self, t_node: &tree_sitter::Node, parser: &Parser) -> NodeId {
+        let node = Node::Error {
+            text: parser.get_text(*t_node).to_string(),
+            range: t_node.byte_range(),
+        };
+        self.alloc_loaded(node, t_node, None, None)
+    }
+
+    // Allocates `node` and remembers the source it was loaded from and whose parent it is.
+    fn alloc_loaded(
+        &mut self,
+        node: Node,
+        t_node: &tree_sitter::Node,
+        binding: Option<tree_sitter::Node>,
+        content: Option<tree_sitter::Node>,
+    ) -> NodeId {
+        let id = self.arena.alloc(node);
+        self.record_parent(id);
+        self.sources.insert(
+            id,
+            NodeSource {
+                range: SourceRange::from_node(t_node),
+                binding: binding.map(|n| SourceRange::from_node(&n)),
+                content: content.map(|n| SourceRange::from_node(&n)),
+            },
+        );
+        id
+    }
+
+    // Records `id` as the parent of its children, see `Context::parent`.
+    pub(crate) fn record_parent(&mut self, id: NodeId) {
+        if let Node::Document { children, .. }
+        | Node::Node { children, .. }
+        | Node::Block { children, .. } = &self.arena[id]
+        {
+            for (index, child) in children.iter().enumerate() {
+                self.parents.insert(*child, (id, index));
+            }
+        }
+    }
+
+    // Parse errors under `node`, in document order.
+    pub fn errors(&self, node: NodeId) -> Vec<NodeId> {
+        let mut errors: Vec<NodeId> = Vec::new();
+        self.errors_rec(node, &mut errors);
+        errors
+    }
+
+    fn errors_rec(&self, node: NodeId, errors: &mut Vec<NodeId>) {
+        match &self.arena[node] {
+            Node::Document { children, .. } | Node::Node { children, .. } => {
+                for child in children {
+                    self.errors_rec(*child, errors);
+                }
+            }
+            Node::Block {
+                header, children, ..
+            } => {
+                self.errors_rec(*header, errors);
+                for child in children {
+                    self.errors_rec(*child, errors);
+                }
+            }
+            Node::Error { .. } => errors.push(node),
+        }
+    }
+
+    // Like `walk`, also passing the innermost document each node is in, or `document` for nodes
+    // that aren't in one under `node`.
+    pub fn walk_in_document(
+        &self,
+        node: NodeId,
+        document: NodeId,
+        f: &mut dyn FnMut(NodeId, &Node, NodeId),
+    ) {
+        let n = &self.arena[node];
+        let document = match n {
+            Node::Document { .. } => node,
+            _ => document,
+        };
+        f(node, n, document);
+        match n {
+            Node::Document { children, .. } | Node::Node { children, .. } => {
+                for child in children {
+                    self.walk_in_document(*child, document, f);
+                }
+            }
+            Node::Block {
+                header, children, ..
+            } => {
+                self.walk_in_document(*header, document, f);
+                for child in children {
+                    self.walk_in_document(*child, document, f);
+                }
+            }
+            Node::Error { .. } => {}
+        }
+    }
+
+    // Calls `f` on `node` and everything under it, in document order.
+    pub fn walk(&self, node: NodeId, f: &mut dyn FnMut(NodeId, &Node)) {
+        let n = &self.arena[node];
+        f(node, n);
+        match n {
+            Node::Document { children, .. } | Node::Node { children, .. } => {
+                for child in children {
+                    self.walk(*child, f);
+                }
+            }
+            Node::Block {
+                header, children, ..
+            } => {
+                self.walk(*header, f);
+                for child in children {
+                    self.walk(*child, f);
+                }
+            }
+            Node::Error { .. } => {}
+        }
+    }
+}
+
+// The `key: value` attributes of a `front_matter` node, in source order.
+fn load_front_matter(
+    front_matter: &tree_sitter::Node,
+    parser: &Parser,
+) -> IndexMap<String, String> {
+    let mut attributes: IndexMap<String, String> = IndexMap::new();
+    for attribute in front_matter.named_children(&mut front_matter.walk()) {
+        let name = attribute
+            .child_by_field_name("name")
+            .map(|n| parser.get_text(n).to_string());
+        let value = attribute
+            .child_by_field_name("value")
+            .map(|n| parser.get_text(n).trim().to_string())
+            .unwrap_or_default();
+        if let Some(name) = name {
+            attributes.insert(name, value);
+        }
+    }
+    attributes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::printer::{PrintContext, DEFAULT_INDENT_WIDTH};
+
+    fn print(ctx: &Context, node: NodeId) -> String {
+        let mut out: Vec<u8> = Vec::new();
+        ctx.pretty_print(
+            node,
+            &mut PrintContext {
+                level: 0,
+                needs_indent: true,
+                indent_width: DEFAULT_INDENT_WIDTH,
+                out: &mut out,
+            },
+        )
+        .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_load_nested_blocks() {
+        let code = String::from("# a\nfoo\n## b\nbar\n# c");
+        let parser = Parser::new(code, tree_sitter_puddlejumper::language());
+        let mut ctx = Context::new();
+        let document = ctx.load_document(&parser).unwrap();
+        let Node::Document { children, .. } = &ctx.arena[document] else {
+            panic!("expected document");
+        };
+        assert_eq!(children.len(), 2);
+        let Node::Block {
+            level, children, ..
+        } = &ctx.arena[children[0]]
+        else {
+            panic!("expected block");
+        };
+        assert_eq!(*level, 1);
+        assert_eq!(children.len(), 2);
+        assert!(matches!(
+            ctx.arena[children[1]],
+            Node::Block { level: 2, .. }
+        ));
+        assert_eq!(
+            print(&ctx, document),
+            "# a\n    foo\n    ## b\n        bar\n# c\n"
+        );
+    }
+
+    #[test]
+    fn test_load_front_matter() {
+        let code = String::from(
+            "---\ntitle: Roadmap\npriorities: high, low\nindent_width: 2\n---\nfoo\n    low\nbar\n    high",
+        );
+        let parser = Parser::new(code, tree_sitter_puddlejumper::language());
+        let mut ctx = Context::new();
+        let document = ctx.load_document(&parser).unwrap();
+        let settings = ctx.document_settings(document);
+        assert_eq!(settings.title, Some(String::from("Roadmap")));
+        assert_eq!(settings.priorities, vec!["high", "low"]);
+        assert_eq!(settings.indent_width, Some(2));
+
+        let list = ctx.make_prioritized_list(document);
+        assert_eq!(
+            print(&ctx, list),
+            "high\n  bar\n    high\nlow\n  foo\n    low\n"
+        );
+    }
+
+    #[test]
+    fn test_load_keeps_errors() {
+        let code = String::from("hello\n@@@ broken\nworld");
+        let parser = Parser::new(code, tree_sitter_puddlejumper::language());
+        let mut ctx = Context::new();
+        let document = ctx.load_document(&parser).unwrap();
+        let errors = ctx.errors(document);
+        assert!(!errors.is_empty());
+        assert!(errors.iter().any(|error| match &ctx.arena[*error] {
+            Node::Error { text, range } => text.contains("@@") && parser.text[range.clone()] == *text,
+            _ => false,
+        }));
+        let printed = print(&ctx, document);
+        for line in ["hello", "broken", "world"] {
+            assert!(printed.contains(line), "{:?} missing from {:?}", line, printed);
+        }
+    }
+
+    #[test]
+    fn test_load_keeps_front_matter_with_errors() {
+        let code = String::from("---\npriorities: high, low\n---\nfoo\n    low\n@@@ broken\n");
+        let parser = Parser::new(code, tree_sitter_puddlejumper::language());
+        let mut ctx = Context::new();
+        let document = ctx.load_document(&parser).unwrap();
+        assert_eq!(ctx.document_settings(document).priorities, vec!["high", "low"]);
+        assert!(!ctx.errors(document).is_empty());
+        let printed = print(&ctx, document);
+        for line in ["foo", "low", "broken"] {
+            assert!(printed.contains(line), "{:?} missing from {:?}", line, printed);
+        }
+    }
 }
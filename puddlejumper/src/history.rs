@@ -0,0 +1,302 @@
+use std::ops::Range;
+use std::time::Duration;
+
+use super::parser::*;
+use super::text_diff::{advance_point, point_at, Change, Diff};
+
+// Undo/redo for an edited document. Each `Parser::update` is recorded as a patch that can be
+// applied in either direction, and undoing replays the inverse patch through the parser as the
+// exact edits, rather than re-diffing the whole text. That keeps the reparse incremental and lets
+// the tree diff and `Context::apply_diff` follow nodes across an undo as they do across an edit.
+
+// Keystrokes closer together than this are undone together.
+pub const DEFAULT_COALESCE_WINDOW: Duration = Duration::from_millis(500);
+
+// One change of a `Diff`, with the text it replaced so that it can be inverted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hunk {
+    pub before_bytes: Range<usize>,
+    pub after_bytes: Range<usize>,
+    pub before_text: String,
+    pub after_text: String,
+}
+
+// Hunks apply in order, like the changes of a `Diff`: each hunk's `after_bytes` start is an offset
+// into the text with the earlier hunks already applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Patch {
+    pub hunks: Vec<Hunk>,
+}
+
+impl Patch {
+    pub fn from_update(update: &Update) -> Patch {
+        Patch {
+            hunks: update
+                .diff
+                .changes
+                .iter()
+                .map(|change| Hunk {
+                    before_bytes: change.before_bytes.clone(),
+                    after_bytes: change.after_bytes.clone(),
+                    before_text: update.old_text[change.before_bytes.clone()].to_string(),
+                    after_text: update.new_text[change.after_bytes.clone()].to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hunks.is_empty()
+    }
+
+    // The patch that takes the new text back to the old one.
+    pub fn invert(&self) -> Patch {
+        Patch {
+            hunks: self
+                .hunks
+                .iter()
+                .map(|hunk| Hunk {
+                    before_bytes: hunk.after_bytes.clone(),
+                    after_bytes: hunk.before_bytes.clone(),
+                    before_text: hunk.after_text.clone(),
+                    after_text: hunk.before_text.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for hunk in &self.hunks {
+            let start = hunk.after_bytes.start;
+            text.replace_range(start..start + hunk.before_text.len(), &hunk.after_text);
+        }
+        text
+    }
+
+    // The `Diff` that `Parser::update` would have computed, given the patched text.
+    pub fn to_diff(&self, new_text: &str) -> Diff {
+        Diff {
+            changes: self
+                .hunks
+                .iter()
+                .map(|hunk| {
+                    let start_position = point_at(new_text, hunk.after_bytes.start);
+                    Change {
+                        before_bytes: hunk.before_bytes.clone(),
+                        after_bytes: hunk.after_bytes.clone(),
+                        start_position,
+                        old_end_position: advance_point(start_position, &hunk.before_text),
+                        new_end_position: point_at(new_text, hunk.after_bytes.end),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    // Folds `next`, applied right after `self`, into a single patch if both are one typed or
+    // deleted run on a single line, e.g. typing "ab" and then "c", or backspacing twice.
+    fn coalesce(&self, next: &Patch) -> Option<Patch> {
+        let (prev, next) = match (self.hunks.as_slice(), next.hunks.as_slice()) {
+            ([prev], [next]) => (prev, next),
+            _ => return None,
+        };
+        if prev.after_text.contains('\n') || next.after_text.contains('\n') {
+            return None;
+        }
+        if next.before_text.contains('\n') || prev.before_text.contains('\n') {
+            return None;
+        }
+        // Typing: `next` inserts right after what `prev` inserted.
+        if prev.before_text.is_empty()
+            && next.before_text.is_empty()
+            && next.before_bytes.start == prev.after_bytes.end
+        {
+            return Some(Patch {
+                hunks: vec![Hunk {
+                    before_bytes: prev.before_bytes.clone(),
+                    after_bytes: prev.after_bytes.start..next.after_bytes.end,
+                    before_text: String::new(),
+                    after_text: format!("{}{}", prev.after_text, next.after_text),
+                }],
+            });
+        }
+        // Backspacing: `next` deletes right before what `prev` deleted.
+        if prev.after_text.is_empty()
+            && next.after_text.is_empty()
+            && next.before_bytes.end == prev.after_bytes.start
+        {
+            return Some(Patch {
+                hunks: vec![Hunk {
+                    before_bytes: next.before_bytes.start..prev.before_bytes.end,
+                    after_bytes: next.after_bytes.clone(),
+                    before_text: format!("{}{}", next.before_text, prev.before_text),
+                    after_text: String::new(),
+                }],
+            });
+        }
+        None
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    patch: Patch,
+    // When the last update folded into this entry was recorded.
+    time: Duration,
+}
+
+#[derive(Debug)]
+pub struct History {
+    undo_stack: Vec<Entry>,
+    redo_stack: Vec<Entry>,
+    pub coalesce_window: Duration,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::new()
+    }
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            coalesce_window: DEFAULT_COALESCE_WINDOW,
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    // Records an update from `Parser::update`, before or after it is applied. `now` is the time
+    // since any fixed point, e.g. `Date.now()` from the wasm build, and only used for coalescing.
+    pub fn record(&mut self, update: &Update, now: Duration) {
+        let patch = Patch::from_update(update);
+        if patch.is_empty() {
+            return;
+        }
+        self.redo_stack.clear();
+        if let Some(last) = self.undo_stack.last_mut() {
+            let recent = now.saturating_sub(last.time) <= self.coalesce_window;
+            if let Some(coalesced) = last.patch.coalesce(&patch).filter(|_| recent) {
+                last.patch = coalesced;
+                last.time = now;
+                return;
+            }
+        }
+        self.undo_stack.push(Entry { patch, time: now });
+    }
+
+    // Reparses the text as it was before the last recorded update. Like `Parser::update`, the
+    // result still has to be passed to `Parser::apply_update`.
+    pub fn undo(&mut self, parser: &mut Parser) -> Option<Update> {
+        let entry = self.undo_stack.pop()?;
+        let update = replay(parser, &entry.patch.invert());
+        self.redo_stack.push(entry);
+        Some(update)
+    }
+
+    pub fn redo(&mut self, parser: &mut Parser) -> Option<Update> {
+        let entry = self.redo_stack.pop()?;
+        let update = replay(parser, &entry.patch);
+        self.undo_stack.push(entry);
+        Some(update)
+    }
+}
+
+fn replay(parser: &mut Parser, patch: &Patch) -> Update {
+    let new_text = patch.apply(&parser.text);
+    let diff = patch.to_diff(&new_text);
+    parser.update_with_diff(new_text, diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text_diff::compute_diff;
+
+    fn update(parser: &mut Parser, history: &mut History, text: &str, millis: u64) {
+        let update = parser.update(String::from(text));
+        history.record(&update, Duration::from_millis(millis));
+        parser.apply_update(update);
+    }
+
+    #[test]
+    fn test_patch_invert() {
+        let before = "hello\nworld\nfoo";
+        let after = "// x\nhello\nwarld!\n";
+        let diff = compute_diff(before, after);
+        let patch = Patch {
+            hunks: diff
+                .changes
+                .iter()
+                .map(|change| Hunk {
+                    before_bytes: change.before_bytes.clone(),
+                    after_bytes: change.after_bytes.clone(),
+                    before_text: before[change.before_bytes.clone()].to_string(),
+                    after_text: after[change.after_bytes.clone()].to_string(),
+                })
+                .collect(),
+        };
+        assert_eq!(patch.apply(before), after);
+        assert_eq!(patch.to_diff(after), diff);
+        assert_eq!(patch.invert().apply(after), before);
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut parser = Parser::new(String::from("hello"), tree_sitter_puddlejumper::language());
+        let mut history = History::new();
+        update(&mut parser, &mut history, "hello\nworld", 0);
+        update(&mut parser, &mut history, "hello\nworld\nfoo", 1000);
+
+        let undone = history.undo(&mut parser).unwrap();
+        parser.apply_update(undone);
+        assert_eq!(parser.text, "hello\nworld");
+        assert_eq!(parser.get_text(parser.tree.root_node()), "hello\nworld");
+
+        let undone = history.undo(&mut parser).unwrap();
+        parser.apply_update(undone);
+        assert_eq!(parser.text, "hello");
+        assert!(history.undo(&mut parser).is_none());
+
+        let redone = history.redo(&mut parser).unwrap();
+        parser.apply_update(redone);
+        assert_eq!(parser.text, "hello\nworld");
+
+        // A new edit drops what could be redone.
+        update(&mut parser, &mut history, "hello\nworld!", 2000);
+        assert!(!history.can_redo());
+    }
+
+    #[test]
+    fn test_coalesce_keystrokes() {
+        let mut parser = Parser::new(String::from("ab"), tree_sitter_puddlejumper::language());
+        let mut history = History::new();
+        update(&mut parser, &mut history, "abc", 0);
+        update(&mut parser, &mut history, "abcd", 100);
+        update(&mut parser, &mut history, "abcde", 200);
+        // Too long after the last keystroke to be part of the same edit.
+        update(&mut parser, &mut history, "abcdef", 2000);
+        update(&mut parser, &mut history, "abcde", 2100);
+
+        let undone = history.undo(&mut parser).unwrap();
+        parser.apply_update(undone);
+        assert_eq!(parser.text, "abcdef");
+        let undone = history.undo(&mut parser).unwrap();
+        parser.apply_update(undone);
+        assert_eq!(parser.text, "abcde");
+        let undone = history.undo(&mut parser).unwrap();
+        parser.apply_update(undone);
+        assert_eq!(parser.text, "ab");
+        assert!(!history.can_undo());
+    }
+}
@@ -1,34 +1,62 @@
 use std::cmp::min;
 
+// Both distances keep one row of the edit graph instead of the whole matrix. `substring_distance`
+// takes its row from the caller, so that a search over thousands of nodes can reuse one buffer.
 
 pub fn levenshtein(a: &str, b: &str) -> usize {
     let a = a.as_bytes();
     let b = b.as_bytes();
-    let mut graph = vec![vec![0; b.len() + 1]; a.len() + 1];
-
-    // initialize costs assuming nothing in common
-    for i in 0..=a.len() {
-        graph[i][0] = i;
-    }
-    for j in 0..=b.len() {
-        graph[0][j] = j;
-    }
+    // row[j] is the cost of turning the first i bytes of a into the first j bytes of b
+    let mut row: Vec<usize> = (0..=b.len()).collect();
 
     for i in 1..=a.len() {
+        // the cost to the top-left, before it's overwritten
+        let mut diagonal = row[0];
+        row[0] = i;
         for j in 1..=b.len() {
             let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
 
             // min cost is min cost of nodes to the left, top, and top-left
-            graph[i][j] = min(
-                graph[i - 1][j] + 1, // deletion
+            let next = min(
+                row[j] + 1, // deletion
                 min(
-                    graph[i][j - 1] + 1, // insertion
-                    graph[i - 1][j - 1] + cost, // substitution
+                    row[j - 1] + 1, // insertion
+                    diagonal + cost, // substitution
                 ),
             );
+            diagonal = row[j];
+            row[j] = next;
+        }
+    }
+    row[b.len()]
+}
+
+// The fewest edits that turn `pattern` into some substring of `text`, so 0 if `text` contains
+// it. Compares chars rather than bytes, since search queries are typed by people. `column` is
+// overwritten, its contents don't matter.
+pub fn substring_distance(
+    pattern: &[char],
+    text: impl IntoIterator<Item = char>,
+    column: &mut Vec<usize>,
+) -> usize {
+    // column[i] is the cost of matching the first i chars of the pattern so that the match ends
+    // at the current char of the text
+    column.clear();
+    column.extend(0..=pattern.len());
+    let mut best = pattern.len();
+
+    for t in text {
+        // matches can start anywhere in the text, so matching nothing is free
+        let mut diagonal = 0;
+        for i in 1..=pattern.len() {
+            let cost = if pattern[i - 1] == t { 0 } else { 1 };
+            let next = min(column[i] + 1, min(column[i - 1] + 1, diagonal + cost));
+            diagonal = column[i];
+            column[i] = next;
         }
+        best = min(best, column[pattern.len()]);
     }
-    graph[a.len()][b.len()]
+    best
 }
 
 #[cfg(test)]
@@ -40,5 +68,21 @@ mod tests {
         let a = "kitten";
         let b = "sitting";
         assert_eq!(levenshtein(a, b), 3);
+        assert_eq!(levenshtein("", b), 7);
+        assert_eq!(levenshtein(a, ""), 6);
+    }
+
+    #[test]
+    fn test_substring_distance() {
+        let mut column: Vec<usize> = Vec::new();
+        let mut distance = |pattern: &str, text: &str| {
+            let pattern: Vec<char> = pattern.chars().collect();
+            substring_distance(&pattern, text.chars(), &mut column)
+        };
+        assert_eq!(distance("test", "Write tests"), 0);
+        assert_eq!(distance("tset", "Write tests"), 2);
+        assert_eq!(distance("dploy", "Deploy"), 1);
+        assert_eq!(distance("abc", ""), 3);
+        assert_eq!(distance("", "abc"), 0);
     }
-}
\ No newline at end of file
+}
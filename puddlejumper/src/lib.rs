@@ -1,6 +1,18 @@
+// Lets every module refer to `tree_sitter` regardless of which backend is enabled.
+#[cfg(feature = "wasm")]
+extern crate tree_sitter_c2rust as tree_sitter;
+
 pub mod node;
 pub mod parser;
 pub mod context;
 pub mod text_diff;
 pub mod tree_diff;
-pub mod levenshtein;
\ No newline at end of file
+pub mod levenshtein;
+pub mod queries;
+pub mod lsp;
+pub mod watch;
+pub mod outline_diff;
+pub mod merge;
+pub mod history;
+pub mod workspace;
+pub mod projection;
\ No newline at end of file
@@ -0,0 +1,631 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::ops::Range;
+
+use serde_json::{json, Value};
+
+use super::node::{Content, Context, Node, NodeId, SourceRange};
+use super::parser::*;
+use super::queries::{Queries, Reference};
+
+// A language server over stdio, speaking JSON-RPC with `Content-Length` framing. Documents are
+// synced in full, and each change goes through `Parser::update` so that reparsing stays
+// incremental.
+//
+// Positions on the wire are LSP positions: zero-based lines and UTF-16 columns.
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_PARAMS: i64 = -32602;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+const SYMBOL_KIND_NAMESPACE: u64 = 3;
+const SYMBOL_KIND_VARIABLE: u64 = 13;
+
+pub struct Document {
+    pub parser: Parser,
+    pub context: Context,
+    pub root: NodeId,
+    pub references: Vec<Reference>,
+}
+
+impl Document {
+    pub fn new(text: String, queries: &Queries) -> Document {
+        let parser = Parser::new(text, tree_sitter_puddlejumper::language());
+        // `load_document` only fails on an unexpected root, which it already wraps.
+        let (context, root) = Context::from_parser(&parser).expect("Error loading document");
+        let references = parser.references(queries);
+        Document {
+            parser,
+            context,
+            root,
+            references,
+        }
+    }
+
+    pub fn update(&mut self, text: String, queries: &Queries) {
+        let update = self.parser.update(text);
+        self.parser.apply_update(update);
+        let (context, root) = Context::from_parser(&self.parser).expect("Error loading document");
+        self.context = context;
+        self.root = root;
+        self.references = self.parser.references(queries);
+    }
+
+    // Identifier ranges of every `@name:` binding, in document order.
+    pub fn bindings(&self) -> Vec<(String, Range<usize>)> {
+        let mut bindings: Vec<(String, Range<usize>)> = Vec::new();
+        for (id, node) in self.context.arena.iter() {
+            let binding = match node {
+                Node::Node { binding, .. } | Node::Block { binding, .. } => binding,
+                _ => continue,
+            };
+            let range = self
+                .context
+                .source(id)
+                .and_then(|source| source.binding);
+            if let (Some(binding), Some(range)) = (binding, range) {
+                bindings.push((binding.clone(), range.byte_range()));
+            }
+        }
+        bindings.sort_by_key(|(_, range)| range.start);
+        bindings
+    }
+
+    // The binding identifier that the identifier at `byte` stands for, whether `byte` is on the
+    // binding itself or on a reference to it.
+    pub fn definition_at(&self, byte: usize) -> Option<(String, Range<usize>)> {
+        let contains = |range: &Range<usize>| range.start <= byte && byte <= range.end;
+        for reference in &self.references {
+            if contains(&reference.byte_range) {
+                return reference
+                    .definition
+                    .clone()
+                    .map(|definition| (reference.name.clone(), definition));
+            }
+        }
+        self.bindings()
+            .into_iter()
+            .find(|(_, range)| contains(range))
+    }
+
+    // References resolving to the binding at `definition`.
+    pub fn references_to(&self, definition: &Range<usize>) -> Vec<Range<usize>> {
+        self.references
+            .iter()
+            .filter(|reference| reference.definition.as_ref() == Some(definition))
+            .map(|reference| reference.byte_range.clone())
+            .collect()
+    }
+}
+
+pub struct Server {
+    pub queries: Queries,
+    pub documents: HashMap<String, Document>,
+    pub shutdown_requested: bool,
+    pub exited: bool,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Server::new()
+    }
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server {
+            queries: Queries::new().expect("Error loading puddlejumper queries"),
+            documents: HashMap::new(),
+            shutdown_requested: false,
+            exited: false,
+        }
+    }
+
+    // Serves messages from `reader` until the client sends `exit` or hangs up.
+    pub fn run(&mut self, reader: &mut dyn BufRead, writer: &mut dyn Write) -> std::io::Result<()> {
+        while !self.exited {
+            let outgoing = match read_message(reader)? {
+                Some(Ok(message)) => self.handle(&message),
+                Some(Err(error)) => vec![error_response(
+                    Value::Null,
+                    PARSE_ERROR,
+                    &format!("Invalid JSON: {}", error),
+                )],
+                None => return Ok(()),
+            };
+            for message in outgoing {
+                write_message(writer, &message)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Handles one incoming message, returning the responses and notifications to send back.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message.get("method").and_then(Value::as_str).unwrap_or("");
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => return self.handle_notification(method, &params),
+        };
+        let result = match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "documentSymbolProvider": true,
+                    "foldingRangeProvider": true,
+                    "renameProvider": true,
+                },
+                "serverInfo": { "name": "puddlejumper-lsp" },
+            })),
+            "shutdown" => {
+                self.shutdown_requested = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => self.definition(&params),
+            "textDocument/references" => self.references(&params),
+            "textDocument/documentSymbol" => self.document_symbols(&params),
+            "textDocument/foldingRange" => self.folding_ranges(&params),
+            "textDocument/rename" => self.rename(&params),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method {:?}", method))),
+        };
+        match result {
+            Ok(result) => vec![json!({ "jsonrpc": "2.0", "id": id, "result": result })],
+            Err((code, message)) => vec![error_response(id, code, &message)],
+        }
+    }
+
+    fn handle_notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params
+            .pointer("/textDocument/uri")
+            .and_then(Value::as_str)
+            .map(String::from);
+        match (method, uri) {
+            ("textDocument/didOpen", Some(uri)) => {
+                let text = params
+                    .pointer("/textDocument/text")
+                    .and_then(Value::as_str)
+                    .unwrap_or("")
+                    .to_string();
+                let document = Document::new(text, &self.queries);
+                self.documents.insert(uri.clone(), document);
+                vec![self.diagnostics(&uri)]
+            }
+            ("textDocument/didChange", Some(uri)) => {
+                // With full sync, the last change holds the whole new text.
+                let text = params
+                    .get("contentChanges")
+                    .and_then(Value::as_array)
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.get("text"))
+                    .and_then(Value::as_str)
+                    .map(String::from);
+                match (text, self.documents.get_mut(&uri)) {
+                    (Some(text), Some(document)) => {
+                        document.update(text, &self.queries);
+                        vec![self.diagnostics(&uri)]
+                    }
+                    _ => vec![],
+                }
+            }
+            ("textDocument/didClose", Some(uri)) => {
+                self.documents.remove(&uri);
+                vec![]
+            }
+            ("exit", _) => {
+                self.exited = true;
+                vec![]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn diagnostics(&self, uri: &str) -> Value {
+        let diagnostics: Vec<Value> = match self.documents.get(uri) {
+            Some(document) => {
+                let text = &document.parser.text;
+                let context = &document.context;
+                let errors = context.errors(document.root).into_iter().map(|error| {
+                    let message = match &context.arena[error] {
+                        Node::Error { text, .. } if !text.trim().is_empty() => {
+                            format!("Unexpected {:?}", text.trim())
+                        }
+                        _ => String::from("Syntax error"),
+                    };
+                    (error, 1, message)
+                });
+                // Dependency cycles and refs to nothing are warnings.
+                let dependencies = context
+                    .dependency_diagnostics(document.root)
+                    .into_iter()
+                    .map(|diagnostic| (diagnostic.node, 2, diagnostic.message));
+                errors
+                    .chain(dependencies)
+                    .filter_map(|(node, severity, message)| {
+                        let source = context.source(node)?;
+                        Some(json!({
+                            "range": range_to_json(text, &source.range.byte_range()),
+                            "severity": severity,
+                            "source": "puddlejumper",
+                            "message": message,
+                        }))
+                    })
+                    .collect()
+            }
+            None => vec![],
+        };
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        })
+    }
+
+    fn document<'a>(
+        &'a self,
+        params: &'a Value,
+    ) -> Result<(&'a str, &'a Document), (i64, String)> {
+        let uri = params
+            .pointer("/textDocument/uri")
+            .and_then(Value::as_str)
+            .ok_or((INVALID_PARAMS, String::from("Missing textDocument.uri")))?;
+        let document = self
+            .documents
+            .get(uri)
+            .ok_or_else(|| (INVALID_PARAMS, format!("Unknown document {}", uri)))?;
+        Ok((uri, document))
+    }
+
+    fn byte_at(&self, params: &Value, document: &Document) -> Result<usize, (i64, String)> {
+        let position = params
+            .get("position")
+            .ok_or((INVALID_PARAMS, String::from("Missing position")))?;
+        let line = position.get("line").and_then(Value::as_u64);
+        let character = position.get("character").and_then(Value::as_u64);
+        match (line, character) {
+            (Some(line), Some(character)) => Ok(position_to_byte(
+                &document.parser.text,
+                line as usize,
+                character as usize,
+            )),
+            _ => Err((INVALID_PARAMS, String::from("Invalid position"))),
+        }
+    }
+
+    fn definition(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (uri, document) = self.document(params)?;
+        let byte = self.byte_at(params, document)?;
+        Ok(match document.definition_at(byte) {
+            Some((_, range)) => location(uri, &document.parser.text, &range),
+            None => Value::Null,
+        })
+    }
+
+    fn references(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (uri, document) = self.document(params)?;
+        let byte = self.byte_at(params, document)?;
+        let include_declaration = params
+            .pointer("/context/includeDeclaration")
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        let (_, definition) = match document.definition_at(byte) {
+            Some(definition) => definition,
+            None => return Ok(Value::Null),
+        };
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        if include_declaration {
+            ranges.push(definition.clone());
+        }
+        ranges.extend(document.references_to(&definition));
+        Ok(ranges
+            .iter()
+            .map(|range| location(uri, &document.parser.text, range))
+            .collect())
+    }
+
+    fn rename(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (uri, document) = self.document(params)?;
+        let byte = self.byte_at(params, document)?;
+        let new_name = params
+            .get("newName")
+            .and_then(Value::as_str)
+            .ok_or((INVALID_PARAMS, String::from("Missing newName")))?;
+        let new_name = new_name.trim_start_matches('@');
+        if new_name.is_empty()
+            || !new_name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err((INVALID_PARAMS, format!("Invalid binding name {:?}", new_name)));
+        }
+        let (_, definition) = match document.definition_at(byte) {
+            Some(definition) => definition,
+            None => return Ok(Value::Null),
+        };
+        let text = &document.parser.text;
+        let edits: Vec<Value> = std::iter::once(definition.clone())
+            .chain(document.references_to(&definition))
+            .map(|range| json!({ "range": range_to_json(text, &range), "newText": new_name }))
+            .collect();
+        Ok(json!({ "changes": { uri: edits } }))
+    }
+
+    fn document_symbols(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (_, document) = self.document(params)?;
+        Ok(Value::Array(symbols(document, document.root)))
+    }
+
+    fn folding_ranges(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (_, document) = self.document(params)?;
+        let mut ranges: Vec<Value> = Vec::new();
+        for (id, node) in document.context.arena.iter() {
+            let children = match node {
+                Node::Node { children, .. } | Node::Block { children, .. } => children,
+                _ => continue,
+            };
+            let (first, last) = match (document.context.source(id), children.last()) {
+                (Some(source), Some(last)) => (source.range, document.context.source(*last)),
+                _ => continue,
+            };
+            let end = match last {
+                Some(last) => last_line(&last.range),
+                None => continue,
+            };
+            let start = first.start_position.row;
+            if end > start {
+                ranges.push(json!({ "startLine": start, "endLine": end }));
+            }
+        }
+        ranges.sort_by_key(|range| range["startLine"].as_u64());
+        Ok(Value::Array(ranges))
+    }
+}
+
+// Name, symbol kind and selection range of a symbol.
+type Symbol = (String, u64, Option<SourceRange>);
+
+// Symbols for bindings and block headers under `node`, nested the way they are in the outline.
+// Nodes that are neither are skipped, and their symbols are lifted to the nearest one that is.
+fn symbols(document: &Document, node: NodeId) -> Vec<Value> {
+    let context = &document.context;
+    let text = &document.parser.text;
+    let (children, symbol): (Vec<NodeId>, Option<Symbol>) =
+        match &context.arena[node] {
+            Node::Document { children, .. } => (children.clone(), None),
+            Node::Node {
+                binding,
+                content,
+                children,
+            } => (
+                children.clone(),
+                binding.as_ref().map(|binding| {
+                    let selection = context.source(node).and_then(|source| source.binding);
+                    let name = match content {
+                        Some(Content::Content(content)) => format!("@{}: {}", binding, content),
+                        _ => format!("@{}", binding),
+                    };
+                    (name, SYMBOL_KIND_VARIABLE, selection)
+                }),
+            ),
+            Node::Block {
+                header, children, ..
+            } => (
+                children.clone(),
+                Some((
+                    header_text(context, *header),
+                    SYMBOL_KIND_NAMESPACE,
+                    context.source(*header).map(|source| source.range),
+                )),
+            ),
+            Node::Error { .. } => (vec![], None),
+        };
+    let child_symbols: Vec<Value> = children
+        .iter()
+        .flat_map(|child| symbols(document, *child))
+        .collect();
+    let (name, kind, selection) = match symbol {
+        Some(symbol) => symbol,
+        None => return child_symbols,
+    };
+    let range = match context.source(node) {
+        Some(source) => source.range,
+        None => return child_symbols,
+    };
+    let selection = selection.unwrap_or(range);
+    vec![json!({
+        "name": name,
+        "kind": kind,
+        "range": range_to_json(text, &range.byte_range()),
+        "selectionRange": range_to_json(text, &selection.byte_range()),
+        "children": child_symbols,
+    })]
+}
+
+fn header_text(context: &Context, header: NodeId) -> String {
+    match &context.arena[header] {
+        Node::Node {
+            content: Some(Content::Content(text)),
+            ..
+        }
+        | Node::Node {
+            content: Some(Content::Ref(text)),
+            ..
+        }
+        | Node::Error { text, .. } => text.trim().to_string(),
+        _ => String::new(),
+    }
+}
+
+// The last line that a range has text on, not counting a trailing newline.
+fn last_line(range: &SourceRange) -> usize {
+    if range.end_position.column == 0 && range.end_position.row > range.start_position.row {
+        range.end_position.row - 1
+    } else {
+        range.end_position.row
+    }
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+fn location(uri: &str, text: &str, range: &Range<usize>) -> Value {
+    json!({ "uri": uri, "range": range_to_json(text, range) })
+}
+
+pub fn range_to_json(text: &str, range: &Range<usize>) -> Value {
+    json!({
+        "start": byte_to_position(text, range.start),
+        "end": byte_to_position(text, range.end),
+    })
+}
+
+pub fn byte_to_position(text: &str, byte: usize) -> Value {
+    let byte = byte.min(text.len());
+    let before = &text[..byte];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    json!({
+        "line": before.matches('\n').count(),
+        "character": before[line_start..].encode_utf16().count(),
+    })
+}
+
+// Clamps to the end of the line, or of the text, like editors expect.
+pub fn position_to_byte(text: &str, line: usize, character: usize) -> usize {
+    let mut line_start = 0;
+    for _ in 0..line {
+        match text[line_start..].find('\n') {
+            Some(newline) => line_start += newline + 1,
+            None => return text.len(),
+        }
+    }
+    let line_text = match text[line_start..].find('\n') {
+        Some(newline) => &text[line_start..line_start + newline],
+        None => &text[line_start..],
+    };
+    let mut units = 0;
+    for (offset, c) in line_text.char_indices() {
+        if units >= character {
+            return line_start + offset;
+        }
+        units += c.len_utf16();
+    }
+    line_start + line_text.len()
+}
+
+// Reads one framed message. Returns `None` once the input is exhausted, and an error string if
+// the body isn't JSON.
+pub fn read_message(reader: &mut dyn BufRead) -> std::io::Result<Option<Result<Value, String>>> {
+    let mut content_length: Option<usize> = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            if content_length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = value.trim().parse().ok();
+            }
+        }
+    }
+    let mut body = vec![0; content_length.unwrap_or(0)];
+    reader.read_exact(&mut body)?;
+    Ok(Some(
+        serde_json::from_slice(&body).map_err(|error| error.to_string()),
+    ))
+}
+
+pub fn write_message(writer: &mut dyn Write, message: &Value) -> std::io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_framing() {
+        let mut out: Vec<u8> = Vec::new();
+        write_message(&mut out, &json!({ "jsonrpc": "2.0", "method": "exit" })).unwrap();
+        write_message(&mut out, &json!({ "jsonrpc": "2.0", "id": 1 })).unwrap();
+        let mut reader = std::io::BufReader::new(&out[..]);
+        assert_eq!(
+            read_message(&mut reader).unwrap(),
+            Some(Ok(json!({ "jsonrpc": "2.0", "method": "exit" })))
+        );
+        assert_eq!(
+            read_message(&mut reader).unwrap(),
+            Some(Ok(json!({ "jsonrpc": "2.0", "id": 1 })))
+        );
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_positions_count_utf16() {
+        let text = "héllo\n@a: 🙂 b";
+        assert_eq!(byte_to_position(text, 3), json!({ "line": 0, "character": 2 }));
+        assert_eq!(position_to_byte(text, 0, 2), 3);
+        // The emoji is two UTF-16 units and four bytes.
+        let b = text.rfind('b').unwrap();
+        assert_eq!(byte_to_position(text, b), json!({ "line": 1, "character": 7 }));
+        assert_eq!(position_to_byte(text, 1, 7), b);
+        assert_eq!(position_to_byte(text, 0, 100), 6);
+        assert_eq!(position_to_byte(text, 5, 0), text.len());
+    }
+
+    #[test]
+    fn test_definition_and_rename() {
+        let mut server = Server::new();
+        server.handle(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": "file:///a.pj", "text": "@a: hello\n@a" } },
+        }));
+        let responses = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "textDocument/definition",
+            "params": {
+                "textDocument": { "uri": "file:///a.pj" },
+                "position": { "line": 1, "character": 1 },
+            },
+        }));
+        assert_eq!(
+            responses[0]["result"]["range"],
+            json!({
+                "start": { "line": 0, "character": 1 },
+                "end": { "line": 0, "character": 2 },
+            })
+        );
+        let responses = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "textDocument/rename",
+            "params": {
+                "textDocument": { "uri": "file:///a.pj" },
+                "position": { "line": 0, "character": 1 },
+                "newName": "b",
+            },
+        }));
+        let edits = responses[0]["result"]["changes"]["file:///a.pj"]
+            .as_array()
+            .unwrap();
+        assert_eq!(edits.len(), 2);
+        assert!(edits.iter().all(|edit| edit["newText"] == "b"));
+    }
+}
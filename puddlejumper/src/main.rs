@@ -1,96 +1,526 @@
-use puddlejumper;
-use std::env;
+use puddlejumper::node::agenda::Date;
+use puddlejumper::node::export::ExportFormat;
+use puddlejumper::node::import::ImportFormat;
+use puddlejumper::node::printer::{PrintContext, DEFAULT_INDENT_WIDTH};
+use puddlejumper::node::{Context, Node, NodeId};
+use puddlejumper::parser::Parser;
+use puddlejumper::text_diff::apply_edits;
+use std::error::Error;
 use std::fs;
-use std::io::{Error, ErrorKind};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Duration;
 
-fn print_usage() {
-    println!("Usage: cargo run -- [debug_print | parse | print_prioritized] <file_path>");
+use clap::Subcommand;
+
+#[derive(clap::Parser)]
+#[command(name = "puddlejumper", version, about = "Tools for puddlejumper outlines")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
 }
 
-fn main() {
-    // Retrieve the file path from command-line arguments
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 3 {
-        print_usage();
-        return;
-    }
+#[derive(Subcommand)]
+enum Command {
+    /// Print the syntax tree of each file
+    #[command(alias = "debug_print")]
+    DebugPrint(Inputs),
+    /// Print the loaded nodes
+    Parse(Inputs),
+    /// Print the loaded nodes as JSON
+    Json {
+        /// Print on a single line
+        #[arg(long)]
+        compact: bool,
+        #[command(flatten)]
+        inputs: Inputs,
+    },
+    /// Print items grouped by priority
+    #[command(alias = "print_prioritized")]
+    PrintPrioritized {
+        /// Spaces per indent level, instead of the document's `indent_width`
+        #[arg(long)]
+        indent_width: Option<usize>,
+        #[command(flatten)]
+        inputs: Inputs,
+    },
+    /// Print open items with a due or scheduled date, from overdue to later
+    Agenda {
+        /// The date to plan from, as `YYYY-MM-DD`, instead of today
+        #[arg(long, value_parser = parse_date)]
+        today: Option<Date>,
+        /// Print the items as JSON
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        inputs: Inputs,
+    },
+    /// Print items in dependency order, blockers first, or the dependency graph
+    Deps {
+        #[arg(long, value_enum, default_value_t = GraphFormat::Text)]
+        format: GraphFormat,
+        #[command(flatten)]
+        inputs: Inputs,
+    },
+    /// Print open items whose `blocked_by` dependencies are all done
+    Ready(Inputs),
+    /// Print the outline with progress and priority counts after each item, e.g. `[3/7, 2×P0]`
+    Stats {
+        /// Print the counts as JSON
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        inputs: Inputs,
+    },
+    /// Find items by content or binding, allowing typos, and print each with its ancestors
+    Search {
+        /// The text to look for, or `@name` for a binding
+        query: String,
+        /// Print at most this many items
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        /// Print the items as JSON
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        inputs: Inputs,
+    },
+    /// Export the outline for readers without puddlejumper
+    Export {
+        #[arg(long, value_enum)]
+        format: Format,
+        #[command(flatten)]
+        inputs: Inputs,
+    },
+    /// Convert a Markdown or OPML outline to puddlejumper source
+    Import {
+        #[arg(long, value_enum)]
+        from: Source,
+        /// The file to convert, or `-` for stdin
+        file: String,
+    },
+    /// Add a `^id` marker to every item that has neither a binding nor one yet
+    AssignIds {
+        /// Write the result back to the file instead of printing it
+        #[arg(long)]
+        in_place: bool,
+        /// The file to update, or `-` for stdin
+        file: String,
+    },
+    /// Print how items changed between two versions of an outline
+    Diff {
+        /// The old version, or `-` for stdin
+        old: String,
+        /// The new version, or `-` for stdin
+        new: String,
+        /// Print the changes as JSON
+        #[arg(long)]
+        json: bool,
+        /// Exit with status 1 if there are changes, like `git diff --exit-code`
+        #[arg(long)]
+        exit_code: bool,
+    },
+    /// Merge two edits of an outline item by item, for use as a git merge driver
+    ///
+    /// Writes the result over OURS and exits with status 1 if there were conflicts. To install:
+    ///
+    ///     git config merge.puddlejumper.driver "puddlejumper merge-driver %O %A %B"
+    ///     echo "*.pj merge=puddlejumper" >> .gitattributes
+    #[command(verbatim_doc_comment)]
+    MergeDriver {
+        /// The common ancestor (%O)
+        base: PathBuf,
+        /// Our version (%A), overwritten with the result
+        ours: PathBuf,
+        /// Their version (%B)
+        theirs: PathBuf,
+    },
+    /// Re-render a view whenever the file changes
+    Watch {
+        #[arg(value_enum)]
+        view: View,
+        file: PathBuf,
+    },
+}
 
-    let command: &str = args[1].as_str();
-    let file_path: &str = &args[2].as_str();
+#[derive(clap::Args)]
+struct Inputs {
+    /// Files to read, or `-` for stdin. A directory stands for the `.pj` files in it. Several
+    /// files are loaded as one document each, under a shared root.
+    #[arg(required = true)]
+    files: Vec<String>,
+}
 
-    if file_path == "" {
-        print_usage();
-        return;
-    }
+#[derive(Clone, clap::ValueEnum)]
+enum View {
+    #[value(name = "print_prioritized", alias = "print-prioritized")]
+    PrintPrioritized,
+}
 
-    // Read the contents of the file
-    let code = match fs::read_to_string(file_path) {
-        Ok(contents) => contents,
-        Err(error) => {
-            println!("Error reading file: {}", error);
-            return;
-        }
-    };
+#[derive(Clone, clap::ValueEnum)]
+enum Format {
+    #[value(alias = "md")]
+    Markdown,
+    Html,
+    Opml,
+}
+
+#[derive(Clone, clap::ValueEnum)]
+enum GraphFormat {
+    Text,
+    Dot,
+    Mermaid,
+}
+
+#[derive(Clone, clap::ValueEnum)]
+enum Source {
+    #[value(alias = "md")]
+    Markdown,
+    Opml,
+}
+
+struct Input {
+    name: String,
+    text: String,
+}
 
-    // Parse and print the code
-    let p = puddlejumper::parser::Parser::new(code, tree_sitter_puddlejumper::language());
+fn main() {
+    let cli = <Cli as clap::Parser>::parse();
+    if let Err(error) = run(cli.command) {
+        eprintln!("puddlejumper: {}", error);
+        process::exit(1);
+    }
+}
+
+fn run(command: Command) -> Result<(), Box<dyn Error>> {
+    let mut out = std::io::stdout().lock();
     match command {
-        "debug_print" => {
-            let result = p.debug_print(&mut std::io::stdout());
-            match result {
-                Ok(_) => (),
-                Err(error) => {
-                    println!("Error debug printing file: {}", error);
-                    return;
+        Command::DebugPrint(inputs) => {
+            let inputs = read_inputs(&inputs)?;
+            for input in &inputs {
+                if inputs.len() > 1 {
+                    writeln!(out, "==> {} <==", input.name)?;
                 }
+                let parser = Parser::new(input.text.clone(), tree_sitter_puddlejumper::language());
+                parser.debug_print(&mut out)?;
+            }
+        }
+        Command::Parse(inputs) => {
+            let (ctx, _root) = load(&read_inputs(&inputs)?)?;
+            for (id, node) in ctx.arena.iter() {
+                writeln!(out, "{:?}:\n{:?}", id.index(), node)?;
+                if let Some(metadata) = ctx.metadata.get(&id) {
+                    writeln!(out, "  {:?}", metadata)?;
+                }
+            }
+        }
+        Command::Json { compact, inputs } => {
+            let (ctx, root) = load(&read_inputs(&inputs)?)?;
+            let json = ctx.to_json(root);
+            if compact {
+                writeln!(out, "{}", serde_json::to_string(&json)?)?;
+            } else {
+                writeln!(out, "{}", serde_json::to_string_pretty(&json)?)?;
+            }
+        }
+        Command::PrintPrioritized {
+            indent_width,
+            inputs,
+        } => {
+            let (mut ctx, root) = load(&read_inputs(&inputs)?)?;
+            let list = ctx.make_prioritized_list(root);
+            if let (Some(width), Node::Document { attributes, .. }) =
+                (indent_width, &mut ctx.arena[list])
+            {
+                attributes.insert(String::from("indent_width"), width.to_string());
+            }
+            ctx.pretty_print(
+                list,
+                &mut PrintContext {
+                    level: 0,
+                    needs_indent: true,
+                    indent_width: DEFAULT_INDENT_WIDTH,
+                    out: &mut out,
+                },
+            )?;
+        }
+        Command::Agenda {
+            today,
+            json,
+            inputs,
+        } => {
+            let (mut ctx, root) = load(&read_inputs(&inputs)?)?;
+            let today = today.unwrap_or_else(Date::today);
+            if json {
+                let settings = ctx.document_settings(root);
+                let items: Vec<serde_json::Value> = ctx
+                    .agenda(root, today)
+                    .iter()
+                    .map(|item| item.to_json(&ctx, &settings))
+                    .collect();
+                writeln!(out, "{}", serde_json::to_string_pretty(&items)?)?;
+            } else {
+                let agenda = ctx.make_agenda(root, today);
+                ctx.pretty_print(
+                    agenda,
+                    &mut PrintContext {
+                        level: 0,
+                        needs_indent: true,
+                        indent_width: DEFAULT_INDENT_WIDTH,
+                        out: &mut out,
+                    },
+                )?;
             }
         }
-        "parse" => {
-            let mut ctx = puddlejumper::node::Context::new();
-            let result = ctx.load_document(&p);
-            match result {
-                Some(_node) => {
-                    for (id, node) in ctx.arena.iter() {
-                        println!("{:?}:\n{:?}", id.index(), node);
-                        ctx.metadata.get(&id).map(|metadata| {
-                            println!("  {:?}", metadata);
-                        });
+        Command::Deps { format, inputs } => {
+            let (ctx, root) = load(&read_inputs(&inputs)?)?;
+            let graph = ctx.dependency_graph(root);
+            match format {
+                GraphFormat::Text => {
+                    let order = graph.topological_order().map_err(|cycles| {
+                        let cycles: Vec<String> = cycles
+                            .iter()
+                            .map(|cycle| ctx.describe_cycle(&graph, cycle))
+                            .collect();
+                        format!("Dependency cycle: {}", cycles.join(", "))
+                    })?;
+                    for node in order {
+                        writeln!(out, "{}", ctx.label(node))?;
                     }
-                    return;
                 }
-                None => {
-                    println!("Error parsing file");
-                    return;
+                GraphFormat::Dot => write!(out, "{}", ctx.dependencies_to_dot(&graph))?,
+                GraphFormat::Mermaid => write!(out, "{}", ctx.dependencies_to_mermaid(&graph))?,
+            }
+        }
+        Command::Ready(inputs) => {
+            let (mut ctx, root) = load(&read_inputs(&inputs)?)?;
+            let list = ctx.make_ready_list(root);
+            ctx.pretty_print(
+                list,
+                &mut PrintContext {
+                    level: 0,
+                    needs_indent: true,
+                    indent_width: DEFAULT_INDENT_WIDTH,
+                    out: &mut out,
+                },
+            )?;
+        }
+        Command::Stats { json, inputs } => {
+            let (ctx, root) = load(&read_inputs(&inputs)?)?;
+            if json {
+                let stats = ctx.stats_to_json(root);
+                writeln!(out, "{}", serde_json::to_string_pretty(&stats)?)?;
+            } else {
+                ctx.print_stats(
+                    root,
+                    &mut PrintContext {
+                        level: 0,
+                        needs_indent: true,
+                        indent_width: DEFAULT_INDENT_WIDTH,
+                        out: &mut out,
+                    },
+                )?;
+            }
+        }
+        Command::Search {
+            query,
+            limit,
+            json,
+            inputs,
+        } => {
+            let (ctx, root) = load(&read_inputs(&inputs)?)?;
+            let results = ctx.search(root, &query, limit);
+            if json {
+                let results: Vec<serde_json::Value> =
+                    results.iter().map(|result| result.to_json(&ctx)).collect();
+                writeln!(out, "{}", serde_json::to_string_pretty(&results)?)?;
+            } else {
+                for result in results {
+                    let mut path = result.breadcrumbs;
+                    path.push(ctx.label(result.node));
+                    writeln!(out, "{}", path.join(" > "))?;
                 }
             }
         }
-        "print_prioritized" => {
-            let mut ctx = puddlejumper::node::Context::new();
-            let result = ctx
-                .load_document(&p)
-                .ok_or(Error::new(ErrorKind::Other, "Error parsing file"))
-                .and_then(|node| {
-                    let list = ctx.make_prioritized_list(node);
-                    return ctx.pretty_print(
-                        list,
-                        &mut puddlejumper::node::printer::PrintContext {
-                            level: 0,
-                            needs_indent: true,
-                            out: &mut std::io::stdout(),
-                        },
-                    );
-                });
-            match result {
-                Ok(_) => (),
-                Err(error) => {
-                    println!("Error printing file: {}", error);
-                    return;
+        Command::Export { format, inputs } => {
+            let (ctx, root) = load(&read_inputs(&inputs)?)?;
+            let format = match format {
+                Format::Markdown => ExportFormat::Markdown,
+                Format::Html => ExportFormat::Html,
+                Format::Opml => ExportFormat::Opml,
+            };
+            ctx.export(root, format, &mut out)?;
+        }
+        Command::Import { from, file } => {
+            let input = read_input(&file)?;
+            let format = match from {
+                Source::Markdown => ImportFormat::Markdown,
+                Source::Opml => ImportFormat::Opml,
+            };
+            let mut ctx = Context::new();
+            let document = ctx
+                .import(&input.text, format)
+                .map_err(|error| format!("{}: {}", input.name, error))?;
+            ctx.print_source(document, &mut out)?;
+        }
+        Command::AssignIds { in_place, file } => {
+            if in_place && file == "-" {
+                return Err("--in-place needs a file, not stdin".into());
+            }
+            let input = read_input(&file)?;
+            let parser = Parser::new(input.text.clone(), tree_sitter_puddlejumper::language());
+            let mut ctx = Context::new();
+            let root = ctx
+                .load_document(&parser)
+                .ok_or_else(|| format!("{}: Error parsing file", input.name))?;
+            let edits = ctx.assign_stable_ids(root);
+            let text = apply_edits(&input.text, &edits);
+            if in_place {
+                fs::write(&file, &text).map_err(|error| format!("{}: {}", file, error))?;
+            } else {
+                write!(out, "{}", text)?;
+            }
+        }
+        Command::Diff {
+            old,
+            new,
+            json,
+            exit_code,
+        } => {
+            if old == "-" && new == "-" {
+                return Err("stdin (`-`) can only be read once".into());
+            }
+            let old = read_input(&old)?;
+            let new = read_input(&new)?;
+            let changes = puddlejumper::outline_diff::diff_outlines(&old.text, &new.text);
+            if json {
+                let json = puddlejumper::outline_diff::changes_to_json(&changes);
+                writeln!(out, "{}", serde_json::to_string_pretty(&json)?)?;
+            } else {
+                for change in &changes {
+                    writeln!(out, "{}", change)?;
                 }
             }
+            out.flush()?;
+            if exit_code && !changes.is_empty() {
+                process::exit(1);
+            }
+        }
+        Command::MergeDriver { base, ours, theirs } => {
+            let read = |path: &PathBuf| {
+                fs::read_to_string(path).map_err(|error| format!("{}: {}", path.display(), error))
+            };
+            let result = puddlejumper::merge::merge(&read(&base)?, &read(&ours)?, &read(&theirs)?);
+            fs::write(&ours, &result.text)
+                .map_err(|error| format!("{}: {}", ours.display(), error))?;
+            if result.conflicts > 0 {
+                eprintln!(
+                    "puddlejumper: {} conflict(s) in {}",
+                    result.conflicts,
+                    ours.display()
+                );
+                process::exit(1);
+            }
+        }
+        Command::Watch {
+            view: View::PrintPrioritized,
+            file,
+        } => watch(&file)?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn parse_date(text: &str) -> Result<Date, String> {
+    Date::parse(text).ok_or_else(|| format!("expected a date like 2026-10-19, got {:?}", text))
+}
+
+fn read_inputs(inputs: &Inputs) -> Result<Vec<Input>, Box<dyn Error>> {
+    if inputs.files.iter().filter(|file| *file == "-").count() > 1 {
+        return Err("stdin (`-`) can only be read once".into());
+    }
+    let mut files: Vec<String> = Vec::new();
+    for file in &inputs.files {
+        if Path::new(file).is_dir() {
+            let pj_files = puddlejumper::workspace::pj_files(Path::new(file))
+                .map_err(|error| format!("{}: {}", file, error))?;
+            files.extend(pj_files.iter().map(|path| path.display().to_string()));
+        } else {
+            files.push(file.clone());
+        }
+    }
+    files.iter().map(|file| read_input(file)).collect()
+}
+
+fn read_input(file: &str) -> Result<Input, Box<dyn Error>> {
+    let text = if file == "-" {
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text)?;
+        text
+    } else {
+        fs::read_to_string(file).map_err(|error| format!("{}: {}", file, error))?
+    };
+    Ok(Input {
+        name: file.to_string(),
+        text,
+    })
+}
+
+// Loads each input as its own document. With more than one input, the documents are combined
+// under one root so that projections see all of them, as in a workspace.
+fn load(inputs: &[Input]) -> Result<(Context, NodeId), Box<dyn Error>> {
+    let mut ctx = Context::new();
+    let mut documents: Vec<NodeId> = Vec::new();
+    for input in inputs {
+        let parser = Parser::new(input.text.clone(), tree_sitter_puddlejumper::language());
+        let document = ctx
+            .load_document(&parser)
+            .ok_or_else(|| format!("{}: Error parsing file", input.name))?;
+        if input.name != "-" {
+            if let Some(name) = puddlejumper::workspace::document_name(Path::new(&input.name)) {
+                ctx.document_names.insert(document, name);
+            }
+        }
+        documents.push(document);
+    }
+    let root = match documents.as_slice() {
+        [document] => *document,
+        _ => ctx.combine_documents(documents),
+    };
+    Ok((ctx, root))
+}
+
+// Re-renders the prioritized projection whenever the file changes, listing which items changed
+// priority since the last render.
+fn watch(path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut watcher = puddlejumper::watch::Watcher::new(path)
+        .map_err(|error| format!("{}: {}", path.display(), error))?;
+    let mut changes: Vec<puddlejumper::watch::PriorityChange> = Vec::new();
+    loop {
+        let mut out = std::io::stdout();
+        // Clear the screen and move the cursor home.
+        write!(out, "\x1b[2J\x1b[H")?;
+        for change in &changes {
+            writeln!(out, "{}", change)?;
         }
-        _ => {
-            print_usage();
-            return;
+        if !changes.is_empty() {
+            writeln!(out)?;
         }
+        watcher.render(&mut out)?;
+        out.flush()?;
+        changes = loop {
+            thread::sleep(Duration::from_millis(200));
+            match watcher.poll() {
+                Ok(Some(changes)) => break changes,
+                Ok(None) => {}
+                // Editors often replace the file on save, so it can briefly be missing.
+                Err(error) if error.kind() == ErrorKind::NotFound => {}
+                // Show the empty outline until the file loads again.
+                Err(error) if error.kind() == ErrorKind::InvalidData => break vec![],
+                Err(error) => return Err(format!("{}: {}", path.display(), error).into()),
+            }
+        };
     }
 }
@@ -0,0 +1,647 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use super::node::{Context, Node, NodeId};
+use super::parser::*;
+use super::tree_diff::UpdateContext;
+
+// Three-way structural merge of outlines, for `puddlejumper merge-driver`. Both sides are diffed
+// against the base with `tree_diff`, which tells which base item each of their items is. The
+// merge is then done per item: its own text (the lines before its children), whether it still
+// exists, and which item it sits under. Edits to different items never conflict; edits to the
+// same item conflict unless they agree, and are written out with conflict markers around that
+// item only. An item both sides moved to different parents is written under each of them, inside
+// conflict markers. Items are written with the exact text of a version that has them the same way,
+// so regions neither side touched keep their formatting and blank lines.
+
+pub struct MergeResult {
+    pub text: String,
+    pub conflicts: usize,
+}
+
+const DEFAULT_INDENT: usize = 4;
+
+#[derive(Debug)]
+struct Item {
+    range: Range<usize>,
+    // Where the item's first line starts.
+    line: usize,
+    // Source indentation of the item's first line.
+    column: usize,
+    // The item's own lines with its indentation removed, without its children.
+    text: String,
+    // The source from the item's first line up to the next item's, blank lines included.
+    raw: String,
+    parent: Option<usize>,
+}
+
+// The items of one version, in document order.
+#[derive(Debug)]
+struct Outline {
+    // Everything before the first item, e.g. the front matter.
+    prefix: String,
+    items: Vec<Item>,
+}
+
+impl Outline {
+    fn new(parser: &Parser) -> Option<Outline> {
+        let (ctx, root) = Context::from_parser(parser)?;
+        let mut outline = Outline {
+            prefix: String::new(),
+            items: Vec::new(),
+        };
+        let children = children(&ctx, root);
+        let prefix_end = children
+            .first()
+            .and_then(|child| ctx.source(*child))
+            .map(|source| line_start(&parser.text, source.range.start_byte))
+            .unwrap_or(parser.text.len());
+        outline.prefix = parser.text[..prefix_end].to_string();
+        for child in children {
+            outline.add(&ctx, &parser.text, child, None);
+        }
+        for index in 0..outline.items.len() {
+            let start = outline.items[index].line;
+            let end = outline
+                .items
+                .get(index + 1)
+                .map_or(parser.text.len(), |next| next.line)
+                .max(start);
+            outline.items[index].raw = parser.text[start..end].to_string();
+        }
+        Some(outline)
+    }
+
+    fn add(&mut self, ctx: &Context, text: &str, node: NodeId, parent: Option<usize>) {
+        let source = match ctx.source(node) {
+            Some(source) => source,
+            None => return,
+        };
+        let children = children(ctx, node);
+        let start = line_start(text, source.range.start_byte);
+        let column = source.range.start_byte - start;
+        let end = children
+            .first()
+            .and_then(|child| ctx.source(*child))
+            .map(|child| line_start(text, child.range.start_byte).max(start))
+            .unwrap_or(source.range.end_byte);
+        let own = text[start..end]
+            .trim_end()
+            .lines()
+            .map(|line| dedent(line, column))
+            .collect::<Vec<&str>>()
+            .join("\n");
+        self.items.push(Item {
+            range: source.range.byte_range(),
+            line: start,
+            column,
+            text: own,
+            raw: String::new(),
+            parent,
+        });
+        let index = self.items.len() - 1;
+        for child in children {
+            self.add(ctx, text, child, Some(index));
+        }
+    }
+}
+
+fn children(ctx: &Context, node: NodeId) -> Vec<NodeId> {
+    match &ctx.arena[node] {
+        Node::Document { children, .. }
+        | Node::Node { children, .. }
+        | Node::Block { children, .. } => children.clone(),
+        Node::Error { .. } => vec![],
+    }
+}
+
+fn line_start(text: &str, byte: usize) -> usize {
+    text[..byte].rfind('\n').map_or(0, |newline| newline + 1)
+}
+
+fn dedent(line: &str, column: usize) -> &str {
+    let spaces = line.len() - line.trim_start_matches(' ').len();
+    &line[spaces.min(column)..]
+}
+
+// One side of the merge, with each of its items mapped to a merge-wide id. Base items keep their
+// index as id.
+struct Side {
+    outline: Outline,
+    ids: Vec<usize>,
+}
+
+impl Side {
+    fn index_of(&self, id: usize) -> Option<usize> {
+        self.ids.iter().position(|item_id| *item_id == id)
+    }
+
+    fn parent_id(&self, index: usize) -> Option<usize> {
+        self.outline.items[index].parent.map(|parent| self.ids[parent])
+    }
+}
+
+// Loads `text` as an edit of `base`, matching its items to base items with the tree diff.
+fn load_side(base_text: &str, base: &Outline, text: &str, next_id: &mut usize) -> Option<Side> {
+    let mut parser = Parser::new(base_text.to_string(), tree_sitter_puddlejumper::language());
+    let update = parser.update(text.to_string());
+    let matched = {
+        let update_context = UpdateContext::new(&update);
+        let mut matched = update_context.matched_ranges("node");
+        matched.extend(update_context.matched_ranges("block"));
+        matched
+    };
+    parser.apply_update(update);
+    let outline = Outline::new(&parser)?;
+    let base_ids: HashMap<Range<usize>, usize> = base
+        .items
+        .iter()
+        .enumerate()
+        .filter_map(|(id, item)| matched.get(&item.range).map(|range| (range.clone(), id)))
+        .collect();
+    let ids = outline
+        .items
+        .iter()
+        .map(|item| match base_ids.get(&item.range) {
+            Some(id) => *id,
+            None => {
+                *next_id += 1;
+                *next_id - 1
+            }
+        })
+        .collect();
+    Some(Side { outline, ids })
+}
+
+enum Merged {
+    Clean(String),
+    Conflict { ours: String, theirs: String },
+}
+
+fn merge_text(base: Option<&str>, ours: Option<&str>, theirs: Option<&str>) -> Option<Merged> {
+    match (base, ours, theirs) {
+        (_, Some(ours), Some(theirs)) if ours == theirs => Some(Merged::Clean(ours.to_string())),
+        (Some(base), Some(ours), Some(theirs)) if ours == base => {
+            Some(Merged::Clean(theirs.to_string()))
+        }
+        (Some(base), Some(ours), Some(theirs)) if theirs == base => {
+            Some(Merged::Clean(ours.to_string()))
+        }
+        (Some(_), Some(ours), Some(theirs)) | (None, Some(ours), Some(theirs)) => {
+            Some(Merged::Conflict {
+                ours: ours.to_string(),
+                theirs: theirs.to_string(),
+            })
+        }
+        // Deleted on one side: fine if the other side left it alone.
+        (Some(base), Some(kept), None) | (Some(base), None, Some(kept)) if kept == base => None,
+        (Some(_), Some(ours), None) => Some(Merged::Conflict {
+            ours: ours.to_string(),
+            theirs: String::new(),
+        }),
+        (Some(_), None, Some(theirs)) => Some(Merged::Conflict {
+            ours: String::new(),
+            theirs: theirs.to_string(),
+        }),
+        (None, Some(new), None) | (None, None, Some(new)) => Some(Merged::Clean(new.to_string())),
+        (_, None, None) => None,
+    }
+}
+
+pub fn merge(base_text: &str, ours_text: &str, theirs_text: &str) -> MergeResult {
+    let clean = |text: &str| MergeResult {
+        text: text.to_string(),
+        conflicts: 0,
+    };
+    if ours_text == theirs_text || theirs_text == base_text {
+        return clean(ours_text);
+    }
+    if ours_text == base_text {
+        return clean(theirs_text);
+    }
+    let base_parser = Parser::new(base_text.to_string(), tree_sitter_puddlejumper::language());
+    let base = Outline::new(&base_parser);
+    let mut next_id = base.as_ref().map_or(0, |base| base.items.len());
+    let sides = base.as_ref().and_then(|base| {
+        let ours = load_side(base_text, base, ours_text, &mut next_id)?;
+        let theirs = load_side(base_text, base, theirs_text, &mut next_id)?;
+        Some((ours, theirs))
+    });
+    match (base, sides) {
+        (Some(base), Some((ours, theirs))) => Merger::new(base, ours, theirs, next_id).merge(),
+        // Nothing structural to go on, so conflict on the whole file.
+        _ => {
+            let mut text = String::new();
+            write_conflict(&mut text, ours_text.trim_end(), theirs_text.trim_end());
+            MergeResult { text, conflicts: 1 }
+        }
+    }
+}
+
+struct Merger {
+    base: Outline,
+    ours: Side,
+    theirs: Side,
+    // Merged own text of each id that survives the merge.
+    texts: HashMap<usize, Merged>,
+    // Ids that stand for the same item, e.g. when both sides added the same line.
+    aliases: HashMap<usize, usize>,
+}
+
+impl Merger {
+    fn new(base: Outline, ours: Side, theirs: Side, ids: usize) -> Merger {
+        let mut merger = Merger {
+            base,
+            ours,
+            theirs,
+            texts: HashMap::new(),
+            aliases: HashMap::new(),
+        };
+        merger.unify_additions();
+        for id in 0..ids {
+            if merger.aliases.contains_key(&id) {
+                continue;
+            }
+            let base = merger.base.items.get(id).map(|item| item.text.as_str());
+            let ours = merger.side_text(true, id);
+            let theirs = merger.side_text(false, id);
+            if let Some(merged) = merge_text(base, ours, theirs) {
+                merger.texts.insert(id, merged);
+            }
+        }
+        merger
+    }
+
+    fn side(&self, ours: bool) -> &Side {
+        if ours {
+            &self.ours
+        } else {
+            &self.theirs
+        }
+    }
+
+    fn side_text(&self, ours: bool, id: usize) -> Option<&str> {
+        let side = self.side(ours);
+        side.index_of(id)
+            .map(|index| side.outline.items[index].text.as_str())
+    }
+
+    // Both sides adding the same text under the same parent is one addition, not two.
+    fn unify_additions(&mut self) {
+        let base_len = self.base.items.len();
+        for index in 0..self.theirs.ids.len() {
+            let id = self.theirs.ids[index];
+            if id < base_len {
+                continue;
+            }
+            let parent = self.theirs.parent_id(index).map(|parent| self.alias(parent));
+            let text = &self.theirs.outline.items[index].text;
+            let same = (0..self.ours.ids.len()).find(|ours_index| {
+                let ours_id = self.ours.ids[*ours_index];
+                ours_id >= base_len
+                    && !self.aliases.values().any(|aliased| *aliased == ours_id)
+                    && self.ours.outline.items[*ours_index].text == *text
+                    && self.ours.parent_id(*ours_index) == parent
+            });
+            if let Some(ours_index) = same {
+                self.aliases.insert(id, self.ours.ids[ours_index]);
+            }
+        }
+        for id in self.theirs.ids.iter_mut() {
+            if let Some(alias) = self.aliases.get(id) {
+                *id = *alias;
+            }
+        }
+    }
+
+    fn alias(&self, id: usize) -> usize {
+        *self.aliases.get(&id).unwrap_or(&id)
+    }
+
+    fn parent_in(&self, version: Version, id: usize) -> Option<Option<usize>> {
+        match version {
+            Version::Base => self
+                .base
+                .items
+                .get(id)
+                .map(|item| item.parent),
+            Version::Ours | Version::Theirs => {
+                let side = self.side(version == Version::Ours);
+                side.index_of(id).map(|index| side.parent_id(index))
+            }
+        }
+    }
+
+    // Where `id` ends up. A side that moved it wins; if both moved it to different parents, it
+    // goes under both, as a conflict.
+    fn merged_parents(&self, id: usize) -> Vec<(Option<usize>, Placement)> {
+        let base = self.parent_in(Version::Base, id);
+        let ours = self.parent_in(Version::Ours, id);
+        let theirs = self.parent_in(Version::Theirs, id);
+        if let (Some(ours), Some(theirs)) = (ours, theirs) {
+            if ours != theirs && Some(ours) != base && Some(theirs) != base {
+                let (ours, theirs) = (self.surviving(ours), self.surviving(theirs));
+                if ours != theirs {
+                    return vec![(ours, Placement::Ours), (theirs, Placement::Theirs)];
+                }
+            }
+        }
+        let parent = match (ours, theirs) {
+            (Some(ours), _) if Some(ours) != base => ours,
+            (_, Some(theirs)) => theirs,
+            (Some(ours), None) => ours,
+            (None, None) => base.flatten(),
+        };
+        vec![(self.surviving(parent), Placement::Merged)]
+    }
+
+    // `parent`, or the closest of its ancestors that survives the merge.
+    fn surviving(&self, mut parent: Option<usize>) -> Option<usize> {
+        let mut seen: HashSet<usize> = HashSet::new();
+        while let Some(id) = parent {
+            if self.texts.contains_key(&id) || !seen.insert(id) {
+                break;
+            }
+            parent = [Version::Ours, Version::Theirs, Version::Base]
+                .iter()
+                .find_map(|version| self.parent_in(*version, id))
+                .flatten();
+        }
+        parent.filter(|id| self.texts.contains_key(id))
+    }
+
+    // Surviving ids in document order: ours, with items only theirs has slotted in after the
+    // item that precedes them in theirs.
+    fn order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = self.ours.ids.clone();
+        let mut previous: Option<usize> = None;
+        for id in &self.theirs.ids {
+            match order.iter().position(|ordered| ordered == id) {
+                Some(position) => previous = Some(position),
+                None => {
+                    let position = previous.map_or(0, |previous| previous + 1);
+                    order.insert(position, *id);
+                    previous = Some(position);
+                }
+            }
+        }
+        for id in 0..self.base.items.len() {
+            if !order.contains(&id) {
+                order.push(id);
+            }
+        }
+        order
+            .into_iter()
+            .filter(|id| self.texts.contains_key(id))
+            .collect()
+    }
+
+    // How far `id` is indented relative to `parent`, from a version where it sits under it.
+    fn relative_indent(&self, id: usize, parent: usize) -> usize {
+        for version in [Version::Ours, Version::Theirs, Version::Base].iter() {
+            if self.parent_in(*version, id) != Some(Some(parent)) {
+                continue;
+            }
+            if let (Some(column), Some(parent_column)) =
+                (self.column_in(*version, id), self.column_in(*version, parent))
+            {
+                return column.saturating_sub(parent_column);
+            }
+        }
+        match self.texts.get(&parent) {
+            // Blocks don't indent their children.
+            Some(Merged::Clean(text)) if text.trim_start().starts_with('#') => 0,
+            _ => DEFAULT_INDENT,
+        }
+    }
+
+    fn column_in(&self, version: Version, id: usize) -> Option<usize> {
+        self.item_in(version, id).map(|item| item.column)
+    }
+
+    fn item_in(&self, version: Version, id: usize) -> Option<&Item> {
+        match version {
+            Version::Base => self.base.items.get(id),
+            Version::Ours | Version::Theirs => {
+                let side = self.side(version == Version::Ours);
+                side.index_of(id).map(|index| &side.outline.items[index])
+            }
+        }
+    }
+
+    // The source of a version that has `id` with this text at this column, to write instead of
+    // re-indenting it.
+    fn raw_text(&self, id: usize, own: &str, column: usize) -> Option<&str> {
+        [Version::Ours, Version::Theirs, Version::Base]
+            .iter()
+            .filter_map(|version| self.item_in(*version, id))
+            .find(|item| item.text == own && item.column == column)
+            .map(|item| item.raw.as_str())
+    }
+
+    fn merge(self) -> MergeResult {
+        let mut conflicts = 0;
+        let mut text = String::new();
+        match merge_text(
+            Some(&self.base.prefix),
+            Some(&self.ours.outline.prefix),
+            Some(&self.theirs.outline.prefix),
+        ) {
+            Some(Merged::Clean(prefix)) => text.push_str(&prefix),
+            Some(Merged::Conflict { ours, theirs }) => {
+                conflicts += 1;
+                write_conflict(&mut text, ours.trim_end(), theirs.trim_end());
+            }
+            None => {}
+        }
+
+        let mut layout = Layout {
+            children: HashMap::new(),
+            ancestors: Vec::new(),
+            conflicts,
+        };
+        for id in self.order() {
+            for (parent, placement) in self.merged_parents(id) {
+                layout.children.entry(parent).or_default().push((id, placement));
+            }
+        }
+        for (id, placement) in layout.children.get(&None).cloned().unwrap_or_default() {
+            let column = [Version::Ours, Version::Theirs, Version::Base]
+                .iter()
+                .find_map(|version| self.column_in(*version, id))
+                .unwrap_or(0);
+            self.write_placed(&mut text, id, placement, column, &mut layout);
+        }
+        MergeResult {
+            text,
+            conflicts: layout.conflicts,
+        }
+    }
+
+    fn write_placed(
+        &self,
+        out: &mut String,
+        id: usize,
+        placement: Placement,
+        column: usize,
+        layout: &mut Layout,
+    ) {
+        if placement == Placement::Merged {
+            self.write_item(out, id, column, layout);
+            return;
+        }
+        let mut moved = String::new();
+        self.write_item(&mut moved, id, column, layout);
+        layout.conflicts += 1;
+        if placement == Placement::Ours {
+            write_conflict(out, moved.trim_end(), "");
+        } else {
+            write_conflict(out, "", moved.trim_end());
+        }
+    }
+
+    // Writes `id` and the items under it.
+    fn write_item(&self, out: &mut String, id: usize, column: usize, layout: &mut Layout) {
+        if layout.ancestors.contains(&id) {
+            return;
+        }
+        match &self.texts[&id] {
+            Merged::Clean(own) => match self.raw_text(id, own, column) {
+                Some(raw) => {
+                    out.push_str(raw);
+                    if !raw.ends_with('\n') {
+                        out.push('\n');
+                    }
+                }
+                None => write_indented(out, own, column),
+            },
+            Merged::Conflict { ours, theirs } => {
+                layout.conflicts += 1;
+                let mut ours_text = String::new();
+                write_indented(&mut ours_text, ours, column);
+                let mut theirs_text = String::new();
+                write_indented(&mut theirs_text, theirs, column);
+                write_conflict(out, ours_text.trim_end(), theirs_text.trim_end());
+            }
+        }
+        layout.ancestors.push(id);
+        for (child, placement) in layout.children.get(&Some(id)).cloned().unwrap_or_default() {
+            let child_column = column + self.relative_indent(child, id);
+            self.write_placed(out, child, placement, child_column, layout);
+        }
+        layout.ancestors.pop();
+    }
+}
+
+// Where an item is written: under its merged parent, or under the parent one side moved it to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Placement {
+    Merged,
+    Ours,
+    Theirs,
+}
+
+// The merged tree while it's written out.
+struct Layout {
+    children: HashMap<Option<usize>, Vec<(usize, Placement)>>,
+    // The items being written, to not loop on items the sides moved under each other.
+    ancestors: Vec<usize>,
+    conflicts: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Version {
+    Base,
+    Ours,
+    Theirs,
+}
+
+fn write_indented(out: &mut String, text: &str, column: usize) {
+    for line in text.lines() {
+        if !line.is_empty() {
+            out.push_str(&" ".repeat(column));
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+}
+
+fn write_conflict(out: &mut String, ours: &str, theirs: &str) {
+    out.push_str("<<<<<<< ours\n");
+    if !ours.is_empty() {
+        out.push_str(ours);
+        out.push('\n');
+    }
+    out.push_str("=======\n");
+    if !theirs.is_empty() {
+        out.push_str(theirs);
+        out.push('\n');
+    }
+    out.push_str(">>>>>>> theirs\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_different_items() {
+        let base = "hello\n    a\nworld\n    b\n";
+        let ours = "hello\n    a2\nworld\n    b\n";
+        let theirs = "hello\n    a\nworld\n    b2\n";
+        let result = merge(base, ours, theirs);
+        assert_eq!(result.conflicts, 0);
+        assert_eq!(result.text, "hello\n    a2\nworld\n    b2\n");
+    }
+
+    #[test]
+    fn test_merge_additions_and_deletions() {
+        let base = "hello\nworld\n";
+        let ours = "hello\n    P1\nworld\n";
+        let theirs = "hello\nworld\nfoo\n";
+        let result = merge(base, ours, theirs);
+        assert_eq!(result.conflicts, 0);
+        assert_eq!(result.text, "hello\n    P1\nworld\nfoo\n");
+
+        let result = merge("a\nb\nc\n", "a\nc\n", "a\nb\nc\nd\n");
+        assert_eq!(result.conflicts, 0);
+        assert_eq!(result.text, "a\nc\nd\n");
+    }
+
+    #[test]
+    fn test_merge_conflict() {
+        let base = "hello\nworld\n";
+        let ours = "hello\nwarld\n";
+        let theirs = "hello\nwurld\n";
+        let result = merge(base, ours, theirs);
+        assert_eq!(result.conflicts, 1);
+        assert_eq!(
+            result.text,
+            "hello\n<<<<<<< ours\nwarld\n=======\nwurld\n>>>>>>> theirs\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_conflicting_moves() {
+        let base = "a\nb\nc\n";
+        let ours = "a\n    c\nb\n";
+        let theirs = "a\nb\n    c\n";
+        let result = merge(base, ours, theirs);
+        assert_eq!(result.conflicts, 2);
+        assert_eq!(
+            result.text,
+            "a\n<<<<<<< ours\n    c\n=======\n>>>>>>> theirs\n\
+             b\n<<<<<<< ours\n=======\n    c\n>>>>>>> theirs\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_keeps_formatting() {
+        let base = "a\n\nb\n      c\n\nd\n";
+        let ours = "a\n\nb\n      c2\n\nd\n";
+        let theirs = "a\n\nb\n      c\n\nd2\n";
+        let result = merge(base, ours, theirs);
+        assert_eq!(result.conflicts, 0);
+        assert_eq!(result.text, "a\n\nb\n      c2\n\nd2\n");
+    }
+}
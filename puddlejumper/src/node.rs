@@ -1,9 +1,23 @@
 pub mod printer;
 pub mod extract_priorities;
+pub mod attributes;
+pub mod json;
+pub mod source;
+pub mod export;
+pub mod import;
+pub mod edit;
+pub mod gc;
+pub mod stable_id;
+pub mod tasks;
+pub mod agenda;
+pub mod dependencies;
+pub mod stats;
+pub mod search;
 
-use std::{collections::HashMap, time::SystemTime};
+use std::{collections::HashMap, ops::Range, time::SystemTime};
 
 use id_arena::{Arena, Id};
+use indexmap::IndexMap;
 
 #[derive(Debug)]
 pub enum Content {
@@ -16,6 +30,8 @@ pub type NodeId = Id<Node>;
 #[derive(Debug)]
 pub enum Node {
     Document {
+        // `key: value` pairs from the front matter, in source order.
+        attributes: IndexMap<String, String>,
         children: Vec<NodeId>,
     },
     Node {
@@ -25,18 +41,78 @@ pub enum Node {
     },
     Block {
         binding: Option<String>,
+        // Number of `#`s in the header, e.g. 2 for `## header`.
+        level: usize,
         header: NodeId,
         children: Vec<NodeId>,
     },
+    // Source that failed to parse, kept verbatim so that nothing is lost while mid-edit.
+    Error {
+        text: String,
+        range: Range<usize>,
+    },
 }
 
 #[derive(Debug)]
 pub struct Context {
     pub arena: Arena<Node>,
     pub metadata: HashMap<NodeId, NodeMetadata>,
+    // Where each loaded node came from. Nodes built by projections have no entry.
+    pub sources: HashMap<NodeId, NodeSource>,
+    // `^id` markers of the nodes that have one, see `stable_id`.
+    pub stable_ids: HashMap<NodeId, String>,
+    // Names of documents loaded from files, by their root, e.g. `backend` for `backend.pj`.
+    // Qualified refs like `@backend.api` resolve against these.
+    pub document_names: HashMap<NodeId, String>,
+    // The parent of each loaded node and the node's index among its children, recorded at load
+    // time. Projections that share loaded nodes don't change it, see `parent`.
+    pub parents: HashMap<NodeId, (NodeId, usize)>,
+    // Arena size at which `collect_garbage` compacts next.
+    pub gc_threshold: usize,
+}
+
+impl Context {
+    // How a node is named in listings: its content or ref, else `@binding`. Blocks are named by
+    // their header, and parse errors by their text.
+    pub fn label(&self, node: NodeId) -> String {
+        match &self.arena[node] {
+            Node::Node {
+                content: Some(Content::Content(text)),
+                ..
+            }
+            | Node::Node {
+                content: Some(Content::Ref(text)),
+                ..
+            } => text.clone(),
+            Node::Node {
+                binding: Some(binding),
+                ..
+            } => format!("@{}", binding),
+            Node::Block { header, .. } => self.label(*header),
+            Node::Error { text, .. } => text.trim().to_string(),
+            _ => String::new(),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct NodeMetadata {
     pub created_at: SystemTime,
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceRange {
+    pub start_byte: usize,
+    pub end_byte: usize,
+    pub start_position: tree_sitter::Point,
+    pub end_position: tree_sitter::Point,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeSource {
+    pub range: SourceRange,
+    // The identifier after `@`, for nodes and blocks with a binding.
+    pub binding: Option<SourceRange>,
+    // The content or ref of a node.
+    pub content: Option<SourceRange>,
+}
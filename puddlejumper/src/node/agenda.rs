@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use super::attributes::DocumentSettings;
+use super::*;
+
+// Due and scheduled dates, and the agenda projection built from them. A date is either a child
+// node like a priority marker:
+//
+//     Ship the API
+//         due: 2026-10-20
+//         scheduled: 2026-10-14
+//
+// or a token in the content, e.g. `Ship the API due:2026-10-20`. Dates are `YYYY-MM-DD`.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Date {
+    pub fn new(year: i32, month: u32, day: u32) -> Option<Date> {
+        let date = Date { year, month, day };
+        if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+            return None;
+        }
+        Some(date)
+    }
+
+    pub fn parse(text: &str) -> Option<Date> {
+        let mut parts = text.trim().splitn(3, '-');
+        let year = parts.next()?;
+        let month = parts.next()?;
+        let day = parts.next()?;
+        if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+            return None;
+        }
+        Date::new(year.parse().ok()?, month.parse().ok()?, day.parse().ok()?)
+    }
+
+    // Days since 1970-01-01, after Howard Hinnant's `days_from_civil`.
+    pub fn days(&self) -> i64 {
+        let year = if self.month <= 2 {
+            self.year as i64 - 1
+        } else {
+            self.year as i64
+        };
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let month = self.month as i64;
+        let day_of_year =
+            (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + self.day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146097 + day_of_era - 719468
+    }
+
+    pub fn from_days(days: i64) -> Date {
+        let days = days + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days - era * 146097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        } as u32;
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+        Date {
+            year: year as i32,
+            month,
+            day,
+        }
+    }
+
+    // The UTC date.
+    pub fn from_system_time(time: SystemTime) -> Date {
+        let seconds = match time.duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_secs() as i64,
+            Err(error) => -(error.duration().as_secs() as i64),
+        };
+        Date::from_days(seconds.div_euclid(86400))
+    }
+
+    pub fn today() -> Date {
+        Date::from_system_time(SystemTime::now())
+    }
+
+    pub fn add_days(&self, days: i64) -> Date {
+        Date::from_days(self.days() + days)
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        4 | 6 | 9 | 11 => 30,
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        _ => 31,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Dates {
+    pub due: Option<Date>,
+    pub scheduled: Option<Date>,
+}
+
+impl Dates {
+    // The date the agenda goes by: when it's due, or else when it's scheduled.
+    pub fn agenda_date(&self) -> Option<Date> {
+        self.due.or(self.scheduled)
+    }
+
+    // Reads a `due:` or `scheduled:` attribute or token, ignoring anything else.
+    fn set(&mut self, text: &str) -> bool {
+        let (key, value) = match text.split_once(':') {
+            Some(pair) => pair,
+            None => return false,
+        };
+        let date = match Date::parse(value) {
+            Some(date) => date,
+            None => return false,
+        };
+        match key.trim() {
+            "due" => self.due = Some(date),
+            "scheduled" => self.scheduled = Some(date),
+            _ => return false,
+        }
+        true
+    }
+}
+
+// Whether `content` is a date attribute, like `due: 2026-10-20`, rather than an item.
+pub fn is_date_attribute(content: &str) -> bool {
+    Dates::default().set(content)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AgendaGroup {
+    Overdue,
+    Today,
+    // The next seven days.
+    ThisWeek,
+    Later,
+}
+
+impl AgendaGroup {
+    pub const ALL: [AgendaGroup; 4] = [
+        AgendaGroup::Overdue,
+        AgendaGroup::Today,
+        AgendaGroup::ThisWeek,
+        AgendaGroup::Later,
+    ];
+
+    pub fn of(date: Date, today: Date) -> AgendaGroup {
+        match date.days() - today.days() {
+            days if days < 0 => AgendaGroup::Overdue,
+            0 => AgendaGroup::Today,
+            1..=7 => AgendaGroup::ThisWeek,
+            _ => AgendaGroup::Later,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AgendaGroup::Overdue => "Overdue",
+            AgendaGroup::Today => "Today",
+            AgendaGroup::ThisWeek => "This week",
+            AgendaGroup::Later => "Later",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AgendaItem {
+    pub node: NodeId,
+    pub group: AgendaGroup,
+    pub dates: Dates,
+    // Index into the document's priorities, if the item has one.
+    pub priority: Option<usize>,
+}
+
+impl Context {
+    pub fn dates(&self, node: NodeId) -> Dates {
+        let mut dates = Dates::default();
+        let (content, children) = match &self.arena[node] {
+            Node::Node {
+                content, children, ..
+            } => (content, children),
+            _ => return dates,
+        };
+        if let Some(Content::Content(text)) = content {
+            if !is_date_attribute(text) {
+                for token in text.split_whitespace() {
+                    dates.set(token);
+                }
+            }
+        }
+        for child in children {
+            if let Node::Node {
+                content: Some(Content::Content(text)),
+                ..
+            } = &self.arena[*child]
+            {
+                dates.set(text);
+            }
+        }
+        dates
+    }
+
+    // Open items under `node` with a date, by group, then by priority and date within a group.
+    pub fn agenda(&self, node: NodeId, today: Date) -> Vec<AgendaItem> {
+        let mut priorities: HashMap<NodeId, usize> = HashMap::new();
+        for (item, priority) in self.extract_priorities(node) {
+            let priority = priority as usize;
+            priorities
+                .entry(item)
+                .and_modify(|p| *p = (*p).min(priority))
+                .or_insert(priority);
+        }
+        let mut items: Vec<AgendaItem> = Vec::new();
+        self.walk(node, &mut |id, _| {
+            let dates = self.dates(id);
+            let date = match dates.agenda_date() {
+                Some(date) if !self.is_done(id) => date,
+                _ => return,
+            };
+            items.push(AgendaItem {
+                node: id,
+                group: AgendaGroup::of(date, today),
+                dates,
+                priority: priorities.get(&id).copied(),
+            });
+        });
+        // Items without a priority go after the ones with one.
+        items.sort_by_key(|item| {
+            (
+                item.group,
+                item.priority.unwrap_or(usize::MAX),
+                item.dates.agenda_date(),
+            )
+        });
+        items
+    }
+
+    // Like `make_prioritized_list`, with a group node for each of `AgendaGroup` that has items.
+    pub fn make_agenda(&mut self, node: NodeId, today: Date) -> NodeId {
+        let items = self.agenda(node, today);
+        let children: Vec<NodeId> = AgendaGroup::ALL
+            .iter()
+            .filter_map(|group| {
+                let nodes: Vec<NodeId> = items
+                    .iter()
+                    .filter(|item| item.group == *group)
+                    .map(|item| item.node)
+                    .collect();
+                if nodes.is_empty() {
+                    return None;
+                }
+                Some(self.arena.alloc(Node::Node {
+                    binding: None,
+                    content: Some(Content::Content(group.name().to_string())),
+                    children: nodes,
+                }))
+            })
+            .collect();
+        self.arena.alloc(Node::Document {
+            attributes: self.attributes(node).cloned().unwrap_or_default(),
+            children,
+        })
+    }
+}
+
+impl AgendaItem {
+    // `settings` are those of the document the agenda was made from, for the priority names.
+    pub fn to_json(&self, ctx: &Context, settings: &DocumentSettings) -> Value {
+        let content = match &ctx.arena[self.node] {
+            Node::Node {
+                content: Some(Content::Content(text)),
+                ..
+            }
+            | Node::Node {
+                content: Some(Content::Ref(text)),
+                ..
+            } => Some(text.clone()),
+            _ => None,
+        };
+        let date = |date: Option<Date>| date.map(|date| date.to_string());
+        json!({
+            "group": self.group.name(),
+            "content": content,
+            "stable_id": ctx.stable_id(self.node),
+            "due": date(self.dates.due),
+            "scheduled": date(self.dates.scheduled),
+            "priority": self.priority.and_then(|p| settings.priorities.get(p)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::printer::{PrintContext, DEFAULT_INDENT_WIDTH};
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_date() {
+        let date = Date::parse("2026-10-19").unwrap();
+        assert_eq!(date.to_string(), "2026-10-19");
+        assert_eq!(Date::from_days(date.days()), date);
+        assert_eq!(Date::parse("1970-01-01").unwrap().days(), 0);
+        assert_eq!(date.add_days(13).to_string(), "2026-11-01");
+        assert_eq!(
+            Date::parse("2024-02-28").unwrap().add_days(1).to_string(),
+            "2024-02-29"
+        );
+        assert_eq!(
+            Date::parse("2100-02-28").unwrap().add_days(1).to_string(),
+            "2100-03-01"
+        );
+        assert_eq!(Date::parse("1969-12-31").unwrap().days(), -1);
+        assert_eq!(Date::parse("2026-02-29"), None);
+        assert_eq!(Date::parse("2026-1-9"), None);
+        assert_eq!(
+            Date::from_system_time(UNIX_EPOCH + std::time::Duration::from_secs(86400 * 365)),
+            Date::parse("1971-01-01").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_agenda_groups() {
+        let today = Date::parse("2026-10-19").unwrap();
+        let of = |text: &str| AgendaGroup::of(Date::parse(text).unwrap(), today);
+        assert_eq!(of("2026-10-18"), AgendaGroup::Overdue);
+        assert_eq!(of("2026-10-19"), AgendaGroup::Today);
+        assert_eq!(of("2026-10-26"), AgendaGroup::ThisWeek);
+        assert_eq!(of("2026-10-27"), AgendaGroup::Later);
+        assert!(is_date_attribute("due: 2026-10-19"));
+        assert!(!is_date_attribute("due tomorrow"));
+    }
+
+    #[test]
+    fn test_make_agenda() {
+        let text = "\
+a due:2026-10-25
+b
+    due: 2026-10-19
+    P2
+c
+    scheduled: 2026-10-19
+    P0
+[x] d due:2026-10-01
+e due:2026-10-01
+f
+";
+        let parser = Parser::new(String::from(text), tree_sitter_puddlejumper::language());
+        let mut ctx = Context::new();
+        let root = ctx.load_document(&parser).unwrap();
+        let agenda = ctx.make_agenda(root, Date::parse("2026-10-19").unwrap());
+        let mut out: Vec<u8> = Vec::new();
+        ctx.pretty_print(
+            agenda,
+            &mut PrintContext {
+                level: 0,
+                needs_indent: true,
+                indent_width: DEFAULT_INDENT_WIDTH,
+                out: &mut out,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\
+Overdue
+    e due:2026-10-01
+Today
+    c
+        scheduled: 2026-10-19
+        P0
+    b
+        due: 2026-10-19
+        P2
+This week
+    a due:2026-10-25
+"
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use super::*;
+
+// Per-document settings read from the front matter, e.g.
+//
+// ---
+// title: Roadmap
+// owner: sleexyz
+// priorities: P0, P1, P2
+// indent_width: 2
+// ---
+#[derive(Debug, PartialEq, Clone)]
+pub struct DocumentSettings {
+    pub title: Option<String>,
+    pub owner: Option<String>,
+    // Indent width used when printing. `None` keeps the printer's default.
+    pub indent_width: Option<usize>,
+    // Priority markers, from highest to lowest priority.
+    pub priorities: Vec<String>,
+}
+
+impl Default for DocumentSettings {
+    fn default() -> Self {
+        DocumentSettings {
+            title: None,
+            owner: None,
+            indent_width: None,
+            priorities: (0..5).map(|p| format!("P{}", p)).collect(),
+        }
+    }
+}
+
+impl DocumentSettings {
+    pub fn from_attributes(attributes: &IndexMap<String, String>) -> Self {
+        let mut settings = DocumentSettings::default();
+        for (key, value) in attributes {
+            match key.as_str() {
+                "title" => settings.title = Some(value.clone()),
+                "owner" => settings.owner = Some(value.clone()),
+                "indent_width" => {
+                    settings.indent_width = value.parse().ok().filter(|width| *width > 0);
+                }
+                "priorities" => {
+                    let priorities: Vec<String> = value
+                        .trim_start_matches('[')
+                        .trim_end_matches(']')
+                        .split(',')
+                        .map(|p| p.trim().to_string())
+                        .filter(|p| !p.is_empty())
+                        .collect();
+                    if !priorities.is_empty() {
+                        settings.priorities = priorities;
+                    }
+                }
+                _ => {}
+            }
+        }
+        settings
+    }
+
+    // Index of `content` in the priority schema, if it is a priority marker.
+    pub fn priority_of(&self, content: &str) -> Option<usize> {
+        self.priorities.iter().position(|p| p == content)
+    }
+}
+
+impl Context {
+    pub fn attributes(&self, node: NodeId) -> Option<&IndexMap<String, String>> {
+        match &self.arena[node] {
+            Node::Document { attributes, .. } => Some(attributes),
+            _ => None,
+        }
+    }
+
+    // Settings for `node` if it is a document, or the defaults otherwise.
+    pub fn document_settings(&self, node: NodeId) -> DocumentSettings {
+        self.attributes(node)
+            .map(DocumentSettings::from_attributes)
+            .unwrap_or_default()
+    }
+
+    // A document with `documents` as its children, e.g. the files of a project, so that
+    // projections span all of them. Its priorities are those of the documents merged by rank, so
+    // that every document's first priority comes before any document's second, and it keeps the
+    // indent width if the documents agree on one. Markers under each document are still read
+    // with that document's own priorities, see `extract_priorities`.
+    pub fn combine_documents(&mut self, documents: Vec<NodeId>) -> NodeId {
+        let settings: Vec<DocumentSettings> = documents
+            .iter()
+            .map(|document| self.document_settings(*document))
+            .collect();
+        let ranks = settings.iter().map(|s| s.priorities.len()).max().unwrap_or(0);
+        let mut priorities: Vec<&String> = Vec::new();
+        for rank in 0..ranks {
+            for priority in settings.iter().filter_map(|s| s.priorities.get(rank)) {
+                if !priorities.contains(&priority) {
+                    priorities.push(priority);
+                }
+            }
+        }
+        let mut attributes: IndexMap<String, String> = IndexMap::new();
+        if !priorities.is_empty() {
+            let priorities: Vec<&str> = priorities.iter().map(|p| p.as_str()).collect();
+            attributes.insert(String::from("priorities"), priorities.join(", "));
+        }
+        let widths: Vec<Option<usize>> = settings.iter().map(|s| s.indent_width).collect();
+        if let Some(Some(width)) = widths.first() {
+            if widths.iter().all(|w| *w == Some(*width)) {
+                attributes.insert(String::from("indent_width"), width.to_string());
+            }
+        }
+        self.arena.alloc(Node::Document {
+            attributes,
+            children: documents,
+        })
+    }
+}
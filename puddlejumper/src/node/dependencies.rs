@@ -0,0 +1,592 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use super::*;
+use crate::workspace::split_ref;
+
+// Dependencies between items. Refs under a `blocked_by` or `depends` child say what an item is
+// waiting on:
+//
+//     Deploy
+//         blocked_by
+//             @api
+//             @db
+//     @api: [x] Build the API
+//     @db: [ ] Migrate the database
+//
+// The graph has an edge from each item to each item it is blocked by. An item is ready to work
+// on once it's open and everything it's blocked by is done, see `tasks`.
+//
+// A ref resolves to a binding in the document it is written in. A qualified ref like
+// `@backend.api` resolves in the document named `backend` in `Context::document_names`, and
+// is unresolved if there is none.
+
+pub const DEPENDENCY_MARKERS: [&str; 2] = ["blocked_by", "depends"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dependency {
+    pub item: NodeId,
+    pub blocker: NodeId,
+    // The ref that says so.
+    pub reference: NodeId,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DependencyGraph {
+    // Items with dependencies or depended on, in document order.
+    pub nodes: Vec<NodeId>,
+    pub edges: Vec<Dependency>,
+    // Refs under a marker that don't resolve to a binding, with the item they are under.
+    pub unresolved: Vec<(NodeId, NodeId)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DependencyDiagnostic {
+    // The ref the diagnostic is about.
+    pub node: NodeId,
+    pub message: String,
+}
+
+fn is_dependency_marker(content: &str) -> bool {
+    DEPENDENCY_MARKERS.contains(&content.trim().trim_end_matches(':'))
+}
+
+impl Context {
+    pub fn dependency_graph(&self, root: NodeId) -> DependencyGraph {
+        // Bindings by the document they are in. The first binding of a name in a document wins,
+        // as in a workspace.
+        let mut bindings: HashMap<NodeId, HashMap<String, NodeId>> = HashMap::new();
+        // Every node, with the document it is in.
+        let mut order: Vec<(NodeId, NodeId)> = Vec::new();
+        self.walk_in_document(root, root, &mut |id, node, document| {
+            order.push((id, document));
+            if let Node::Node {
+                binding: Some(binding),
+                ..
+            }
+            | Node::Block {
+                binding: Some(binding),
+                ..
+            } = node
+            {
+                bindings
+                    .entry(document)
+                    .or_default()
+                    .entry(binding.clone())
+                    .or_insert(id);
+            }
+        });
+        let documents: HashMap<&str, NodeId> = self
+            .document_names
+            .iter()
+            .map(|(document, name)| (name.as_str(), *document))
+            .collect();
+
+        let mut edges: Vec<Dependency> = Vec::new();
+        let mut unresolved: Vec<(NodeId, NodeId)> = Vec::new();
+        for (item, document) in &order {
+            for reference in self.dependency_refs(*item) {
+                let text = match &self.arena[reference] {
+                    Node::Node {
+                        content: Some(Content::Ref(text)),
+                        ..
+                    } => text,
+                    _ => continue,
+                };
+                let (qualifier, name) = split_ref(text);
+                let scope = match qualifier {
+                    Some(qualifier) => documents.get(qualifier).copied(),
+                    None => Some(*document),
+                };
+                match scope.and_then(|scope| bindings.get(&scope)?.get(name)) {
+                    Some(blocker) => edges.push(Dependency {
+                        item: *item,
+                        blocker: *blocker,
+                        reference,
+                    }),
+                    None => unresolved.push((*item, reference)),
+                }
+            }
+        }
+        let in_graph: HashSet<NodeId> = edges
+            .iter()
+            .flat_map(|edge| [edge.item, edge.blocker])
+            .collect();
+        DependencyGraph {
+            nodes: order
+                .into_iter()
+                .map(|(node, _)| node)
+                .filter(|node| in_graph.contains(node))
+                .collect(),
+            edges,
+            unresolved,
+        }
+    }
+
+    // The ref nodes under the `blocked_by` and `depends` children of `node`.
+    fn dependency_refs(&self, node: NodeId) -> Vec<NodeId> {
+        let children = match &self.arena[node] {
+            Node::Node { children, .. } => children,
+            _ => return vec![],
+        };
+        children
+            .iter()
+            .filter_map(|child| match &self.arena[*child] {
+                Node::Node {
+                    content: Some(Content::Content(text)),
+                    children,
+                    ..
+                } if is_dependency_marker(text) => Some(children.clone()),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+
+    // Cycles and refs to nothing, for editors to show.
+    pub fn dependency_diagnostics(&self, root: NodeId) -> Vec<DependencyDiagnostic> {
+        let graph = self.dependency_graph(root);
+        let mut diagnostics: Vec<DependencyDiagnostic> = Vec::new();
+        for (_, reference) in &graph.unresolved {
+            diagnostics.push(DependencyDiagnostic {
+                node: *reference,
+                message: String::from("Blocked by an item that doesn't exist"),
+            });
+        }
+        for cycle in graph.cycles() {
+            let message = format!("Dependency cycle: {}", self.describe_cycle(&graph, &cycle));
+            // Point at the refs that close the cycle.
+            for edge in &graph.edges {
+                if cycle.contains(&edge.item) && cycle.contains(&edge.blocker) {
+                    diagnostics.push(DependencyDiagnostic {
+                        node: edge.reference,
+                        message: message.clone(),
+                    });
+                }
+            }
+        }
+        diagnostics
+    }
+
+    // E.g. "a -> c -> b -> a", where each item is blocked by the next, for one of `cycles`.
+    pub fn describe_cycle(&self, graph: &DependencyGraph, cycle: &[NodeId]) -> String {
+        let path = graph.cycle_path(cycle);
+        let names: Vec<String> = path
+            .iter()
+            .chain(path.first())
+            .map(|node| self.label(*node))
+            .collect();
+        names.join(" -> ")
+    }
+
+    // Open items in the graph whose blockers are all done, in dependency order.
+    pub fn ready(&self, root: NodeId) -> Vec<NodeId> {
+        let graph = self.dependency_graph(root);
+        let cyclic: HashSet<NodeId> = graph.cycles().into_iter().flatten().collect();
+        let order = graph.order_ignoring_cycles();
+        order
+            .into_iter()
+            .filter(|node| !self.is_done(*node) && !cyclic.contains(node))
+            .filter(|node| {
+                graph
+                    .blockers(*node)
+                    .into_iter()
+                    .all(|blocker| self.is_done(blocker))
+            })
+            .collect()
+    }
+
+    // Like `make_prioritized_list`, with the items that are ready to work on.
+    pub fn make_ready_list(&mut self, root: NodeId) -> NodeId {
+        let children = self.ready(root);
+        self.arena.alloc(Node::Document {
+            attributes: self.attributes(root).cloned().unwrap_or_default(),
+            children,
+        })
+    }
+
+    // The graph in Graphviz DOT, with an arrow from each blocker to what it blocks.
+    pub fn dependencies_to_dot(&self, graph: &DependencyGraph) -> String {
+        let mut out = String::from("digraph dependencies {\n");
+        for node in &graph.nodes {
+            let label = self.label(*node).replace('\\', "\\\\").replace('"', "\\\"");
+            out.push_str(&format!("    {} [label=\"{}\"];\n", graph_id(*node), label));
+        }
+        for edge in &graph.edges {
+            out.push_str(&format!(
+                "    {} -> {};\n",
+                graph_id(edge.blocker),
+                graph_id(edge.item)
+            ));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    // The graph as a Mermaid flowchart, arrows as in `dependencies_to_dot`.
+    pub fn dependencies_to_mermaid(&self, graph: &DependencyGraph) -> String {
+        let mut out = String::from("flowchart TD\n");
+        for node in &graph.nodes {
+            let label = self.label(*node).replace('"', "#quot;");
+            out.push_str(&format!("    {}[\"{}\"]\n", graph_id(*node), label));
+        }
+        for edge in &graph.edges {
+            out.push_str(&format!(
+                "    {} --> {}\n",
+                graph_id(edge.blocker),
+                graph_id(edge.item)
+            ));
+        }
+        out
+    }
+}
+
+fn graph_id(node: NodeId) -> String {
+    format!("n{}", node.index())
+}
+
+impl DependencyGraph {
+    pub fn blockers(&self, node: NodeId) -> Vec<NodeId> {
+        self.edges
+            .iter()
+            .filter(|edge| edge.item == node)
+            .map(|edge| edge.blocker)
+            .collect()
+    }
+
+    // Strongly connected components with more than one item, or an item blocked by itself, each
+    // in document order.
+    pub fn cycles(&self) -> Vec<Vec<NodeId>> {
+        let mut tarjan = Tarjan {
+            graph: self,
+            index: HashMap::new(),
+            low: HashMap::new(),
+            stack: Vec::new(),
+            on_stack: HashSet::new(),
+            components: Vec::new(),
+        };
+        for node in &self.nodes {
+            if !tarjan.index.contains_key(node) {
+                tarjan.visit(*node);
+            }
+        }
+        let position: HashMap<NodeId, usize> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (*node, i))
+            .collect();
+        let mut cycles: Vec<Vec<NodeId>> = tarjan
+            .components
+            .into_iter()
+            .filter(|component| {
+                component.len() > 1 || self.blockers(component[0]).contains(&component[0])
+            })
+            .map(|mut component| {
+                component.sort_by_key(|node| position[node]);
+                component
+            })
+            .collect();
+        cycles.sort_by_key(|cycle| position[&cycle[0]]);
+        cycles
+    }
+
+    // A path of edges through the items of `cycle`, one of `cycles`, from its first item back to
+    // it: each item is blocked by the next, and the last by the first. A cycle with more than two
+    // items can have more than one such path, and this is a shortest one, so it needn't include
+    // all of them.
+    pub fn cycle_path(&self, cycle: &[NodeId]) -> Vec<NodeId> {
+        let start = cycle[0];
+        let members: HashSet<NodeId> = cycle.iter().copied().collect();
+        // Breadth-first from `start`, with the item each item was reached from.
+        let mut previous: HashMap<NodeId, NodeId> = HashMap::new();
+        let mut queue: VecDeque<NodeId> = VecDeque::new();
+        queue.push_back(start);
+        while let Some(node) = queue.pop_front() {
+            for blocker in self.blockers(node) {
+                if blocker == start {
+                    let mut path = vec![node];
+                    while let Some(before) = previous.get(&path[path.len() - 1]) {
+                        path.push(*before);
+                    }
+                    path.reverse();
+                    return path;
+                }
+                if members.contains(&blocker) && !previous.contains_key(&blocker) {
+                    previous.insert(blocker, node);
+                    queue.push_back(blocker);
+                }
+            }
+        }
+        cycle.to_vec()
+    }
+
+    // Every item after the items it is blocked by, or the cycles that prevent that.
+    pub fn topological_order(&self) -> Result<Vec<NodeId>, Vec<Vec<NodeId>>> {
+        let cycles = self.cycles();
+        if !cycles.is_empty() {
+            return Err(cycles);
+        }
+        Ok(self.order_ignoring_cycles())
+    }
+
+    // Kahn's algorithm, taking items in document order when there's a choice. Items on or after
+    // a cycle are left out.
+    fn order_ignoring_cycles(&self) -> Vec<NodeId> {
+        let mut waiting: HashMap<NodeId, usize> =
+            self.nodes.iter().map(|node| (*node, 0)).collect();
+        for edge in &self.edges {
+            *waiting.get_mut(&edge.item).unwrap() += 1;
+        }
+        let mut order: Vec<NodeId> = Vec::new();
+        let mut done: HashSet<NodeId> = HashSet::new();
+        loop {
+            let next = self
+                .nodes
+                .iter()
+                .find(|node| !done.contains(*node) && waiting[*node] == 0);
+            let next = match next {
+                Some(next) => *next,
+                None => break,
+            };
+            done.insert(next);
+            order.push(next);
+            for edge in &self.edges {
+                if edge.blocker == next {
+                    *waiting.get_mut(&edge.item).unwrap() -= 1;
+                }
+            }
+        }
+        order
+    }
+}
+
+struct Tarjan<'a> {
+    graph: &'a DependencyGraph,
+    index: HashMap<NodeId, usize>,
+    low: HashMap<NodeId, usize>,
+    stack: Vec<NodeId>,
+    on_stack: HashSet<NodeId>,
+    components: Vec<Vec<NodeId>>,
+}
+
+impl Tarjan<'_> {
+    fn visit(&mut self, node: NodeId) {
+        let index = self.index.len();
+        self.index.insert(node, index);
+        self.low.insert(node, index);
+        self.stack.push(node);
+        self.on_stack.insert(node);
+        for blocker in self.graph.blockers(node) {
+            if !self.index.contains_key(&blocker) {
+                self.visit(blocker);
+                let low = self.low[&node].min(self.low[&blocker]);
+                self.low.insert(node, low);
+            } else if self.on_stack.contains(&blocker) {
+                let low = self.low[&node].min(self.index[&blocker]);
+                self.low.insert(node, low);
+            }
+        }
+        if self.low[&node] == self.index[&node] {
+            let mut component: Vec<NodeId> = Vec::new();
+            while let Some(member) = self.stack.pop() {
+                self.on_stack.remove(&member);
+                component.push(member);
+                if member == node {
+                    break;
+                }
+            }
+            self.components.push(component);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn load(text: &str) -> (Context, NodeId) {
+        let parser = Parser::new(String::from(text), tree_sitter_puddlejumper::language());
+        let mut ctx = Context::new();
+        let root = ctx.load_document(&parser).unwrap();
+        (ctx, root)
+    }
+
+    fn labels(ctx: &Context, nodes: &[NodeId]) -> Vec<String> {
+        nodes.iter().map(|node| ctx.label(*node)).collect()
+    }
+
+    #[test]
+    fn test_order_and_ready() {
+        let (ctx, root) = load(
+            "\
+@deploy: [ ] Deploy
+    blocked_by
+        @api
+        @db
+@api: [x] Build the API
+@db: [ ] Migrate the database
+    depends
+        @schema
+@schema: [x] Schema
+",
+        );
+        let graph = ctx.dependency_graph(root);
+        assert_eq!(graph.edges.len(), 3);
+        assert_eq!(
+            labels(&ctx, &graph.topological_order().unwrap()),
+            vec![
+                "[x] Build the API",
+                "[x] Schema",
+                "[ ] Migrate the database",
+                "[ ] Deploy"
+            ]
+        );
+        assert_eq!(
+            labels(&ctx, &ctx.ready(root)),
+            vec!["[ ] Migrate the database"]
+        );
+        assert_eq!(ctx.dependency_diagnostics(root), vec![]);
+
+        let dot = ctx.dependencies_to_dot(&graph);
+        assert!(dot.contains("[label=\"[ ] Deploy\"]"));
+        assert_eq!(dot.matches(" -> ").count(), 3);
+        let mermaid = ctx.dependencies_to_mermaid(&graph);
+        assert!(mermaid.starts_with("flowchart TD\n"));
+        assert_eq!(mermaid.matches(" --> ").count(), 3);
+    }
+
+    #[test]
+    fn test_cycles() {
+        let (ctx, root) = load(
+            "\
+@a: a
+    blocked_by
+        @b
+@b: b
+    blocked_by
+        @a
+@c: c
+    blocked_by
+        @c
+        @missing
+",
+        );
+        let graph = ctx.dependency_graph(root);
+        let cycles: Vec<Vec<String>> = graph
+            .cycles()
+            .iter()
+            .map(|cycle| labels(&ctx, cycle))
+            .collect();
+        assert_eq!(cycles, vec![vec!["a", "b"], vec!["c"]]);
+        assert!(graph.topological_order().is_err());
+        assert_eq!(ctx.ready(root), vec![]);
+
+        let messages: Vec<String> = ctx
+            .dependency_diagnostics(root)
+            .into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Blocked by an item that doesn't exist",
+                "Dependency cycle: a -> b -> a",
+                "Dependency cycle: a -> b -> a",
+                "Dependency cycle: c -> c",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cycle_path() {
+        let (ctx, root) = load(
+            "\
+@a: a
+    blocked_by
+        @c
+@b: b
+    blocked_by
+        @a
+@c: c
+    blocked_by
+        @b
+",
+        );
+        let graph = ctx.dependency_graph(root);
+        let cycles = graph.cycles();
+        assert_eq!(labels(&ctx, &cycles[0]), vec!["a", "b", "c"]);
+        assert_eq!(
+            labels(&ctx, &graph.cycle_path(&cycles[0])),
+            vec!["a", "c", "b"]
+        );
+        assert_eq!(ctx.describe_cycle(&graph, &cycles[0]), "a -> c -> b -> a");
+    }
+
+    #[test]
+    fn test_qualified_refs() {
+        let mut ctx = Context::new();
+        let mut documents: Vec<NodeId> = Vec::new();
+        for (name, text) in [
+            (
+                "a",
+                "\
+@x: [ ] a.x
+    blocked_by
+        @b.x
+        @y
+        @c.x
+@y: [ ] a.y
+",
+            ),
+            ("b", "@x: [ ] b.x\n@y: [ ] b.y\n"),
+        ] {
+            let parser = Parser::new(String::from(text), tree_sitter_puddlejumper::language());
+            let document = ctx.load_document(&parser).unwrap();
+            ctx.document_names.insert(document, String::from(name));
+            documents.push(document);
+        }
+        let root = ctx.combine_documents(documents);
+        let graph = ctx.dependency_graph(root);
+        let edges: Vec<(String, String)> = graph
+            .edges
+            .iter()
+            .map(|edge| (ctx.label(edge.item), ctx.label(edge.blocker)))
+            .collect();
+        assert_eq!(
+            edges,
+            vec![
+                (String::from("[ ] a.x"), String::from("[ ] b.x")),
+                (String::from("[ ] a.x"), String::from("[ ] a.y")),
+            ]
+        );
+        // `@c.x` names a document that isn't there.
+        assert_eq!(graph.unresolved.len(), 1);
+    }
+
+    #[test]
+    fn test_cycle_path_without_source() {
+        let mut ctx = Context::new();
+        let [a, b, c, d] = [0, 1, 2, 3].map(|_| {
+            ctx.arena.alloc(Node::Node {
+                binding: None,
+                content: None,
+                children: vec![],
+            })
+        });
+        let edge = |item, blocker| Dependency {
+            item,
+            blocker,
+            reference: item,
+        };
+        // a -> c -> b -> a, and a shortcut a -> d -> a.
+        let graph = DependencyGraph {
+            nodes: vec![a, b, c, d],
+            edges: vec![edge(a, c), edge(c, b), edge(b, a), edge(a, d), edge(d, a)],
+            unresolved: vec![],
+        };
+        assert_eq!(graph.cycles(), vec![vec![a, b, c, d]]);
+        assert_eq!(graph.cycle_path(&[a, b, c, d]), vec![a, d]);
+        assert_eq!(graph.cycle_path(&[b, a, c]), vec![b, a, c]);
+    }
+}
@@ -0,0 +1,629 @@
+use std::fmt;
+
+use super::printer::DEFAULT_INDENT_WIDTH;
+use super::*;
+use crate::text_diff::TextEdit;
+
+// Structural editing commands for a projectional editor. Each command takes the source `text` the
+// context was loaded from and returns the text edits that carry it out, which `Parser::edit`
+// applies incrementally. The context itself is left as is; reload it from the parser afterwards.
+
+#[derive(Debug, PartialEq)]
+pub enum EditError {
+    // The node wasn't loaded from source, e.g. it was built by a projection.
+    NotLoaded,
+    NoParent,
+    NoPreviousSibling,
+    NoNextSibling,
+    InvalidBinding(String),
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EditError::NotLoaded => write!(f, "Node has no source"),
+            EditError::NoParent => write!(f, "Node has no parent to move out of"),
+            EditError::NoPreviousSibling => write!(f, "Node has no previous sibling"),
+            EditError::NoNextSibling => write!(f, "Node has no next sibling"),
+            EditError::InvalidBinding(name) => write!(f, "Invalid binding name {:?}", name),
+            EditError::Unsupported(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for EditError {}
+
+fn line_start(text: &str, byte: usize) -> usize {
+    text[..byte].rfind('\n').map_or(0, |newline| newline + 1)
+}
+
+// The end of the line containing `byte`, after its newline.
+fn line_end(text: &str, byte: usize) -> usize {
+    text[byte..]
+        .find('\n')
+        .map_or(text.len(), |newline| byte + newline + 1)
+}
+
+fn indentation(text: &str, line_start: usize) -> usize {
+    text[line_start..].len() - text[line_start..].trim_start_matches(' ').len()
+}
+
+// Edits that shift every non-blank line of `lines` right by `width` spaces.
+fn indent_lines(text: &str, lines: Range<usize>, width: usize) -> Vec<TextEdit> {
+    line_starts(text, lines)
+        .filter(|start| !text[*start..line_end(text, *start)].trim().is_empty())
+        .map(|start| TextEdit {
+            range: start..start,
+            text: " ".repeat(width),
+        })
+        .collect()
+}
+
+// Edits that shift every line of `lines` left by up to `width` spaces.
+fn outdent_lines(text: &str, lines: Range<usize>, width: usize) -> Vec<TextEdit> {
+    line_starts(text, lines)
+        .map(|start| start..start + indentation(text, start).min(width))
+        .filter(|range| !range.is_empty())
+        .map(|range| TextEdit {
+            range,
+            text: String::new(),
+        })
+        .collect()
+}
+
+fn line_starts(text: &str, lines: Range<usize>) -> impl Iterator<Item = usize> + '_ {
+    let Range { start, end } = lines;
+    std::iter::once(start).chain(
+        text[start..end]
+            .match_indices('\n')
+            .map(move |(newline, _)| start + newline + 1)
+            .filter(move |line| *line < end),
+    )
+}
+
+// `lines` shifted right by `width` spaces, or left if `width` is negative.
+fn reindent(lines: &str, width: isize) -> String {
+    lines
+        .split_inclusive('\n')
+        .map(|line| {
+            if width >= 0 {
+                if line.trim().is_empty() {
+                    line.to_string()
+                } else {
+                    format!("{}{}", " ".repeat(width as usize), line)
+                }
+            } else {
+                let spaces = (line.len() - line.trim_start_matches(' ').len()).min(-width as usize);
+                line[spaces..].to_string()
+            }
+        })
+        .collect()
+}
+
+// Edits that remove the whole lines `lines`. A last line without a newline takes the newline
+// before it instead.
+fn delete_lines(text: &str, lines: Range<usize>) -> TextEdit {
+    let range = if lines.end == text.len() && !text.ends_with('\n') && lines.start > 0 {
+        lines.start - 1..lines.end
+    } else {
+        lines
+    };
+    TextEdit {
+        range,
+        text: String::new(),
+    }
+}
+
+// Edits that move the whole lines `lines` to the line boundary `to`, as `moved`.
+fn move_lines(text: &str, lines: Range<usize>, to: usize, moved: &str) -> Vec<TextEdit> {
+    let moved = moved.trim_end_matches('\n');
+    let insert = TextEdit {
+        range: to..to,
+        text: if to == text.len() && !text.ends_with('\n') {
+            format!("\n{}", moved)
+        } else {
+            format!("{}\n", moved)
+        },
+    };
+    let delete = delete_lines(text, lines);
+    if to <= delete.range.start {
+        vec![insert, delete]
+    } else {
+        vec![delete, insert]
+    }
+}
+
+impl Context {
+    // The whole lines `node` spans in `text`, with its newline.
+    pub fn source_lines(&self, text: &str, node: NodeId) -> Result<Range<usize>, EditError> {
+        let range = self.source(node).ok_or(EditError::NotLoaded)?.range;
+        let start = line_start(text, range.start_byte);
+        // Ranges can run into the indentation of the line after, so end at the last
+        // non-whitespace character.
+        let end = start + text[start..range.end_byte].trim_end().len();
+        Ok(start..line_end(text, end))
+    }
+
+    // The node whose children include `node` in the loaded tree, and where. Nodes that weren't
+    // loaded from source, and document roots, have none.
+    pub fn parent(&self, node: NodeId) -> Option<(NodeId, usize)> {
+        self.parents.get(&node).copied()
+    }
+
+    fn siblings(&self, node: NodeId) -> Result<(NodeId, &[NodeId], usize), EditError> {
+        let (parent, index) = self.parent(node).ok_or(EditError::NoParent)?;
+        match &self.arena[parent] {
+            Node::Document { children, .. }
+            | Node::Node { children, .. }
+            | Node::Block { children, .. } => Ok((parent, children, index)),
+            Node::Error { .. } => Err(EditError::NoParent),
+        }
+    }
+
+    // The document's `indent_width`, or the printer's default.
+    fn indent_width(&self, node: NodeId) -> usize {
+        let mut root = node;
+        while let Some((parent, _)) = self.parent(root) {
+            root = parent;
+        }
+        self.document_settings(root)
+            .indent_width
+            .unwrap_or(DEFAULT_INDENT_WIDTH)
+    }
+
+    fn is_block(&self, node: NodeId) -> bool {
+        matches!(self.arena[node], Node::Block { .. })
+    }
+
+    // Makes `node` the last child of its previous sibling.
+    pub fn indent(&self, text: &str, node: NodeId) -> Result<Vec<TextEdit>, EditError> {
+        let (_, siblings, index) = self.siblings(node)?;
+        let previous = match index {
+            0 => return Err(EditError::NoPreviousSibling),
+            _ => siblings[index - 1],
+        };
+        let last_child = match &self.arena[previous] {
+            Node::Node { children, .. } => children.last(),
+            _ => {
+                return Err(EditError::Unsupported(
+                    "Only nodes can have indented children",
+                ))
+            }
+        };
+        if last_child.is_some_and(|child| self.is_block(*child)) && !self.is_block(node) {
+            return Err(EditError::Unsupported(
+                "The previous sibling's children end with a block",
+            ));
+        }
+        let lines = self.source_lines(text, node)?;
+        Ok(indent_lines(text, lines, self.indent_width(node)))
+    }
+
+    // Makes `node` the next sibling of its parent. Later siblings stay with the parent.
+    pub fn outdent(&self, text: &str, node: NodeId) -> Result<Vec<TextEdit>, EditError> {
+        let (parent, siblings, index) = self.siblings(node)?;
+        if !matches!(self.arena[parent], Node::Node { .. }) {
+            return Err(EditError::NoParent);
+        }
+        self.parent(parent).ok_or(EditError::NoParent)?;
+        let lines = self.source_lines(text, node)?;
+        let width = self.indent_width(node);
+        if index == siblings.len() - 1 {
+            return Ok(outdent_lines(text, lines, width));
+        }
+        let to = self.source_lines(text, parent)?.end;
+        let moved = reindent(&text[lines.clone()], -(width as isize));
+        Ok(move_lines(text, lines, to, &moved))
+    }
+
+    // Swaps `node` with its previous sibling.
+    pub fn move_up(&self, text: &str, node: NodeId) -> Result<Vec<TextEdit>, EditError> {
+        let (_, siblings, index) = self.siblings(node)?;
+        let previous = match index {
+            0 => return Err(EditError::NoPreviousSibling),
+            _ => siblings[index - 1],
+        };
+        self.check_swap(node, previous)?;
+        let lines = self.source_lines(text, node)?;
+        let to = self.source_lines(text, previous)?.start;
+        Ok(move_lines(text, lines.clone(), to, &text[lines]))
+    }
+
+    // Swaps `node` with its next sibling.
+    pub fn move_down(&self, text: &str, node: NodeId) -> Result<Vec<TextEdit>, EditError> {
+        let (_, siblings, index) = self.siblings(node)?;
+        let next = *siblings.get(index + 1).ok_or(EditError::NoNextSibling)?;
+        self.check_swap(node, next)?;
+        let lines = self.source_lines(text, node)?;
+        let to = self.source_lines(text, next)?.end;
+        Ok(move_lines(text, lines.clone(), to, &text[lines]))
+    }
+
+    // Blocks nest by level and always come after the nodes next to them, so only nodes, or
+    // blocks of the same level, can trade places.
+    fn check_swap(&self, node: NodeId, other: NodeId) -> Result<(), EditError> {
+        match (&self.arena[node], &self.arena[other]) {
+            (Node::Block { level: a, .. }, Node::Block { level: b, .. }) if a == b => Ok(()),
+            (Node::Block { .. }, _) | (_, Node::Block { .. }) => Err(EditError::Unsupported(
+                "Only nodes, or blocks of the same level, can swap places",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // Moves `node` into a new block with the given header. Blocks come after the nodes next to
+    // them, so the block goes after the last of `node`'s siblings that isn't a block, at a level
+    // that doesn't take in the blocks after it.
+    pub fn wrap_in_block(
+        &self,
+        text: &str,
+        node: NodeId,
+        header: &str,
+    ) -> Result<Vec<TextEdit>, EditError> {
+        let header = header.trim();
+        if header.is_empty() || header.contains('\n') {
+            return Err(EditError::Unsupported("Block headers are a single line"));
+        }
+        if !matches!(self.arena[node], Node::Node { .. }) {
+            return Err(EditError::Unsupported(
+                "Only nodes can be wrapped in a block",
+            ));
+        }
+        let (parent, siblings, index) = self.siblings(node)?;
+        let parent_level = match &self.arena[parent] {
+            Node::Block { level, .. } => *level,
+            _ => 0,
+        };
+        let level = siblings
+            .iter()
+            .filter_map(|sibling| match &self.arena[*sibling] {
+                Node::Block { level, .. } => Some(*level),
+                _ => None,
+            })
+            .fold(parent_level + 1, usize::max);
+        if level > 6 {
+            return Err(EditError::Unsupported("Blocks nest at most 6 levels deep"));
+        }
+        let lines = self.source_lines(text, node)?;
+        let header_line = format!(
+            "{}{} {}\n",
+            " ".repeat(indentation(text, lines.start)),
+            "#".repeat(level),
+            header
+        );
+        let last_node = siblings
+            .iter()
+            .rposition(|sibling| !self.is_block(*sibling))
+            .unwrap_or(index);
+        if last_node == index {
+            return Ok(vec![TextEdit {
+                range: lines.start..lines.start,
+                text: header_line,
+            }]);
+        }
+        let to = self.source_lines(text, siblings[last_node])?.end;
+        let moved = format!("{}{}", header_line, &text[lines.clone()]);
+        Ok(move_lines(text, lines, to, &moved))
+    }
+
+    // Splits the content of `node` at `byte`. The rest becomes the next sibling, and takes the
+    // node's children with it, the way pressing enter mid-line does in an outliner.
+    pub fn split(&self, text: &str, node: NodeId, byte: usize) -> Result<Vec<TextEdit>, EditError> {
+        let content = match &self.arena[node] {
+            Node::Node {
+                content: Some(Content::Content(_)),
+                ..
+            } => self.source(node).and_then(|source| source.content),
+            _ => return Err(EditError::Unsupported("Only text content can be split")),
+        }
+        .ok_or(EditError::NotLoaded)?;
+        if byte <= content.start_byte || byte >= content.end_byte || !text.is_char_boundary(byte) {
+            return Err(EditError::Unsupported("Split inside the content"));
+        }
+        let rest = text[byte..content.end_byte].trim_start();
+        if rest.trim_end().is_empty() || rest.starts_with(['@', '#']) {
+            return Err(EditError::Unsupported(
+                "The rest would not be text content on its own",
+            ));
+        }
+        let indent = indentation(
+            text,
+            line_start(text, self.source(node).unwrap().range.start_byte),
+        );
+        Ok(vec![TextEdit {
+            range: byte..content.end_byte - rest.len(),
+            text: format!("\n{}", " ".repeat(indent)),
+        }])
+    }
+
+    // Appends the content of the next sibling to `node`. The sibling's children follow the
+    // node's own.
+    pub fn join(&self, text: &str, node: NodeId) -> Result<Vec<TextEdit>, EditError> {
+        let (_, siblings, index) = self.siblings(node)?;
+        let next = *siblings.get(index + 1).ok_or(EditError::NoNextSibling)?;
+        match &self.arena[node] {
+            Node::Node {
+                content: Some(Content::Content(_)),
+                ..
+            } => {}
+            _ => {
+                return Err(EditError::Unsupported(
+                    "Only text content can be joined onto",
+                ))
+            }
+        }
+        let next_text = match &self.arena[next] {
+            Node::Node {
+                binding: Some(_), ..
+            } => {
+                return Err(EditError::Unsupported(
+                    "Joining would drop the next sibling's binding",
+                ))
+            }
+            Node::Node {
+                content: Some(Content::Content(text)),
+                ..
+            }
+            | Node::Node {
+                content: Some(Content::Ref(text)),
+                ..
+            } => text.trim(),
+            _ => return Err(EditError::Unsupported("The next sibling has no content")),
+        };
+        let content = self
+            .source(node)
+            .and_then(|source| source.content)
+            .ok_or(EditError::NotLoaded)?;
+        let next_content = self
+            .source(next)
+            .and_then(|source| source.content)
+            .ok_or(EditError::NotLoaded)?;
+        let end = content.start_byte + text[content.byte_range()].trim_end().len();
+        let next_line =
+            line_start(text, next_content.start_byte)..line_end(text, next_content.end_byte);
+        Ok(vec![
+            TextEdit {
+                range: end..end,
+                text: format!(" {}", next_text),
+            },
+            delete_lines(text, next_line),
+        ])
+    }
+
+    // Removes `node` and everything under it.
+    pub fn delete(&self, text: &str, node: NodeId) -> Result<Vec<TextEdit>, EditError> {
+        self.parent(node).ok_or(EditError::NoParent)?;
+        let lines = self.source_lines(text, node)?;
+        Ok(vec![delete_lines(text, lines)])
+    }
+
+    // Sets or, with `None`, removes the binding of a node or block.
+    pub fn set_binding(
+        &self,
+        text: &str,
+        node: NodeId,
+        name: Option<&str>,
+    ) -> Result<Vec<TextEdit>, EditError> {
+        if let Some(name) = name {
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(EditError::InvalidBinding(name.to_string()));
+            }
+        }
+        let source = self.source(node).ok_or(EditError::NotLoaded)?;
+        let start = source.range.start_byte;
+        // The identifier, or for an anonymous `@:` binding the empty range after the `@`.
+        let identifier = match source.binding {
+            Some(binding) => Some(binding.byte_range()),
+            None if text[start..].starts_with("@:") => Some(start + 1..start + 1),
+            None => None,
+        };
+        let edit = |range: Range<usize>, text: String| Ok(vec![TextEdit { range, text }]);
+        match (&self.arena[node], identifier, name) {
+            (Node::Node { .. } | Node::Block { .. }, Some(identifier), Some(name)) => {
+                edit(identifier, name.to_string())
+            }
+            (Node::Node { .. } | Node::Block { .. }, None, None) => Ok(vec![]),
+            (Node::Node { .. }, Some(_), None) => match source.content {
+                Some(content) => edit(start..content.start_byte, String::new()),
+                None => Err(EditError::Unsupported(
+                    "The node has nothing but its binding",
+                )),
+            },
+            (Node::Node { .. }, None, Some(name)) => edit(start..start, format!("@{}: ", name)),
+            (Node::Block { header, .. }, Some(_), None) => {
+                let header = self.source(*header).ok_or(EditError::NotLoaded)?;
+                edit(
+                    line_start(text, start)..line_start(text, header.range.start_byte),
+                    String::new(),
+                )
+            }
+            (Node::Block { .. }, None, Some(name)) => {
+                let line = line_start(text, start);
+                let indent = " ".repeat(indentation(text, line));
+                edit(line..line, format!("{}@{}:\n", indent, name))
+            }
+            _ => Err(EditError::Unsupported(
+                "Only nodes and blocks have bindings",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::text_diff::apply_edits;
+
+    fn find(ctx: &Context, label: &str) -> NodeId {
+        ctx.arena
+            .iter()
+            .find(|(_, node)| match node {
+                Node::Node {
+                    content: Some(Content::Content(text)),
+                    ..
+                } => text.trim() == label,
+                _ => false,
+            })
+            .map(|(id, _)| id)
+            .unwrap()
+    }
+
+    // Runs `command` on the node labelled `label` and checks that the tree follows the text.
+    fn edit(
+        code: &str,
+        label: &str,
+        command: impl Fn(&Context, &str, NodeId) -> Result<Vec<TextEdit>, EditError>,
+    ) -> Result<String, EditError> {
+        let mut parser = Parser::new(String::from(code), tree_sitter_puddlejumper::language());
+        let mut ctx = Context::new();
+        ctx.load_document(&parser).unwrap();
+        let edits = command(&ctx, code, find(&ctx, label))?;
+        let update = parser.edit(&edits);
+        parser.apply_update(update);
+        let expected = apply_edits(code, &edits);
+        let reparsed = Parser::new(expected.clone(), tree_sitter_puddlejumper::language());
+        assert_eq!(
+            parser.tree.root_node().to_sexp(),
+            reparsed.tree.root_node().to_sexp()
+        );
+        Ok(expected)
+    }
+
+    #[test]
+    fn test_indent_outdent() {
+        assert_eq!(
+            edit("a\nb\n    c\n", "b", Context::indent),
+            Ok(String::from("a\n    b\n        c\n"))
+        );
+        assert_eq!(
+            edit("a\nb\n", "a", Context::indent),
+            Err(EditError::NoPreviousSibling)
+        );
+        assert_eq!(
+            edit("a\n    b\nc\n", "b", Context::outdent),
+            Ok(String::from("a\nb\nc\n"))
+        );
+        assert_eq!(
+            edit("a\n    b\n    c\nd", "b", Context::outdent),
+            Ok(String::from("a\n    c\nb\nd"))
+        );
+        assert_eq!(
+            edit("a\nb\n", "a", Context::outdent),
+            Err(EditError::NoParent)
+        );
+    }
+
+    #[test]
+    fn test_move() {
+        assert_eq!(
+            edit("a\nb\n    c", "b", Context::move_up),
+            Ok(String::from("b\n    c\na"))
+        );
+        assert_eq!(
+            edit("a\nb", "b", Context::move_up),
+            Ok(String::from("b\na"))
+        );
+        assert_eq!(
+            edit("a\nb", "a", Context::move_down),
+            Ok(String::from("b\na"))
+        );
+        assert_eq!(
+            edit("a\nb", "b", Context::move_down),
+            Err(EditError::NoNextSibling)
+        );
+    }
+
+    #[test]
+    fn test_wrap_in_block() {
+        assert_eq!(
+            edit("a\nb\n", "b", |ctx, text, node| ctx
+                .wrap_in_block(text, node, "Later")),
+            Ok(String::from("a\n# Later\nb\n"))
+        );
+        assert_eq!(
+            edit("a\nb\n## c\n", "a", |ctx, text, node| ctx
+                .wrap_in_block(text, node, "Later")),
+            Ok(String::from("b\n## Later\na\n## c\n"))
+        );
+    }
+
+    #[test]
+    fn test_split_join() {
+        assert_eq!(
+            edit("hello world\n    x\n", "hello world", |ctx, text, node| ctx
+                .split(text, node, 5)),
+            Ok(String::from("hello\nworld\n    x\n"))
+        );
+        assert_eq!(
+            edit("hello\nworld\n    x\n", "hello", Context::join),
+            Ok(String::from("hello world\n    x\n"))
+        );
+        assert_eq!(
+            edit("hello\n@w: world\n", "hello", Context::join),
+            Err(EditError::Unsupported(
+                "Joining would drop the next sibling's binding"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_delete() {
+        assert_eq!(
+            edit("a\n    b\nc\n", "a", Context::delete),
+            Ok(String::from("c\n"))
+        );
+        assert_eq!(edit("a\nc", "c", Context::delete), Ok(String::from("a")));
+    }
+
+    #[test]
+    fn test_set_binding() {
+        assert_eq!(
+            edit("a\n", "a", |ctx, text, node| ctx.set_binding(
+                text,
+                node,
+                Some("x")
+            )),
+            Ok(String::from("@x: a\n"))
+        );
+        assert_eq!(
+            edit("@x: a\n", "a", |ctx, text, node| ctx.set_binding(
+                text,
+                node,
+                Some("y")
+            )),
+            Ok(String::from("@y: a\n"))
+        );
+        assert_eq!(
+            edit("@x: a\n", "a", |ctx, text, node| ctx
+                .set_binding(text, node, None)),
+            Ok(String::from("a\n"))
+        );
+        assert_eq!(
+            edit("a\n", "a", |ctx, text, node| ctx.set_binding(
+                text,
+                node,
+                Some("a b")
+            )),
+            Err(EditError::InvalidBinding(String::from("a b")))
+        );
+    }
+
+    #[test]
+    fn test_line_edits() {
+        let text = "a\n    b\n\n    c\nd";
+        assert_eq!(
+            apply_edits(text, &indent_lines(text, 2..text.len() - 1, 2)),
+            "a\n      b\n\n      c\nd"
+        );
+        assert_eq!(
+            apply_edits(text, &outdent_lines(text, 2..text.len() - 1, 4)),
+            "a\nb\n\nc\nd"
+        );
+        assert_eq!(
+            apply_edits(text, &move_lines(text, 0..2, text.len(), "a")),
+            "    b\n\n    c\nd\na"
+        );
+    }
+}
@@ -0,0 +1,439 @@
+use std::io::Write;
+
+use super::attributes::DocumentSettings;
+use super::*;
+use crate::workspace::split_ref;
+
+// Exporters for readers who don't use puddlejumper. Each walks the tree from a document node.
+// `@name:` bindings become anchors and `@name` refs become links to them. Refs into other documents
+// link to those documents' exports, assumed to sit next to this one under their file stems.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Markdown,
+    Html,
+    Opml,
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Backslash-escapes what Markdown would read as markup, e.g. `*`, `[` or a leading `#` or `-`.
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::new();
+    for (i, c) in text.char_indices() {
+        let list_marker = i == 0 && matches!(c, '-' | '+');
+        let ordered_marker =
+            matches!(c, '.' | ')') && i > 0 && text[..i].bytes().all(|b| b.is_ascii_digit());
+        if list_marker || ordered_marker || "\\`*_[]<>#!|~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Where a ref links to: "#a" for "@a", and for a ref into another document of the workspace,
+// e.g. "@backend.api", the anchor in that document's export, "backend.md#api".
+fn ref_href(text: &str, extension: &str) -> String {
+    match split_ref(text) {
+        (Some(document), name) => format!("{}.{}#{}", document, extension, name),
+        (None, name) => format!("#{}", name),
+    }
+}
+
+impl Context {
+    pub fn export(
+        &self,
+        node: NodeId,
+        format: ExportFormat,
+        out: &mut dyn Write,
+    ) -> Result<(), std::io::Error> {
+        match format {
+            ExportFormat::Markdown => self.export_markdown(node, out),
+            ExportFormat::Html => self.export_html(node, out),
+            ExportFormat::Opml => self.export_opml(node, out),
+        }
+    }
+
+    // Blocks become headings and nodes nested lists.
+    pub fn export_markdown(&self, node: NodeId, out: &mut dyn Write) -> Result<(), std::io::Error> {
+        let settings = self.document_settings(node);
+        if let Some(title) = &settings.title {
+            writeln!(out, "# {}\n", escape_markdown(title))?;
+        }
+        // Headings shift down one level under a title.
+        let offset = if settings.title.is_some() { 1 } else { 0 };
+        self.export_markdown_rec(node, 0, offset, out)
+    }
+
+    fn export_markdown_rec(
+        &self,
+        node: NodeId,
+        depth: usize,
+        offset: usize,
+        out: &mut dyn Write,
+    ) -> Result<(), std::io::Error> {
+        match &self.arena[node] {
+            Node::Document { children, .. } => {
+                for child in children {
+                    self.export_markdown_rec(*child, 0, offset, out)?;
+                }
+            }
+            Node::Node {
+                binding,
+                content,
+                children,
+            } => {
+                write!(out, "{}- ", "  ".repeat(depth))?;
+                if let Some(binding) = binding {
+                    write!(out, "<a id=\"{}\"></a>", escape(binding))?;
+                }
+                match (content, binding) {
+                    (Some(Content::Content(text)), _) => {
+                        writeln!(out, "{}", escape_markdown(text))?
+                    }
+                    (Some(Content::Ref(text)), _) => writeln!(
+                        out,
+                        "[{}]({})",
+                        escape_markdown(text),
+                        ref_href(text, "md")
+                    )?,
+                    (None, Some(binding)) => {
+                        writeln!(out, "{}", escape_markdown(&format!("@{}", binding)))?
+                    }
+                    (None, None) => writeln!(out)?,
+                }
+                for child in children {
+                    self.export_markdown_rec(*child, depth + 1, offset, out)?;
+                }
+            }
+            Node::Block {
+                binding,
+                level,
+                header,
+                children,
+            } => {
+                write!(out, "\n{} ", "#".repeat((level + offset).min(6)))?;
+                if let Some(binding) = binding {
+                    write!(out, "<a id=\"{}\"></a>", escape(binding))?;
+                }
+                writeln!(out, "{}\n", escape_markdown(&self.plain_text(*header)))?;
+                for child in children {
+                    self.export_markdown_rec(*child, 0, offset, out)?;
+                }
+            }
+            Node::Error { text, .. } => {
+                writeln!(
+                    out,
+                    "{}- {}",
+                    "  ".repeat(depth),
+                    escape_markdown(text.trim())
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    // A standalone page, with blocks and nodes that have children as collapsible sections.
+    pub fn export_html(&self, node: NodeId, out: &mut dyn Write) -> Result<(), std::io::Error> {
+        let settings = self.document_settings(node);
+        let title = settings.title.clone().unwrap_or_default();
+        writeln!(out, "<!DOCTYPE html>")?;
+        writeln!(out, "<html>")?;
+        writeln!(out, "<head>")?;
+        writeln!(out, "<meta charset=\"utf-8\">")?;
+        writeln!(out, "<title>{}</title>", escape(&title))?;
+        writeln!(
+            out,
+            "<style>ul {{ list-style: none; }} summary {{ cursor: pointer; }} \
+             summary > h1, summary > h2, summary > h3, summary > h4, summary > h5, \
+             summary > h6 {{ display: inline; }}</style>"
+        )?;
+        writeln!(out, "</head>")?;
+        writeln!(out, "<body>")?;
+        if settings.title.is_some() {
+            writeln!(out, "<h1>{}</h1>", escape(&title))?;
+        }
+        self.export_html_rec(node, &settings, out)?;
+        writeln!(out, "</body>")?;
+        writeln!(out, "</html>")?;
+        Ok(())
+    }
+
+    fn export_html_rec(
+        &self,
+        node: NodeId,
+        settings: &DocumentSettings,
+        out: &mut dyn Write,
+    ) -> Result<(), std::io::Error> {
+        match &self.arena[node] {
+            Node::Document { children, .. } => self.export_html_list(children, settings, out)?,
+            Node::Node {
+                binding, children, ..
+            } => {
+                let id = match binding {
+                    Some(binding) => format!(" id=\"{}\"", escape(binding)),
+                    None => String::new(),
+                };
+                if children.is_empty() {
+                    writeln!(out, "<li{}>{}</li>", id, self.html_text(node))?;
+                } else {
+                    writeln!(out, "<li{}><details open>", id)?;
+                    writeln!(out, "<summary>{}</summary>", self.html_text(node))?;
+                    self.export_html_list(children, settings, out)?;
+                    writeln!(out, "</details></li>")?;
+                }
+            }
+            Node::Block {
+                binding,
+                level,
+                header,
+                children,
+            } => {
+                let id = match binding {
+                    Some(binding) => format!(" id=\"{}\"", escape(binding)),
+                    None => String::new(),
+                };
+                let heading = (level + settings.title.is_some() as usize).min(6);
+                writeln!(out, "<section{}><details open>", id)?;
+                writeln!(
+                    out,
+                    "<summary><h{level}>{}</h{level}></summary>",
+                    self.html_text(*header),
+                    level = heading
+                )?;
+                let (nodes, blocks): (Vec<NodeId>, Vec<NodeId>) = children
+                    .iter()
+                    .partition(|child| !matches!(self.arena[**child], Node::Block { .. }));
+                self.export_html_list(&nodes, settings, out)?;
+                for block in blocks {
+                    self.export_html_rec(block, settings, out)?;
+                }
+                writeln!(out, "</details></section>")?;
+            }
+            Node::Error { text, .. } => {
+                writeln!(out, "<li><pre>{}</pre></li>", escape(text.trim()))?;
+            }
+        }
+        Ok(())
+    }
+
+    // Nodes go in a list; blocks are sections of their own.
+    fn export_html_list(
+        &self,
+        children: &[NodeId],
+        settings: &DocumentSettings,
+        out: &mut dyn Write,
+    ) -> Result<(), std::io::Error> {
+        let mut in_list = false;
+        for child in children {
+            let is_block = matches!(self.arena[*child], Node::Block { .. });
+            if !is_block && !in_list {
+                writeln!(out, "<ul>")?;
+            } else if is_block && in_list {
+                writeln!(out, "</ul>")?;
+            }
+            in_list = !is_block;
+            self.export_html_rec(*child, settings, out)?;
+        }
+        if in_list {
+            writeln!(out, "</ul>")?;
+        }
+        Ok(())
+    }
+
+    fn html_text(&self, node: NodeId) -> String {
+        match &self.arena[node] {
+            Node::Node {
+                content: Some(Content::Ref(text)),
+                ..
+            } => format!(
+                "<a href=\"{}\">{}</a>",
+                escape(&ref_href(text, "html")),
+                escape(text)
+            ),
+            _ => escape(&self.plain_text(node)),
+        }
+    }
+
+    // The text a node shows as a single line, e.g. a block header.
+    fn plain_text(&self, node: NodeId) -> String {
+        match &self.arena[node] {
+            Node::Node {
+                content: Some(Content::Content(text)),
+                ..
+            }
+            | Node::Node {
+                content: Some(Content::Ref(text)),
+                ..
+            } => text.clone(),
+            Node::Node {
+                binding: Some(binding),
+                ..
+            } => format!("@{}", binding),
+            Node::Block { header, .. } => self.plain_text(*header),
+            Node::Error { text, .. } => text.trim().to_string(),
+            _ => String::new(),
+        }
+    }
+
+    // OPML 2.0, for outliner apps. Refs become `link` outlines pointing at their binding.
+    pub fn export_opml(&self, node: NodeId, out: &mut dyn Write) -> Result<(), std::io::Error> {
+        let settings = self.document_settings(node);
+        writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(out, "<opml version=\"2.0\">")?;
+        writeln!(out, "  <head>")?;
+        if let Some(title) = &settings.title {
+            writeln!(out, "    <title>{}</title>", escape(title))?;
+        }
+        if let Some(owner) = &settings.owner {
+            writeln!(out, "    <ownerName>{}</ownerName>", escape(owner))?;
+        }
+        writeln!(out, "  </head>")?;
+        writeln!(out, "  <body>")?;
+        self.export_opml_rec(node, 2, out)?;
+        writeln!(out, "  </body>")?;
+        writeln!(out, "</opml>")?;
+        Ok(())
+    }
+
+    fn export_opml_rec(
+        &self,
+        node: NodeId,
+        depth: usize,
+        out: &mut dyn Write,
+    ) -> Result<(), std::io::Error> {
+        let indent = "  ".repeat(depth);
+        let (attributes, children) = match &self.arena[node] {
+            Node::Document { children, .. } => {
+                for child in children {
+                    self.export_opml_rec(*child, depth, out)?;
+                }
+                return Ok(());
+            }
+            Node::Node {
+                binding,
+                content,
+                children,
+            } => {
+                let mut attributes = format!("text=\"{}\"", escape(&self.plain_text(node)));
+                if let Some(Content::Ref(text)) = content {
+                    attributes.push_str(&format!(
+                        " type=\"link\" url=\"{}\"",
+                        escape(&ref_href(text, "opml"))
+                    ));
+                }
+                if let Some(binding) = binding {
+                    attributes.push_str(&format!(" id=\"{}\"", escape(binding)));
+                }
+                (attributes, children)
+            }
+            Node::Block {
+                binding, children, ..
+            } => {
+                let mut attributes = format!("text=\"{}\"", escape(&self.plain_text(node)));
+                if let Some(binding) = binding {
+                    attributes.push_str(&format!(" id=\"{}\"", escape(binding)));
+                }
+                (attributes, children)
+            }
+            Node::Error { text, .. } => {
+                writeln!(out, "{}<outline text=\"{}\"/>", indent, escape(text.trim()))?;
+                return Ok(());
+            }
+        };
+        if children.is_empty() {
+            writeln!(out, "{}<outline {}/>", indent, attributes)?;
+        } else {
+            writeln!(out, "{}<outline {}>", indent, attributes)?;
+            for child in children {
+                self.export_opml_rec(*child, depth + 1, out)?;
+            }
+            writeln!(out, "{}</outline>", indent)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn export(code: &str, format: ExportFormat) -> String {
+        let parser = Parser::new(String::from(code), tree_sitter_puddlejumper::language());
+        let mut ctx = Context::new();
+        let document = ctx.load_document(&parser).unwrap();
+        let mut out: Vec<u8> = Vec::new();
+        ctx.export(document, format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_export_markdown() {
+        assert_eq!(
+            export("# Backend\n@api: API\n    see @api", ExportFormat::Markdown),
+            "\n# Backend\n\n- <a id=\"api\"></a>API\n  - see @api\n"
+        );
+        assert_eq!(
+            export("a\n    @b: b\n    @b", ExportFormat::Markdown),
+            "- a\n  - <a id=\"b\"></a>b\n  - [@b](#b)\n"
+        );
+        assert_eq!(
+            export("*not* bold\n    [x] done_now\n    - dash", ExportFormat::Markdown),
+            "- \\*not\\* bold\n  - \\[x\\] done\\_now\n  - \\- dash\n"
+        );
+    }
+
+    #[test]
+    fn test_export_qualified_refs() {
+        assert_eq!(
+            export("see\n    @backend.api", ExportFormat::Markdown),
+            "- see\n  - [@backend.api](backend.md#api)\n"
+        );
+        let html = export("@backend#api", ExportFormat::Html);
+        assert!(html.contains("<a href=\"backend.html#api\">@backend#api</a>"));
+        assert_eq!(ref_href("@backend.api", "opml"), "backend.opml#api");
+        assert_eq!(ref_href("@api", "opml"), "#api");
+    }
+
+    #[test]
+    fn test_escape_markdown() {
+        assert_eq!(escape_markdown("# not a heading"), "\\# not a heading");
+        assert_eq!(escape_markdown("1. first"), "1\\. first");
+        assert_eq!(escape_markdown("a-b 1.5"), "a-b 1.5");
+    }
+
+    #[test]
+    fn test_export_html() {
+        let html = export("a & b\n    @x: c\n@x", ExportFormat::Html);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<summary>a &amp; b</summary>"));
+        assert!(html.contains("<li id=\"x\">c</li>"));
+        assert!(html.contains("<a href=\"#x\">@x</a>"));
+    }
+
+    #[test]
+    fn test_export_opml() {
+        assert_eq!(
+            export("---\ntitle: Plan\n---\na\n    @b: b\n@b", ExportFormat::Opml),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <opml version=\"2.0\">\n  \
+             <head>\n    \
+             <title>Plan</title>\n  \
+             </head>\n  \
+             <body>\n    \
+             <outline text=\"a\">\n      \
+             <outline text=\"b\" id=\"b\"/>\n    \
+             </outline>\n    \
+             <outline text=\"@b\" type=\"link\" url=\"#b\"/>\n  \
+             </body>\n\
+             </opml>\n"
+        );
+    }
+}
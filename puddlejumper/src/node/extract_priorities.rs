@@ -1,18 +1,53 @@
+use super::attributes::DocumentSettings;
 use super::*;
 
 impl Context {
     pub fn extract_priorities(&self, node: NodeId) -> Vec<(NodeId, i32)> {
-        let mut priorities: Vec<(NodeId, i32)> = Vec::new();
-        self.extract_priorities_rec(node, &mut priorities);
-        return priorities;
+        let settings = self.document_settings(node);
+        return self
+            .extract_priorities_with(node, &settings)
+            .into_iter()
+            .map(|(node, _, priority)| (node, priority))
+            .collect();
     }
 
-    fn extract_priorities_rec(&self, node: NodeId, priorities: &mut Vec<(NodeId, i32)>) {
+    // Like `extract_priorities`, for any node under a document with `settings`. Also returns the
+    // node whose children decided each priority, i.e. the parent of the priority marker.
+    pub fn extract_priorities_with(
+        &self,
+        node: NodeId,
+        settings: &DocumentSettings,
+    ) -> Vec<(NodeId, NodeId, i32)> {
+        let mut priorities: Vec<(NodeId, NodeId, i32)> = Vec::new();
+        self.extract_priorities_rec(node, settings, &mut priorities);
+        priorities
+    }
+
+    fn extract_priorities_rec(
+        &self,
+        node: NodeId,
+        settings: &DocumentSettings,
+        priorities: &mut Vec<(NodeId, NodeId, i32)>,
+    ) {
         match &self.arena[node] {
             Node::Document { children, .. } => {
+                // A document combined into another one reads its markers with its own
+                // priorities, which map to the outer ones by name, see `combine_documents`.
+                let own = self.document_settings(node);
+                if own.priorities == settings.priorities {
+                    for child in children {
+                        self.extract_priorities_rec(*child, settings, priorities);
+                    }
+                    return;
+                }
+                let mut nested: Vec<(NodeId, NodeId, i32)> = Vec::new();
                 for child in children {
-                    self.extract_priorities_rec(*child, priorities);
+                    self.extract_priorities_rec(*child, &own, &mut nested);
                 }
+                priorities.extend(nested.into_iter().filter_map(|(node, decider, p)| {
+                    let p = settings.priority_of(&own.priorities[p as usize])?;
+                    Some((node, decider, p as i32))
+                }));
             }
             Node::Node { children, .. } => {
                 for child in children {
@@ -22,40 +57,36 @@ impl Context {
                             children,
                             ..
                         } => {
-                            match content.as_str() {
-                                "P0" => Some(0),
-                                "P1" => Some(1),
-                                "P2" => Some(2),
-                                "P3" => Some(3),
-                                "P4" => Some(4),
-                                _ => None,
-                            }
-                            .map(|p| {
+                            settings.priority_of(content).map(|p| {
+                                let p = p as i32;
                                 // Determine if priority should be applied to parent or child
                                 if children.len() > 0 {
                                     for c in children {
-                                        priorities.push((*c, p));
+                                        priorities.push((*c, node, p));
                                     }
                                 } else {
-                                    priorities.push((node, p));
+                                    priorities.push((node, node, p));
                                 }
                             });
                         }
                         _ => {}
                     }
-                    self.extract_priorities_rec(*child, priorities);
+                    self.extract_priorities_rec(*child, settings, priorities);
                 }
             }
             _ => return,
         }
     }
     pub fn make_prioritized_list(&mut self, node: NodeId) -> NodeId {
-        let mut priority_nodes: [Vec<NodeId>; 5] = [(); 5].map(|_| vec![]);
+        let settings = self.document_settings(node);
+        let mut priority_nodes: Vec<Vec<NodeId>> = vec![vec![]; settings.priorities.len()];
         let priorities: Vec<(NodeId, i32)> = self.extract_priorities(node);
         for (node, priority) in priorities {
             priority_nodes[priority as usize].push(node);
         }
         let list = Node::Document {
+            // Carry over the source document's settings, e.g. its indent width.
+            attributes: self.attributes(node).cloned().unwrap_or_default(),
             children: priority_nodes
                 .iter()
                 .enumerate()
@@ -65,7 +96,7 @@ impl Context {
                     }
                     Some(self.arena.alloc(Node::Node {
                         binding: None,
-                        content: Some(Content::Content(format!("P{}", index))),
+                        content: Some(Content::Content(settings.priorities[index].clone())),
                         children: nodes.to_vec(),
                     }))
                 })
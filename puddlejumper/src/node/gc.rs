@@ -0,0 +1,198 @@
+use std::collections::{HashMap, HashSet};
+
+use id_arena::Arena;
+
+use super::*;
+
+// `id_arena` never frees, and every `load_document` or projection allocates into the same arena,
+// so a context that lives for a whole editing session keeps growing. Compaction copies the nodes
+// still reachable from the given roots into a fresh arena and drops the rest, along with their
+// metadata, sources, stable ids and document names. That changes every `NodeId`, so it returns
+// the old-to-new mapping for callers that hold on to ids.
+
+pub type NodeIdMap = HashMap<NodeId, NodeId>;
+
+// `collect_garbage` compacts once the arena has grown to this many times the nodes that survived
+// the last compaction.
+pub const GC_GROWTH_FACTOR: usize = 2;
+// Smaller arenas aren't worth compacting.
+pub const MIN_GC_NODES: usize = 1024;
+
+impl Context {
+    // Every node reachable from `roots`, including the roots, in arena order.
+    pub fn reachable(&self, roots: &[NodeId]) -> Vec<NodeId> {
+        let mut seen: HashSet<NodeId> = HashSet::new();
+        let mut stack: Vec<NodeId> = roots.to_vec();
+        while let Some(node) = stack.pop() {
+            if !seen.insert(node) {
+                continue;
+            }
+            match &self.arena[node] {
+                Node::Document { children, .. } | Node::Node { children, .. } => {
+                    stack.extend(children);
+                }
+                Node::Block {
+                    header, children, ..
+                } => {
+                    stack.push(*header);
+                    stack.extend(children);
+                }
+                Node::Error { .. } => {}
+            }
+        }
+        let mut nodes: Vec<NodeId> = seen.into_iter().collect();
+        nodes.sort_by_key(|node| node.index());
+        nodes
+    }
+
+    // Drops every node not reachable from `roots`. Roots can be documents as well as projections
+    // of them, and nodes shared between them stay shared. Nodes keep their relative order.
+    pub fn compact(&mut self, roots: &[NodeId]) -> NodeIdMap {
+        let live: HashSet<NodeId> = self.reachable(roots).into_iter().collect();
+        let old = std::mem::replace(&mut self.arena, Arena::with_capacity(live.len()));
+        let mut map: NodeIdMap = HashMap::new();
+        for (id, node) in old {
+            if live.contains(&id) {
+                map.insert(id, self.arena.alloc(node));
+            }
+        }
+        // Everything under a live node is live, so all of these are mapped.
+        for (_, node) in self.arena.iter_mut() {
+            match node {
+                Node::Document { children, .. } | Node::Node { children, .. } => {
+                    for child in children.iter_mut() {
+                        *child = map[child];
+                    }
+                }
+                Node::Block {
+                    header, children, ..
+                } => {
+                    *header = map[header];
+                    for child in children.iter_mut() {
+                        *child = map[child];
+                    }
+                }
+                Node::Error { .. } => {}
+            }
+        }
+        self.metadata = std::mem::take(&mut self.metadata)
+            .into_iter()
+            .filter_map(|(id, metadata)| Some((*map.get(&id)?, metadata)))
+            .collect();
+        self.sources = std::mem::take(&mut self.sources)
+            .into_iter()
+            .filter_map(|(id, source)| Some((*map.get(&id)?, source)))
+            .collect();
+        self.stable_ids = std::mem::take(&mut self.stable_ids)
+            .into_iter()
+            .filter_map(|(id, stable_id)| Some((*map.get(&id)?, stable_id)))
+            .collect();
+        self.document_names = std::mem::take(&mut self.document_names)
+            .into_iter()
+            .filter_map(|(id, name)| Some((*map.get(&id)?, name)))
+            .collect();
+        self.parents = std::mem::take(&mut self.parents)
+            .into_iter()
+            .filter_map(|(id, (parent, index))| {
+                Some((*map.get(&id)?, (*map.get(&parent)?, index)))
+            })
+            .collect();
+        self.gc_threshold = (self.arena.len() * GC_GROWTH_FACTOR).max(MIN_GC_NODES);
+        map
+    }
+
+    // Compacts if the arena has grown enough since the last compaction, which keeps it within
+    // `GC_GROWTH_FACTOR` times what is reachable from `roots` at a constant cost per allocation.
+    // Call it wherever the caller knows all of its roots, e.g. after each update.
+    pub fn collect_garbage(&mut self, roots: &[NodeId]) -> Option<NodeIdMap> {
+        if self.arena.len() < self.gc_threshold {
+            return None;
+        }
+        Some(self.compact(roots))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    fn node(ctx: &mut Context, content: &str, children: Vec<NodeId>) -> NodeId {
+        let id = ctx.arena.alloc(Node::Node {
+            binding: None,
+            content: Some(Content::Content(String::from(content))),
+            children,
+        });
+        ctx.metadata.insert(
+            id,
+            NodeMetadata {
+                created_at: SystemTime::UNIX_EPOCH,
+            },
+        );
+        id
+    }
+
+    fn document(ctx: &mut Context, children: Vec<NodeId>) -> NodeId {
+        ctx.arena.alloc(Node::Document {
+            attributes: IndexMap::new(),
+            children,
+        })
+    }
+
+    fn print(ctx: &Context, node: NodeId) -> String {
+        let mut out: Vec<u8> = Vec::new();
+        ctx.print_source(node, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut ctx = Context::new();
+        // An old version of the document.
+        let a = node(&mut ctx, "a", vec![]);
+        document(&mut ctx, vec![a]);
+        // The current one, and a projection sharing its nodes.
+        let c = node(&mut ctx, "c", vec![]);
+        let p0 = node(&mut ctx, "P0", vec![]);
+        let b = node(&mut ctx, "b", vec![c, p0]);
+        let root = document(&mut ctx, vec![b]);
+        ctx.record_parent(b);
+        ctx.record_parent(root);
+        let bucket = node(&mut ctx, "P0", vec![b]);
+        let list = document(&mut ctx, vec![bucket]);
+        let before = (print(&ctx, root), print(&ctx, list));
+
+        let map = ctx.compact(&[root, list]);
+        assert_eq!(ctx.arena.len(), 6);
+        assert_eq!(ctx.metadata.len(), 4);
+        assert_eq!(map.get(&a), None);
+        let (root, list) = (map[&root], map[&list]);
+        assert_eq!((print(&ctx, root), print(&ctx, list)), before);
+        // Still shared.
+        match (&ctx.arena[root], &ctx.arena[map[&bucket]]) {
+            (Node::Document { children: x, .. }, Node::Node { children: y, .. }) => {
+                assert_eq!(x, y)
+            }
+            _ => panic!(),
+        }
+        // Parents are still those of the document, not of the projection.
+        assert_eq!(ctx.parent(map[&p0]), Some((map[&b], 1)));
+        assert_eq!(ctx.parent(map[&b]), Some((root, 0)));
+    }
+
+    #[test]
+    fn test_collect_garbage_stays_bounded() {
+        let mut ctx = Context::new();
+        let mut root = document(&mut ctx, vec![]);
+        for i in 0..10 * MIN_GC_NODES {
+            // Replace the document with a new version on every edit.
+            let child = node(&mut ctx, &i.to_string(), vec![]);
+            root = document(&mut ctx, vec![child]);
+            if let Some(map) = ctx.collect_garbage(&[root]) {
+                root = map[&root];
+            }
+            assert!(ctx.arena.len() <= MIN_GC_NODES);
+        }
+        assert_eq!(print(&ctx, root), format!("{}\n", 10 * MIN_GC_NODES - 1));
+    }
+}
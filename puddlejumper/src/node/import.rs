@@ -0,0 +1,615 @@
+use std::fmt;
+
+use super::*;
+
+// Importers for planning docs written elsewhere, the reverse of `export`. The result can be
+// written out as `.pj` source with `print_source`. Checkboxes (`[ ]`, `[x]`) are kept as part of
+// the content, and the anchors and links that `export` writes become bindings and refs again.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    Markdown,
+    Opml,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ImportError {
+    InvalidOpml(String),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::InvalidOpml(message) => write!(f, "Invalid OPML: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+fn is_identifier(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// `@name` if `text` is exactly a ref.
+fn as_ref(text: &str) -> Option<String> {
+    let name = text.strip_prefix('@')?;
+    is_identifier(name).then(|| text.to_string())
+}
+
+// Splits off the `<a id="name"></a>` anchor that `export_markdown` puts before bindings.
+fn split_anchor(text: &str) -> (Option<String>, &str) {
+    let anchor = text
+        .strip_prefix("<a id=\"")
+        .and_then(|rest| rest.split_once("\"></a>"))
+        .filter(|(name, _)| is_identifier(name));
+    match anchor {
+        Some((name, rest)) => (Some(name.to_string()), rest.trim_start()),
+        None => (None, text),
+    }
+}
+
+// Markdown links to `#name` that `export_markdown` writes for refs, e.g. `[@name](#name)`.
+fn link_ref(text: &str) -> Option<String> {
+    let (label, target) = text
+        .strip_prefix('[')?
+        .strip_suffix(')')?
+        .split_once("](#")?;
+    as_ref(label).filter(|_| label[1..] == *target)
+}
+
+fn content(text: &str) -> Option<Content> {
+    let text = text.trim();
+    if text.is_empty() {
+        None
+    } else if let Some(text) = as_ref(text).or_else(|| link_ref(text)) {
+        Some(Content::Ref(text))
+    } else {
+        Some(Content::Content(text.to_string()))
+    }
+}
+
+fn indent_of(line: &str) -> usize {
+    line.chars()
+        .take_while(|c| c.is_whitespace())
+        .map(|c| if c == '\t' { 4 } else { 1 })
+        .sum()
+}
+
+// The level and text of an ATX heading, e.g. `## text`.
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    let rest = &line[level..];
+    if !(1..=6).contains(&level) || !(rest.is_empty() || rest.starts_with([' ', '\t'])) {
+        return None;
+    }
+    Some((level, rest.trim().trim_end_matches('#').trim_end()))
+}
+
+// The text after a `-`, `*`, `+`, `1.` or `1)` list marker.
+fn list_item(line: &str) -> Option<&str> {
+    let digits = line.chars().take_while(|c| c.is_ascii_digit()).count();
+    let rest = match digits {
+        0 => line.strip_prefix(['-', '*', '+'])?,
+        _ => line[digits..].strip_prefix(['.', ')'])?,
+    };
+    if rest.is_empty() {
+        Some(rest)
+    } else if rest.starts_with([' ', '\t']) {
+        Some(rest.trim_start())
+    } else {
+        None
+    }
+}
+
+impl Context {
+    pub fn import(&mut self, text: &str, format: ImportFormat) -> Result<NodeId, ImportError> {
+        match format {
+            ImportFormat::Markdown => Ok(self.import_markdown(text)),
+            ImportFormat::Opml => self.import_opml(text),
+        }
+    }
+
+    fn push_child(&mut self, parent: NodeId, child: NodeId) {
+        match &mut self.arena[parent] {
+            Node::Document { children, .. }
+            | Node::Node { children, .. }
+            | Node::Block { children, .. } => children.push(child),
+            Node::Error { .. } => {}
+        }
+    }
+
+    fn alloc_item(&mut self, text: &str) -> NodeId {
+        let (binding, text) = split_anchor(text.trim());
+        self.arena.alloc(Node::Node {
+            binding,
+            content: content(text),
+            children: vec![],
+        })
+    }
+
+    // Headings become blocks and list items nodes, nested by indentation. Other text becomes
+    // nodes of the enclosing item or block.
+    pub fn import_markdown(&mut self, text: &str) -> NodeId {
+        let mut lines = text.lines().peekable();
+        let mut attributes: IndexMap<String, String> = IndexMap::new();
+        if lines.peek().map(|line| line.trim_end()) == Some("---") {
+            lines.next();
+            for line in lines.by_ref() {
+                if line.trim_end() == "---" {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    attributes.insert(name.trim().to_string(), value.trim().to_string());
+                }
+            }
+        }
+        let document = self.arena.alloc(Node::Document {
+            attributes,
+            children: vec![],
+        });
+
+        // Open blocks by level, and open list items by indentation within the innermost block.
+        let mut blocks: Vec<(usize, NodeId)> = Vec::new();
+        let mut items: Vec<(usize, NodeId)> = Vec::new();
+        for line in lines {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let indent = indent_of(line);
+            let trimmed = line.trim_start();
+            if let Some((level, text)) = heading(trimmed).filter(|_| indent < 4) {
+                let (binding, text) = split_anchor(text);
+                let header = self.alloc_item(text);
+                let block = self.arena.alloc(Node::Block {
+                    binding,
+                    level,
+                    header,
+                    children: vec![],
+                });
+                while blocks.last().is_some_and(|(open, _)| *open >= level) {
+                    blocks.pop();
+                }
+                let parent = blocks.last().map_or(document, |(_, block)| *block);
+                self.push_child(parent, block);
+                blocks.push((level, block));
+                items.clear();
+                continue;
+            }
+            while items.last().is_some_and(|(open, _)| *open >= indent) {
+                items.pop();
+            }
+            let parent = items
+                .last()
+                .or(blocks.last())
+                .map_or(document, |(_, parent)| *parent);
+            match list_item(trimmed) {
+                Some(text) => {
+                    let node = self.alloc_item(text);
+                    self.push_child(parent, node);
+                    items.push((indent, node));
+                }
+                None => {
+                    let node = self.alloc_item(trimmed);
+                    self.push_child(parent, node);
+                }
+            }
+        }
+        self.drop_empty_items(document);
+        document
+    }
+
+    // Outlines become nodes. `_complete="true"` or `_status="checked"` become a `[x]` checkbox.
+    pub fn import_opml(&mut self, text: &str) -> Result<NodeId, ImportError> {
+        let opml = parse_xml(text)?;
+        if opml.name != "opml" {
+            return Err(ImportError::InvalidOpml(format!(
+                "expected <opml>, found <{}>",
+                opml.name
+            )));
+        }
+        let mut attributes: IndexMap<String, String> = IndexMap::new();
+        if let Some(head) = opml.child("head") {
+            for (tag, name) in [("title", "title"), ("ownerName", "owner")] {
+                if let Some(element) = head.child(tag) {
+                    attributes.insert(String::from(name), element.text.trim().to_string());
+                }
+            }
+        }
+        let document = self.arena.alloc(Node::Document {
+            attributes,
+            children: vec![],
+        });
+        let body = opml
+            .child("body")
+            .ok_or_else(|| ImportError::InvalidOpml(String::from("missing <body>")))?;
+        for outline in body.children.iter().filter(|c| c.name == "outline") {
+            let node = self.import_outline(outline);
+            self.push_child(document, node);
+        }
+        self.drop_empty_items(document);
+        Ok(document)
+    }
+
+    fn import_outline(&mut self, outline: &Element) -> NodeId {
+        let text = outline.attribute("text").unwrap_or("").trim();
+        let checked = outline.attribute("_complete") == Some("true")
+            || outline.attribute("_status") == Some("checked");
+        let link = outline
+            .attribute("url")
+            .and_then(|url| url.strip_prefix('#'))
+            .filter(|name| is_identifier(name) && outline.attribute("type") == Some("link"));
+        let content = match link {
+            Some(name) => Some(Content::Ref(format!("@{}", name))),
+            None if checked => Some(Content::Content(
+                format!("[x] {}", text).trim_end().to_string(),
+            )),
+            None => content(text),
+        };
+        let binding = outline
+            .attribute("id")
+            .filter(|id| is_identifier(id))
+            .map(String::from);
+        let node = self.arena.alloc(Node::Node {
+            binding,
+            content,
+            children: vec![],
+        });
+        for child in outline.children.iter().filter(|c| c.name == "outline") {
+            let child = self.import_outline(child);
+            self.push_child(node, child);
+        }
+        node
+    }
+
+    // Drops items with no binding, content or children under `node`, e.g. an empty `-`, which
+    // `print_source` has nothing to write for. Empty items that hold children are kept.
+    fn drop_empty_items(&mut self, node: NodeId) {
+        let children = match &self.arena[node] {
+            Node::Document { children, .. }
+            | Node::Node { children, .. }
+            | Node::Block { children, .. } => children.clone(),
+            Node::Error { .. } => return,
+        };
+        for child in &children {
+            self.drop_empty_items(*child);
+        }
+        let kept: Vec<NodeId> = children
+            .into_iter()
+            .filter(|child| {
+                !matches!(
+                    &self.arena[*child],
+                    Node::Node {
+                        binding: None,
+                        content: None,
+                        children,
+                    } if children.is_empty()
+                )
+            })
+            .collect();
+        match &mut self.arena[node] {
+            Node::Document { children, .. }
+            | Node::Node { children, .. }
+            | Node::Block { children, .. } => *children = kept,
+            Node::Error { .. } => {}
+        }
+    }
+}
+
+// Just enough XML for OPML: elements, attributes, text and entities. Declarations, comments and
+// doctypes are skipped.
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Element>,
+    text: String,
+}
+
+impl Element {
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn invalid(message: &str) -> ImportError {
+    ImportError::InvalidOpml(message.to_string())
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest.find(';').map(|end| &rest[1..end]);
+        let decoded = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => {
+                let code = match entity.strip_prefix("#x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => entity.strip_prefix('#').and_then(|dec| dec.parse().ok()),
+                };
+                code.and_then(char::from_u32)
+            }
+        });
+        match (entity, decoded) {
+            (Some(entity), Some(decoded)) => {
+                result.push(decoded);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+fn parse_xml(text: &str) -> Result<Element, ImportError> {
+    // Open elements; the bottom one collects the root.
+    let mut stack: Vec<Element> = vec![Element::default()];
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        let text = unescape(&rest[..start]);
+        stack.last_mut().unwrap().text.push_str(&text);
+        rest = &rest[start..];
+        let skip = [("<?", "?>"), ("<!--", "-->"), ("<!", ">")]
+            .iter()
+            .find(|(open, _)| rest.starts_with(open));
+        if let Some((_, close)) = skip {
+            let end = rest
+                .find(close)
+                .ok_or_else(|| invalid("unterminated tag"))?;
+            rest = &rest[end + close.len()..];
+            continue;
+        }
+        let end = tag_end(rest).ok_or_else(|| invalid("unterminated tag"))?;
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+        if let Some(name) = tag.strip_prefix('/') {
+            let element = stack.pop().filter(|_| !stack.is_empty());
+            match element {
+                Some(element) if element.name == name.trim() => {
+                    stack.last_mut().unwrap().children.push(element)
+                }
+                _ => return Err(invalid(&format!("unexpected </{}>", name.trim()))),
+            }
+            continue;
+        }
+        let (tag, self_closing) = match tag.strip_suffix('/') {
+            Some(tag) => (tag, true),
+            None => (tag, false),
+        };
+        let element = parse_tag(tag)?;
+        if self_closing {
+            stack.last_mut().unwrap().children.push(element);
+        } else {
+            stack.push(element);
+        }
+    }
+    if stack.len() > 1 {
+        return Err(invalid(&format!(
+            "unclosed <{}>",
+            stack.last().unwrap().name
+        )));
+    }
+    stack
+        .pop()
+        .and_then(|root| root.children.into_iter().next())
+        .ok_or_else(|| invalid("no root element"))
+}
+
+// Index of the `>` closing the tag at the start of `text`, skipping quoted attribute values.
+fn tag_end(text: &str) -> Option<usize> {
+    let mut quote: Option<char> = None;
+    for (index, c) in text.char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '>') => return Some(index),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_tag(tag: &str) -> Result<Element, ImportError> {
+    let tag = tag.trim();
+    let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+    let mut element = Element {
+        name: tag[..name_end].to_string(),
+        ..Default::default()
+    };
+    let mut rest = tag[name_end..].trim_start();
+    while !rest.is_empty() {
+        let (name, value) = rest
+            .split_once('=')
+            .ok_or_else(|| invalid(&format!("bad attribute in <{}>", element.name)))?;
+        let value = value.trim_start();
+        let quote = value
+            .chars()
+            .next()
+            .filter(|c| *c == '"' || *c == '\'')
+            .ok_or_else(|| invalid(&format!("unquoted attribute in <{}>", element.name)))?;
+        let end = value[1..]
+            .find(quote)
+            .ok_or_else(|| invalid(&format!("unterminated attribute in <{}>", element.name)))?;
+        element
+            .attributes
+            .push((name.trim().to_string(), unescape(&value[1..end + 1])));
+        rest = value[end + 2..].trim_start();
+    }
+    Ok(element)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn import(text: &str, format: ImportFormat) -> String {
+        let mut ctx = Context::new();
+        let document = ctx.import(text, format).unwrap();
+        let mut out: Vec<u8> = Vec::new();
+        ctx.print_source(document, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_import_markdown() {
+        assert_eq!(
+            import(
+                "---\ntitle: Plan\n---\n# Backend\n\n- [ ] api\n  - [x] auth\n* db\n## Later\n1. cache\n",
+                ImportFormat::Markdown
+            ),
+            "---\ntitle: Plan\n---\n# Backend\n[ ] api\n    [x] auth\ndb\n## Later\ncache\n"
+        );
+        assert_eq!(
+            import(
+                "- <a id=\"api\"></a>API\n  - [@api](#api)\n",
+                ImportFormat::Markdown
+            ),
+            "@api: API\n    @api\n"
+        );
+    }
+
+    #[test]
+    fn test_import_opml() {
+        assert_eq!(
+            import(
+                "<?xml version=\"1.0\"?>\n<opml version=\"2.0\">\n  \
+                 <head><title>Plan &amp; more</title></head>\n  \
+                 <body>\n    \
+                 <outline text=\"a\">\n      \
+                 <outline text=\"b\" id=\"b\" _complete=\"true\"/>\n    \
+                 </outline>\n    \
+                 <outline text=\"@b\" type=\"link\" url=\"#b\"/>\n  \
+                 </body>\n</opml>\n",
+                ImportFormat::Opml
+            ),
+            "---\ntitle: Plan & more\n---\na\n    @b: [x] b\n@b\n"
+        );
+        let mut ctx = Context::new();
+        assert_eq!(
+            ctx.import_opml("<opml><body></opml>"),
+            Err(ImportError::InvalidOpml(String::from("unexpected </opml>")))
+        );
+    }
+
+    // The tree under `node`, without ids, to compare trees in different places of the arena.
+    fn shape(ctx: &Context, node: NodeId) -> String {
+        let children = |children: &[NodeId]| {
+            children
+                .iter()
+                .map(|child| shape(ctx, *child))
+                .collect::<Vec<String>>()
+        };
+        match &ctx.arena[node] {
+            Node::Document {
+                attributes,
+                children: c,
+            } => format!("{:?} {:?}", attributes, children(c)),
+            Node::Node {
+                binding,
+                content,
+                children: c,
+            } => format!("{:?} {:?} {:?}", binding, content, children(c)),
+            Node::Block {
+                binding,
+                level,
+                header,
+                children: c,
+            } => format!(
+                "{:?} {} {} {:?}",
+                binding,
+                level,
+                shape(ctx, *header),
+                children(c)
+            ),
+            Node::Error { text, .. } => format!("error {:?}", text),
+        }
+    }
+
+    fn assert_round_trip(text: &str, format: ImportFormat) {
+        let mut ctx = Context::new();
+        let imported = ctx.import(text, format).unwrap();
+        let mut source: Vec<u8> = Vec::new();
+        ctx.print_source(imported, &mut source).unwrap();
+        let source = String::from_utf8(source).unwrap();
+        let parser = Parser::new(source.clone(), tree_sitter_puddlejumper::language());
+        let loaded = ctx.load_document(&parser).unwrap();
+        assert_eq!(shape(&ctx, imported), shape(&ctx, loaded), "{}", source);
+    }
+
+    #[test]
+    fn test_import_round_trip() {
+        assert_round_trip(
+            "# #1 priority\n\
+             - #hashtag\n\
+             - @alice to review\n\
+             - Fix ^bug in parser\n\
+             - x ^2\n\
+             - \\escaped\n\
+             -\n  \
+               - under an empty item\n\
+             -\n\
+             - \n  \
+               -\n\
+             - <a id=\"api\"></a>API\n  \
+               - [@api](#api)\n",
+            ImportFormat::Markdown,
+        );
+        assert_round_trip(
+            "<opml version=\"2.0\"><body>\
+             <outline text=\"\"><outline text=\"a ^b\"/></outline>\
+             <outline text=\"\" _complete=\"true\"/>\
+             <outline text=\" \"/>\
+             </body></opml>",
+            ImportFormat::Opml,
+        );
+    }
+
+    #[test]
+    fn test_import_empty_items() {
+        assert_eq!(
+            import("- a\n-\n- b\n  -\n-\n  - c\n", ImportFormat::Markdown),
+            "a\nb\n@:\n    c\n"
+        );
+    }
+
+    #[test]
+    fn test_import_unprintable() {
+        let mut ctx = Context::new();
+        let document = ctx
+            .import(
+                "<opml><body><outline text=\"two&#10;lines\"/></body></opml>",
+                ImportFormat::Opml,
+            )
+            .unwrap();
+        let mut out: Vec<u8> = Vec::new();
+        let error = ctx.print_source(document, &mut out).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+        let empty = ctx.arena.alloc(Node::Node {
+            binding: None,
+            content: None,
+            children: vec![],
+        });
+        let error = ctx.print_source(empty, &mut out).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,438 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde_json::{json, Map, Value};
+
+use super::*;
+
+// Bumped whenever the shape of the JSON below changes incompatibly.
+pub const JSON_SCHEMA_VERSION: u64 = 1;
+
+// A `node::Context` serializes to a flat node table, so that nodes shared between a document and
+// its projections are only written once:
+//
+// {
+//   "version": 1,
+//   "root": 0,
+//   "nodes": [
+//     { "id": 0, "kind": "document", "attributes": { "title": "..." }, "children": [1] },
+//     { "id": 1, "kind": "node", "binding": "a", "content": { "kind": "content", "text": "..." },
+//       "children": [], "metadata": { "created_at": 1690000000000 }, "stable_id": "k3x9qa",
+//       "source": { "range": <range>, "binding": <range>, "content": <range> } },
+//     { "id": 2, "kind": "block", "binding": null, "level": 1, "header": 3, "children": [] },
+//     { "id": 4, "kind": "error", "text": "...", "range": { "start": 0, "end": 3 } }
+//   ]
+// }
+//
+// where a source <range> is
+//   { "start": 0, "end": 9, "start_point": { "row": 0, "column": 0 }, "end_point": { ... } }
+// and is left out for nodes that weren't loaded from text. `stable_id` is the node's `^id` marker,
+// if it has one.
+//
+// Ids are only meaningful within one serialized value.
+
+#[derive(Debug, PartialEq)]
+pub enum JsonError {
+    UnsupportedVersion(Value),
+    Invalid(String),
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported schema version: {}", version)
+            }
+            JsonError::Invalid(message) => write!(f, "Invalid JSON: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+impl Content {
+    pub fn to_json(&self) -> Value {
+        match self {
+            Content::Content(text) => json!({ "kind": "content", "text": text }),
+            Content::Ref(text) => json!({ "kind": "ref", "text": text }),
+        }
+    }
+
+    pub fn from_json(value: &Value) -> Result<Content, JsonError> {
+        let text = get_str(value, "text")?.to_string();
+        match get_str(value, "kind")? {
+            "content" => Ok(Content::Content(text)),
+            "ref" => Ok(Content::Ref(text)),
+            kind => Err(JsonError::Invalid(format!("unknown content kind {:?}", kind))),
+        }
+    }
+}
+
+fn range_to_json(range: &Range<usize>) -> Value {
+    json!({ "start": range.start, "end": range.end })
+}
+
+fn range_from_json(value: &Value) -> Result<Range<usize>, JsonError> {
+    Ok(get_usize(value, "start")?..get_usize(value, "end")?)
+}
+
+fn point_to_json(point: &tree_sitter::Point) -> Value {
+    json!({ "row": point.row, "column": point.column })
+}
+
+fn point_from_json(value: &Value) -> Result<tree_sitter::Point, JsonError> {
+    Ok(tree_sitter::Point {
+        row: get_usize(value, "row")?,
+        column: get_usize(value, "column")?,
+    })
+}
+
+impl SourceRange {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "start": self.start_byte,
+            "end": self.end_byte,
+            "start_point": point_to_json(&self.start_position),
+            "end_point": point_to_json(&self.end_position),
+        })
+    }
+
+    pub fn from_json(value: &Value) -> Result<SourceRange, JsonError> {
+        Ok(SourceRange {
+            start_byte: get_usize(value, "start")?,
+            end_byte: get_usize(value, "end")?,
+            start_position: point_from_json(get(value, "start_point")?)?,
+            end_position: point_from_json(get(value, "end_point")?)?,
+        })
+    }
+}
+
+impl NodeSource {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "range": self.range.to_json(),
+            "binding": self.binding.as_ref().map(SourceRange::to_json),
+            "content": self.content.as_ref().map(SourceRange::to_json),
+        })
+    }
+
+    pub fn from_json(value: &Value) -> Result<NodeSource, JsonError> {
+        let optional = |key: &str| match value.get(key) {
+            None | Some(Value::Null) => Ok(None),
+            Some(range) => SourceRange::from_json(range).map(Some),
+        };
+        Ok(NodeSource {
+            range: SourceRange::from_json(get(value, "range")?)?,
+            binding: optional("binding")?,
+            content: optional("content")?,
+        })
+    }
+}
+
+fn get<'a>(value: &'a Value, key: &str) -> Result<&'a Value, JsonError> {
+    value
+        .get(key)
+        .ok_or_else(|| JsonError::Invalid(format!("missing field {:?}", key)))
+}
+
+fn get_str<'a>(value: &'a Value, key: &str) -> Result<&'a str, JsonError> {
+    get(value, key)?
+        .as_str()
+        .ok_or_else(|| JsonError::Invalid(format!("field {:?} is not a string", key)))
+}
+
+fn get_usize(value: &Value, key: &str) -> Result<usize, JsonError> {
+    get(value, key)?
+        .as_u64()
+        .map(|n| n as usize)
+        .ok_or_else(|| JsonError::Invalid(format!("field {:?} is not a number", key)))
+}
+
+fn get_ids(value: &Value, key: &str) -> Result<Vec<usize>, JsonError> {
+    get(value, key)?
+        .as_array()
+        .ok_or_else(|| JsonError::Invalid(format!("field {:?} is not an array", key)))?
+        .iter()
+        .map(|id| {
+            id.as_u64()
+                .map(|id| id as usize)
+                .ok_or_else(|| JsonError::Invalid(format!("invalid id in {:?}", key)))
+        })
+        .collect()
+}
+
+fn get_binding(value: &Value) -> Result<Option<String>, JsonError> {
+    match value.get("binding") {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(binding)) => Ok(Some(binding.clone())),
+        Some(_) => Err(JsonError::Invalid(String::from("binding is not a string"))),
+    }
+}
+
+impl Context {
+    // Serializes `root` and everything reachable from it.
+    pub fn to_json(&self, root: NodeId) -> Value {
+        let mut nodes: Vec<Value> = Vec::new();
+        let mut seen: HashSet<NodeId> = HashSet::new();
+        self.to_json_rec(root, &mut nodes, &mut seen);
+        json!({
+            "version": JSON_SCHEMA_VERSION,
+            "root": root.index(),
+            "nodes": nodes,
+        })
+    }
+
+    fn to_json_rec(&self, node: NodeId, nodes: &mut Vec<Value>, seen: &mut HashSet<NodeId>) {
+        if !seen.insert(node) {
+            return;
+        }
+        nodes.push(self.node_to_json(node));
+        match &self.arena[node] {
+            Node::Document { children, .. } | Node::Node { children, .. } => {
+                for child in children {
+                    self.to_json_rec(*child, nodes, seen);
+                }
+            }
+            Node::Block {
+                header, children, ..
+            } => {
+                self.to_json_rec(*header, nodes, seen);
+                for child in children {
+                    self.to_json_rec(*child, nodes, seen);
+                }
+            }
+            Node::Error { .. } => {}
+        }
+    }
+
+    // A single entry of the node table.
+    pub fn node_to_json(&self, node: NodeId) -> Value {
+        let ids = |children: &Vec<NodeId>| -> Value {
+            children.iter().map(|child| json!(child.index())).collect()
+        };
+        let mut value = match &self.arena[node] {
+            Node::Document {
+                attributes,
+                children,
+            } => json!({
+                "kind": "document",
+                "attributes": attributes
+                    .iter()
+                    .map(|(key, value)| (key.clone(), Value::String(value.clone())))
+                    .collect::<Map<String, Value>>(),
+                "children": ids(children),
+            }),
+            Node::Node {
+                binding,
+                content,
+                children,
+            } => json!({
+                "kind": "node",
+                "binding": binding,
+                "content": content.as_ref().map(Content::to_json),
+                "children": ids(children),
+            }),
+            Node::Block {
+                binding,
+                level,
+                header,
+                children,
+            } => json!({
+                "kind": "block",
+                "binding": binding,
+                "level": level,
+                "header": header.index(),
+                "children": ids(children),
+            }),
+            Node::Error { text, range } => json!({
+                "kind": "error",
+                "text": text,
+                "range": range_to_json(range),
+            }),
+        };
+        value["id"] = json!(node.index());
+        if let Some(metadata) = self.metadata.get(&node) {
+            let created_at = metadata
+                .created_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            value["metadata"] = json!({ "created_at": created_at });
+        }
+        if let Some(stable_id) = self.stable_ids.get(&node) {
+            value["stable_id"] = json!(stable_id);
+        }
+        if let Some(source) = self.sources.get(&node) {
+            value["source"] = source.to_json();
+        }
+        value
+    }
+
+    // Loads a value written by `to_json` into this context, returning the new id of its root.
+    pub fn load_json(&mut self, value: &Value) -> Result<NodeId, JsonError> {
+        let version = get(value, "version")?;
+        if version.as_u64() != Some(JSON_SCHEMA_VERSION) {
+            return Err(JsonError::UnsupportedVersion(version.clone()));
+        }
+        let mut entries: HashMap<usize, &Value> = HashMap::new();
+        for entry in get(value, "nodes")?
+            .as_array()
+            .ok_or_else(|| JsonError::Invalid(String::from("nodes is not an array")))?
+        {
+            entries.insert(get_usize(entry, "id")?, entry);
+        }
+        let mut loaded: HashMap<usize, NodeId> = HashMap::new();
+        self.load_json_rec(get_usize(value, "root")?, &entries, &mut loaded, 0)
+    }
+
+    fn load_json_rec(
+        &mut self,
+        id: usize,
+        entries: &HashMap<usize, &Value>,
+        loaded: &mut HashMap<usize, NodeId>,
+        depth: usize,
+    ) -> Result<NodeId, JsonError> {
+        if let Some(node) = loaded.get(&id) {
+            return Ok(*node);
+        }
+        // Ids can only refer to nodes that were written out, which are finitely many, so a
+        // deeper chain than that must be a cycle.
+        if depth > entries.len() {
+            return Err(JsonError::Invalid(String::from("cycle in node table")));
+        }
+        let entry: &Value = entries
+            .get(&id)
+            .ok_or_else(|| JsonError::Invalid(format!("unknown node id {}", id)))?;
+        let node = match get_str(entry, "kind")? {
+            "document" => {
+                let mut attributes: IndexMap<String, String> = IndexMap::new();
+                if let Some(object) = entry.get("attributes").and_then(Value::as_object) {
+                    for (key, value) in object {
+                        attributes.insert(key.clone(), value.as_str().unwrap_or("").to_string());
+                    }
+                }
+                Node::Document {
+                    attributes,
+                    children: self.load_json_children(entry, entries, loaded, depth)?,
+                }
+            }
+            "node" => Node::Node {
+                binding: get_binding(entry)?,
+                content: match entry.get("content") {
+                    None | Some(Value::Null) => None,
+                    Some(content) => Some(Content::from_json(content)?),
+                },
+                children: self.load_json_children(entry, entries, loaded, depth)?,
+            },
+            "block" => {
+                let header = get_usize(entry, "header")?;
+                let header = self.load_json_rec(header, entries, loaded, depth + 1)?;
+                Node::Block {
+                    binding: get_binding(entry)?,
+                    level: get_usize(entry, "level")?,
+                    header,
+                    children: self.load_json_children(entry, entries, loaded, depth)?,
+                }
+            }
+            "error" => Node::Error {
+                text: get_str(entry, "text")?.to_string(),
+                range: range_from_json(get(entry, "range")?)?,
+            },
+            kind => return Err(JsonError::Invalid(format!("unknown node kind {:?}", kind))),
+        };
+        let node_id = self.arena.alloc(node);
+        if let Some(created_at) = entry
+            .get("metadata")
+            .and_then(|metadata| metadata.get("created_at"))
+            .and_then(Value::as_u64)
+        {
+            self.metadata.insert(
+                node_id,
+                NodeMetadata {
+                    created_at: UNIX_EPOCH + Duration::from_millis(created_at),
+                },
+            );
+        }
+        if let Some(stable_id) = entry.get("stable_id").and_then(Value::as_str) {
+            self.stable_ids.insert(node_id, stable_id.to_string());
+        }
+        if let Some(source) = entry.get("source") {
+            self.sources.insert(node_id, NodeSource::from_json(source)?);
+            self.record_parent(node_id);
+        }
+        loaded.insert(id, node_id);
+        Ok(node_id)
+    }
+
+    fn load_json_children(
+        &mut self,
+        entry: &Value,
+        entries: &HashMap<usize, &Value>,
+        loaded: &mut HashMap<usize, NodeId>,
+        depth: usize,
+    ) -> Result<Vec<NodeId>, JsonError> {
+        get_ids(entry, "children")?
+            .into_iter()
+            .map(|child| self.load_json_rec(child, entries, loaded, depth + 1))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::printer::{PrintContext, DEFAULT_INDENT_WIDTH};
+    use crate::parser::Parser;
+
+    fn print(ctx: &Context, node: NodeId) -> String {
+        let mut out: Vec<u8> = Vec::new();
+        ctx.pretty_print(
+            node,
+            &mut PrintContext {
+                level: 0,
+                needs_indent: true,
+                indent_width: DEFAULT_INDENT_WIDTH,
+                out: &mut out,
+            },
+        )
+        .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let code = String::from("---\ntitle: x\n---\n@a: hello\n    @a\n# header\nworld");
+        let parser = Parser::new(code, tree_sitter_puddlejumper::language());
+        let mut ctx = Context::new();
+        let document = ctx.load_document(&parser).unwrap();
+        let json = ctx.to_json(document);
+        assert_eq!(json["version"], JSON_SCHEMA_VERSION);
+        assert_eq!(json["nodes"][0]["kind"], "document");
+        assert_eq!(json["nodes"][0]["attributes"]["title"], "x");
+        assert_eq!(json["nodes"][1]["binding"], "a");
+        assert_eq!(
+            json["nodes"][2]["content"],
+            json!({ "kind": "ref", "text": "@a" })
+        );
+
+        assert_eq!(json["nodes"][1]["source"]["binding"]["start"], 18);
+        assert_eq!(
+            json["nodes"][1]["source"]["range"]["start_point"],
+            json!({ "row": 3, "column": 0 })
+        );
+
+        let mut loaded = Context::new();
+        let root = loaded.load_json(&json).unwrap();
+        assert_eq!(print(&loaded, root), print(&ctx, document));
+    }
+
+    #[test]
+    fn test_json_rejects_other_versions() {
+        let mut ctx = Context::new();
+        assert_eq!(
+            ctx.load_json(&json!({ "version": 0, "root": 0, "nodes": [] })),
+            Err(JsonError::UnsupportedVersion(json!(0)))
+        );
+    }
+}
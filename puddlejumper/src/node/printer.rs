@@ -1,20 +1,42 @@
+use super::attributes::DocumentSettings;
 use super::*;
 
+pub const DEFAULT_INDENT_WIDTH: usize = 4;
+
 pub struct PrintContext<'out> {
     pub level: usize,
     pub out: &'out mut dyn std::io::Write,
     pub needs_indent: bool,
+    pub indent_width: usize,
 }
 impl Context {
     pub fn pretty_print<'a>(
         &'a self,
         node: NodeId,
         ctx: &mut PrintContext,
+    ) -> Result<(), std::io::Error> {
+        self.pretty_print_annotated(node, ctx, &|_| None)
+    }
+
+    // Like `pretty_print`, with `annotate`'s text for a node after its content, e.g. the progress
+    // rollups of `stats`.
+    pub fn pretty_print_annotated(
+        &self,
+        node: NodeId,
+        ctx: &mut PrintContext,
+        annotate: &dyn Fn(NodeId) -> Option<String>,
     ) -> Result<(), std::io::Error> {
         match &self.arena[node] {
-            Node::Document { children, .. } => {
+            Node::Document {
+                attributes,
+                children,
+            } => {
+                let settings = DocumentSettings::from_attributes(attributes);
+                if let Some(indent_width) = settings.indent_width {
+                    ctx.indent_width = indent_width;
+                }
                 for child in children {
-                    self.pretty_print(*child, ctx)?;
+                    self.pretty_print_annotated(*child, ctx, annotate)?;
                 }
             }
             Node::Node {
@@ -23,75 +45,277 @@ impl Context {
                 children,
                 ..
             } => {
-                let mut indent = String::new();
-                for _ in 0..ctx.level {
-                    indent.push_str("    ");
+                if ctx.needs_indent {
+                    write!(ctx.out, "{}", " ".repeat(ctx.level * ctx.indent_width))?;
                 }
-                write!(ctx.out, "{}", indent)?;
                 match binding {
                     Some(binding) => {
                         write!(ctx.out, "@{}:", binding)?;
                     }
                     None => (),
                 }
+                let annotation = annotate(node)
+                    .map(|annotation| format!(" {}", annotation))
+                    .unwrap_or_default();
                 match content {
                     Some(Content::Content(content)) => {
-                        writeln!(ctx.out, "{}", content)?;
+                        writeln!(ctx.out, "{}{}", content, annotation)?;
                     }
                     Some(Content::Ref(content)) => {
-                        writeln!(ctx.out, "{}", content)?;
+                        writeln!(ctx.out, "{}{}", content, annotation)?;
                     }
                     None => (),
                 }
                 for child in children {
-                    self.pretty_print(
+                    self.pretty_print_annotated(
                         *child,
                         &mut PrintContext {
                             level: ctx.level + 1,
                             out: ctx.out,
                             needs_indent: true,
+                            indent_width: ctx.indent_width,
                         },
+                        annotate,
                     )?;
                 }
             }
             Node::Block {
                 header,
                 binding,
+                level,
                 children,
-                ..
             } => {
-                let mut indent = String::new();
-                for _ in 0..ctx.level {
-                    indent.push_str("  ");
-                }
+                let indent = " ".repeat(ctx.level * ctx.indent_width);
                 match binding {
                     Some(binding) => {
-                        writeln!(ctx.out, "{}{}:", indent, binding)?;
+                        writeln!(ctx.out, "{}@{}:", indent, binding)?;
                     }
                     None => (),
                 }
-                self.pretty_print(
+                write!(ctx.out, "{}{} ", indent, "#".repeat(*level))?;
+                // The header line stands for the block.
+                self.pretty_print_annotated(
                     *header,
                     &mut PrintContext {
                         level: ctx.level,
                         out: ctx.out,
                         needs_indent: false,
+                        indent_width: ctx.indent_width,
+                    },
+                    &|id| {
+                        if id == *header {
+                            annotate(node)
+                        } else {
+                            annotate(id)
+                        }
                     },
                 )?;
                 for child in children {
-                    self.pretty_print(
+                    self.pretty_print_annotated(
                         *child,
                         &mut PrintContext {
                             level: ctx.level + 1,
                             out: ctx.out,
                             needs_indent: true,
+                            indent_width: ctx.indent_width,
                         },
+                        annotate,
                     )?;
                 }
             }
+            Node::Error { text, .. } => {
+                if ctx.needs_indent {
+                    write!(ctx.out, "{}", " ".repeat(ctx.level * ctx.indent_width))?;
+                }
+                writeln!(ctx.out, "{}", text.trim_end_matches('\n'))?;
+            }
         }
         Ok(())
     }
 
+    // Prints `node` as `.pj` source that loads back into the same tree, unlike `pretty_print`,
+    // which is for display. Front matter is kept, and block children stay at the block's indent
+    // since blocks nest by level. Content is escaped with `escape_content`, and a node with
+    // neither content nor binding is written as `@:` to hold its children. Fails with
+    // `InvalidData` for what has no source form, e.g. content on several lines or an empty node
+    // without children.
+    pub fn print_source(
+        &self,
+        node: NodeId,
+        out: &mut dyn std::io::Write,
+    ) -> Result<(), std::io::Error> {
+        let settings = self.document_settings(node);
+        if let Some(attributes) = self.attributes(node).filter(|a| !a.is_empty()) {
+            writeln!(out, "---")?;
+            for (key, value) in attributes {
+                writeln!(out, "{}: {}", key, value)?;
+            }
+            writeln!(out, "---")?;
+        }
+        self.print_source_rec(
+            node,
+            &mut PrintContext {
+                level: 0,
+                out,
+                needs_indent: true,
+                indent_width: settings.indent_width.unwrap_or(DEFAULT_INDENT_WIDTH),
+            },
+        )
+    }
 
-}
\ No newline at end of file
+    fn print_source_rec(&self, node: NodeId, ctx: &mut PrintContext) -> Result<(), std::io::Error> {
+        let indent = " ".repeat(ctx.level * ctx.indent_width);
+        match &self.arena[node] {
+            Node::Document { children, .. } => {
+                for child in children {
+                    self.print_source_rec(*child, ctx)?;
+                }
+            }
+            Node::Node {
+                binding,
+                content,
+                children,
+            } => {
+                if ctx.needs_indent {
+                    write!(ctx.out, "{}", indent)?;
+                }
+                match (binding, content) {
+                    (Some(binding), Some(_)) => write!(ctx.out, "@{}: ", check_binding(binding)?)?,
+                    (Some(binding), None) => write!(ctx.out, "@{}:", check_binding(binding)?)?,
+                    (None, Some(_)) => (),
+                    (None, None) if children.is_empty() => {
+                        return Err(unprintable("an empty node"))
+                    }
+                    (None, None) => write!(ctx.out, "@:")?,
+                }
+                match content {
+                    Some(Content::Content(text)) => write!(ctx.out, "{}", escape_content(text)?)?,
+                    Some(Content::Ref(text)) => write!(ctx.out, "{}", text)?,
+                    None => (),
+                }
+                match self.stable_ids.get(&node) {
+                    Some(id) if content.is_some() => writeln!(ctx.out, " ^{}", id)?,
+                    _ => writeln!(ctx.out)?,
+                }
+                for child in children {
+                    self.print_source_rec(
+                        *child,
+                        &mut PrintContext {
+                            level: ctx.level + 1,
+                            out: ctx.out,
+                            needs_indent: true,
+                            indent_width: ctx.indent_width,
+                        },
+                    )?;
+                }
+            }
+            Node::Block {
+                binding,
+                level,
+                header,
+                children,
+            } => {
+                if let Some(binding) = binding {
+                    writeln!(ctx.out, "{}@{}:", indent, check_binding(binding)?)?;
+                }
+                write!(ctx.out, "{}{} ", indent, "#".repeat(*level))?;
+                ctx.needs_indent = false;
+                self.print_source_rec(*header, ctx)?;
+                ctx.needs_indent = true;
+                for child in children {
+                    self.print_source_rec(*child, ctx)?;
+                }
+            }
+            Node::Error { text, .. } => {
+                if ctx.needs_indent {
+                    write!(ctx.out, "{}", indent)?;
+                }
+                writeln!(ctx.out, "{}", text.trim_end_matches('\n'))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Content as it's written in `.pj` source. A leading `#`, `@` or `\` would read as a block, a
+// binding or ref, or an escape, and a trailing ` ^word` as an id marker, so they get a `\` that
+// `unescape_content` drops when the source is loaded.
+pub fn escape_content(text: &str) -> Result<String, std::io::Error> {
+    if text.is_empty() || text.trim() != text || text.contains(['\n', '\r']) {
+        return Err(unprintable(&format!("content {:?}", text)));
+    }
+    let mut escaped = text.to_string();
+    if let Some(space) = text.rfind(' ') {
+        if reads_as_marker(text[space + 1..].trim_start_matches('\\')) {
+            escaped.insert(space + 1, '\\');
+        }
+    }
+    if text.starts_with(['#', '@', '\\']) {
+        escaped.insert(0, '\\');
+    }
+    Ok(escaped)
+}
+
+pub fn unescape_content(text: &str) -> String {
+    let mut unescaped = text.to_string();
+    if let Some(space) = text.rfind(' ') {
+        let word = &text[space + 1..];
+        if word.starts_with('\\') && reads_as_marker(word.trim_start_matches('\\')) {
+            unescaped.remove(space + 1);
+        }
+    }
+    if unescaped.starts_with('\\') && unescaped[1..].starts_with(['#', '@', '\\']) {
+        unescaped.remove(0);
+    }
+    unescaped
+}
+
+// An id marker, or a lone `^`, which content can't end with either.
+fn reads_as_marker(word: &str) -> bool {
+    word.strip_prefix('^')
+        .is_some_and(|id| id.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+fn check_binding(binding: &str) -> Result<&str, std::io::Error> {
+    if binding.is_empty()
+        || !binding
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(unprintable(&format!("binding {:?}", binding)));
+    }
+    Ok(binding)
+}
+
+fn unprintable(what: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("Can't write {} as .pj source", what),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_content() {
+        for (text, escaped) in &[
+            ("plain", "plain"),
+            ("#hashtag", "\\#hashtag"),
+            ("@alice to review", "\\@alice to review"),
+            ("\\#", "\\\\#"),
+            ("x ^2", "x \\^2"),
+            ("x \\^2", "x \\\\^2"),
+            ("Fix ^bug in parser", "Fix ^bug in parser"),
+            ("^top", "^top"),
+            ("x ^", "x \\^"),
+        ] {
+            assert_eq!(escape_content(text).unwrap(), *escaped);
+            assert_eq!(unescape_content(escaped), *text);
+        }
+        assert!(escape_content("two\nlines").is_err());
+        assert!(escape_content(" indented").is_err());
+        assert!(escape_content("").is_err());
+    }
+}
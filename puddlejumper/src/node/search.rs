@@ -0,0 +1,240 @@
+use std::cmp::Reverse;
+
+use serde_json::{json, Value};
+
+use super::attributes::DocumentSettings;
+use super::*;
+use crate::levenshtein::substring_distance;
+
+// Fuzzy search over the content and binding names of an outline, ignoring case. A node matches if
+// its content or binding contains the query with at most `max_distance` edits, and results are
+// ranked by fewest edits, then by whether the text starts with the query, then by shortest text,
+// then in document order. Priority markers without children are left out, as in `stats`.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SearchField {
+    Binding,
+    Content,
+}
+
+impl SearchField {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SearchField::Binding => "binding",
+            SearchField::Content => "content",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResult {
+    pub node: NodeId,
+    pub field: SearchField,
+    pub distance: usize,
+    // The labels of the node's ancestors, outermost first.
+    pub breadcrumbs: Vec<String>,
+}
+
+impl SearchResult {
+    pub fn to_json(&self, ctx: &Context) -> Value {
+        let position = |byte: usize, point: tree_sitter::Point| json!({ "byte": byte, "row": point.row, "column": point.column });
+        let source = ctx.source(self.node);
+        json!({
+            "label": ctx.label(self.node),
+            "stable_id": ctx.stable_id(self.node),
+            "breadcrumbs": self.breadcrumbs,
+            "field": self.field.name(),
+            "distance": self.distance,
+            "start": source.map(|s| position(s.range.start_byte, s.range.start_position)),
+            "end": source.map(|s| position(s.range.end_byte, s.range.end_position)),
+        })
+    }
+}
+
+// A quarter of the query's length, so short queries have to match exactly.
+pub fn max_distance(query: &[char]) -> usize {
+    query.len() / 4
+}
+
+struct Match {
+    result: SearchResult,
+    prefix: bool,
+    length: usize,
+}
+
+impl Context {
+    // The best `limit` matches for `query` under `root`.
+    pub fn search(&self, root: NodeId, query: &str, limit: usize) -> Vec<SearchResult> {
+        let query: Vec<char> = query.trim().to_lowercase().chars().collect();
+        if query.is_empty() {
+            return vec![];
+        }
+        let settings = self.document_settings(root);
+        let mut matches: Vec<Match> = Vec::new();
+        let mut breadcrumbs: Vec<String> = Vec::new();
+        let mut column: Vec<usize> = Vec::new();
+        self.search_rec(
+            root,
+            &query,
+            &settings,
+            &mut breadcrumbs,
+            &mut matches,
+            &mut column,
+        );
+        // `sort_by_key` is stable, which keeps ties in document order.
+        matches.sort_by_key(|m| (m.result.distance, Reverse(m.prefix), m.length));
+        matches.into_iter().take(limit).map(|m| m.result).collect()
+    }
+
+    fn search_rec(
+        &self,
+        node: NodeId,
+        query: &[char],
+        settings: &DocumentSettings,
+        breadcrumbs: &mut Vec<String>,
+        matches: &mut Vec<Match>,
+        // Scratch space for `substring_distance`.
+        column: &mut Vec<usize>,
+    ) {
+        let (fields, children) = match &self.arena[node] {
+            Node::Document { children, .. } => {
+                for child in children {
+                    self.search_rec(*child, query, settings, breadcrumbs, matches, column);
+                }
+                return;
+            }
+            Node::Node {
+                binding,
+                content,
+                children,
+            } => {
+                let content = match content {
+                    Some(Content::Content(text)) => {
+                        if children.is_empty() && settings.priority_of(text).is_some() {
+                            return;
+                        }
+                        Some(text)
+                    }
+                    // Refs are searched through what they refer to.
+                    Some(Content::Ref(_)) | None => None,
+                };
+                ((binding.as_ref(), content), children)
+            }
+            Node::Block {
+                binding,
+                header,
+                children,
+                ..
+            } => {
+                let content = match &self.arena[*header] {
+                    Node::Node {
+                        content: Some(Content::Content(text)),
+                        ..
+                    } => Some(text),
+                    _ => None,
+                };
+                ((binding.as_ref(), content), children)
+            }
+            Node::Error { .. } => return,
+        };
+
+        let (binding, content) = fields;
+        let best = vec![
+            binding.map(|text| (SearchField::Binding, text)),
+            content.map(|text| (SearchField::Content, text)),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|(field, text)| {
+            let text = || text.chars().flat_map(char::to_lowercase);
+            // `@api` finds the binding `api`.
+            let query = match (field, query) {
+                (SearchField::Binding, ['@', rest @ ..]) => rest,
+                _ => query,
+            };
+            let distance = substring_distance(query, text(), column);
+            let prefix = text().take(query.len()).eq(query.iter().copied());
+            (distance, Reverse(prefix), text().count(), field)
+        })
+        .min();
+        if let Some((distance, Reverse(prefix), length, field)) = best {
+            if distance <= max_distance(query) {
+                matches.push(Match {
+                    result: SearchResult {
+                        node,
+                        field,
+                        distance,
+                        breadcrumbs: breadcrumbs.clone(),
+                    },
+                    prefix,
+                    length,
+                });
+            }
+        }
+
+        breadcrumbs.push(self.label(node));
+        for child in children {
+            self.search_rec(*child, query, settings, breadcrumbs, matches, column);
+        }
+        breadcrumbs.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    const TEXT: &str = "\
+# Backend
+@api: API
+    Write tests
+    P0
+Deploy
+    see @api
+    Tests for deploy
+";
+
+    fn search(query: &str) -> Vec<(String, Vec<String>, usize)> {
+        let parser = Parser::new(String::from(TEXT), tree_sitter_puddlejumper::language());
+        let mut ctx = Context::new();
+        let root = ctx.load_document(&parser).unwrap();
+        ctx.search(root, query, 10)
+            .into_iter()
+            .map(|result| (ctx.label(result.node), result.breadcrumbs, result.distance))
+            .collect()
+    }
+
+    #[test]
+    fn test_search() {
+        assert_eq!(
+            search("tests"),
+            vec![
+                (
+                    String::from("Tests for deploy"),
+                    vec![String::from("Backend"), String::from("Deploy")],
+                    0
+                ),
+                (
+                    String::from("Write tests"),
+                    vec![String::from("Backend"), String::from("API")],
+                    0
+                ),
+            ]
+        );
+        // By binding.
+        assert_eq!(
+            search("@api"),
+            vec![(String::from("API"), vec![String::from("Backend")], 0)]
+        );
+        assert_eq!(search("backnd"), vec![(String::from("Backend"), vec![], 1)]);
+        assert_eq!(search("P0"), vec![]);
+        assert_eq!(search("  "), vec![]);
+    }
+
+    #[test]
+    fn test_max_distance() {
+        assert_eq!(max_distance(&['a', 'p', 'i']), 0);
+        assert_eq!(max_distance(&"backnd".chars().collect::<Vec<char>>()), 1);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::text_diff::Diff;
+
+use super::*;
+
+impl SourceRange {
+    pub fn from_node(t_node: &tree_sitter::Node) -> SourceRange {
+        SourceRange {
+            start_byte: t_node.start_byte(),
+            end_byte: t_node.end_byte(),
+            start_position: t_node.start_position(),
+            end_position: t_node.end_position(),
+        }
+    }
+
+    pub fn empty(byte: usize, position: tree_sitter::Point) -> SourceRange {
+        SourceRange {
+            start_byte: byte,
+            end_byte: byte,
+            start_position: position,
+            end_position: position,
+        }
+    }
+
+    pub fn byte_range(&self) -> Range<usize> {
+        self.start_byte..self.end_byte
+    }
+
+    // Inclusive of the end, so that a cursor just after a node still finds it.
+    pub fn contains(&self, byte: usize) -> bool {
+        self.start_byte <= byte && byte <= self.end_byte
+    }
+
+    // Moves the range the way `tree_sitter::Tree::edit` moves nodes: positions after the edit
+    // shift with it, and positions inside it collapse to the end of the new text.
+    pub fn edit(&mut self, edit: &tree_sitter::InputEdit) {
+        (self.start_byte, self.start_position) =
+            edit_position(self.start_byte, self.start_position, edit);
+        (self.end_byte, self.end_position) = edit_position(self.end_byte, self.end_position, edit);
+    }
+}
+
+fn edit_position(
+    byte: usize,
+    point: tree_sitter::Point,
+    edit: &tree_sitter::InputEdit,
+) -> (usize, tree_sitter::Point) {
+    if byte >= edit.old_end_byte {
+        let point = if point.row == edit.old_end_position.row {
+            tree_sitter::Point {
+                row: edit.new_end_position.row,
+                column: edit.new_end_position.column + point.column
+                    - edit.old_end_position.column,
+            }
+        } else {
+            tree_sitter::Point {
+                row: edit.new_end_position.row + point.row - edit.old_end_position.row,
+                column: point.column,
+            }
+        };
+        return (edit.new_end_byte + byte - edit.old_end_byte, point);
+    }
+    if byte > edit.start_byte {
+        return (edit.new_end_byte, edit.new_end_position);
+    }
+    (byte, point)
+}
+
+impl NodeSource {
+    pub fn edit(&mut self, edit: &tree_sitter::InputEdit) {
+        self.range.edit(edit);
+        if let Some(binding) = self.binding.as_mut() {
+            binding.edit(edit);
+        }
+        if let Some(content) = self.content.as_mut() {
+            content.edit(edit);
+        }
+    }
+}
+
+impl Context {
+    pub fn source(&self, node: NodeId) -> Option<&NodeSource> {
+        self.sources.get(&node)
+    }
+
+    // Keeps source ranges in step with an edit to the text, without reloading.
+    pub fn apply_edit(&mut self, edit: &tree_sitter::InputEdit) {
+        for source in self.sources.values_mut() {
+            source.edit(edit);
+        }
+        for (id, node) in self.arena.iter_mut() {
+            if let Node::Error { range, .. } = node {
+                if let Some(source) = self.sources.get(&id) {
+                    *range = source.range.byte_range();
+                }
+            }
+        }
+    }
+
+    // Applies the edits of a `Parser::update`, in order.
+    pub fn apply_diff(&mut self, diff: &Diff) {
+        for change in &diff.changes {
+            self.apply_edit(&change.input_edit());
+        }
+    }
+
+    // The innermost loaded node under `node` whose source contains `byte`.
+    pub fn node_at(&self, node: NodeId, byte: usize) -> Option<NodeId> {
+        let source = self.sources.get(&node)?;
+        if !source.range.contains(byte) {
+            return None;
+        }
+        let children: Vec<NodeId> = match &self.arena[node] {
+            Node::Document { children, .. } | Node::Node { children, .. } => children.clone(),
+            Node::Block {
+                header, children, ..
+            } => std::iter::once(*header).chain(children.iter().copied()).collect(),
+            Node::Error { .. } => Vec::new(),
+        };
+        for child in children {
+            if let Some(found) = self.node_at(child, byte) {
+                return Some(found);
+            }
+        }
+        Some(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn point(row: usize, column: usize) -> tree_sitter::Point {
+        tree_sitter::Point { row, column }
+    }
+
+    #[test]
+    fn test_edit_shifts_later_ranges() {
+        let mut range = SourceRange {
+            start_byte: 6,
+            end_byte: 11,
+            start_position: point(1, 0),
+            end_position: point(1, 5),
+        };
+        // Insert "ab\n" at the start of the text.
+        range.edit(&tree_sitter::InputEdit {
+            start_byte: 0,
+            old_end_byte: 0,
+            new_end_byte: 3,
+            start_position: point(0, 0),
+            old_end_position: point(0, 0),
+            new_end_position: point(1, 0),
+        });
+        assert_eq!(range.byte_range(), 9..14);
+        assert_eq!(range.start_position, point(2, 0));
+        assert_eq!(range.end_position, point(2, 5));
+    }
+
+    #[test]
+    fn test_load_records_sources() {
+        let code = String::from("hello\n    @a: world");
+        let mut parser = Parser::new(code, tree_sitter_puddlejumper::language());
+        let mut ctx = Context::new();
+        let document = ctx.load_document(&parser).unwrap();
+
+        let world = ctx.node_at(document, 16).unwrap();
+        let source = ctx.source(world).unwrap().clone();
+        assert_eq!(source.binding.unwrap().byte_range(), 11..12);
+        assert_eq!(source.binding.unwrap().start_position, point(1, 5));
+        assert_eq!(source.content.unwrap().byte_range(), 14..19);
+        assert_eq!(source.content.unwrap().end_position, point(1, 13));
+
+        let update = parser.update(String::from("hi\nhello\n    @a: world"));
+        ctx.apply_diff(&update.diff);
+        parser.apply_update(update);
+        let mut reloaded = Context::new();
+        let reloaded_document = reloaded.load_document(&parser).unwrap();
+        let reloaded_world = reloaded.node_at(reloaded_document, 19).unwrap();
+        assert_eq!(ctx.source(world), reloaded.source(reloaded_world));
+    }
+}
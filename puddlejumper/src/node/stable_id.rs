@@ -0,0 +1,157 @@
+use std::collections::HashSet;
+
+use super::*;
+use crate::text_diff::TextEdit;
+
+// Ids that, unlike `NodeId`s, mean the same thing across processes and reloads, so that links and
+// external tools can refer to items durably. A node with a binding is identified by its binding,
+// and any other node by the `^id` marker after its content, e.g. `Write tests ^k3x9qa`. Markers
+// are derived from the content when they are first assigned, and then stay as the content changes.
+
+pub const STABLE_ID_LENGTH: usize = 6;
+
+impl Context {
+    pub fn stable_id(&self, node: NodeId) -> Option<&str> {
+        match &self.arena[node] {
+            Node::Node {
+                binding: Some(binding),
+                ..
+            }
+            | Node::Block {
+                binding: Some(binding),
+                ..
+            } => Some(binding),
+            // A block without a binding goes by its header.
+            Node::Block { header, .. } => self.stable_ids.get(header).map(String::as_str),
+            _ => self.stable_ids.get(&node).map(String::as_str),
+        }
+    }
+
+    pub fn find_stable_id(&self, root: NodeId, id: &str) -> Option<NodeId> {
+        let mut found: Option<NodeId> = None;
+        self.walk(root, &mut |node, _| {
+            if found.is_none() && self.stable_id(node) == Some(id) {
+                found = Some(node);
+            }
+        });
+        found
+    }
+
+    // Gives a marker to every node under `root` that has content but neither a binding nor a
+    // marker, except priority markers and refs. Returns the edits that add the markers to the
+    // source, for the nodes that were loaded from it.
+    pub fn assign_stable_ids(&mut self, root: NodeId) -> Vec<TextEdit> {
+        let settings = self.document_settings(root);
+        let mut taken: HashSet<String> = HashSet::new();
+        let mut missing: Vec<(NodeId, String)> = Vec::new();
+        self.walk(root, &mut |node, n| {
+            if let Some(id) = self.stable_id(node) {
+                taken.insert(id.to_string());
+            }
+            if let Node::Node {
+                binding: None,
+                content: Some(Content::Content(text)),
+                ..
+            } = n
+            {
+                if !self.stable_ids.contains_key(&node) && settings.priority_of(text).is_none() {
+                    missing.push((node, text.clone()));
+                }
+            }
+        });
+        let mut edits: Vec<TextEdit> = Vec::new();
+        for (node, text) in missing {
+            let id = generate_stable_id(&text, &taken);
+            taken.insert(id.clone());
+            if let Some(content) = self.source(node).and_then(|source| source.content) {
+                edits.push(TextEdit {
+                    range: content.end_byte..content.end_byte,
+                    text: format!(" ^{}", id),
+                });
+            }
+            self.stable_ids.insert(node, id);
+        }
+        edits
+    }
+}
+
+// A base-36 hash of `text`, rehashed with a counter until it isn't in `taken`.
+pub fn generate_stable_id(text: &str, taken: &HashSet<String>) -> String {
+    (0u64..)
+        .map(|attempt| {
+            let mut hash = fnv1a(text.as_bytes());
+            if attempt > 0 {
+                hash = fnv1a(format!("{}\0{}", text, attempt).as_bytes());
+            }
+            base36(hash, STABLE_ID_LENGTH)
+        })
+        .find(|id| !taken.contains(id))
+        .unwrap()
+}
+
+// FNV-1a, because `DefaultHasher` isn't guaranteed to hash the same across Rust versions.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn base36(mut value: u64, length: usize) -> String {
+    const DIGITS: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+    let mut id = String::new();
+    for _ in 0..length {
+        id.push(DIGITS[(value % 36) as usize] as char);
+        value /= 36;
+    }
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::text_diff::apply_edits;
+
+    #[test]
+    fn test_generate_stable_id() {
+        let mut taken: HashSet<String> = HashSet::new();
+        let id = generate_stable_id("Write tests", &taken);
+        assert_eq!(id.len(), STABLE_ID_LENGTH);
+        assert_eq!(generate_stable_id("Write tests", &taken), id);
+        taken.insert(id.clone());
+        let other = generate_stable_id("Write tests", &taken);
+        assert_ne!(other, id);
+        assert_eq!(other.len(), STABLE_ID_LENGTH);
+    }
+
+    #[test]
+    fn test_assign_stable_ids() {
+        let text = "@api: API\n    Write tests ^k3x9qa\n    Docs\n        P1\n";
+        let parser = Parser::new(String::from(text), tree_sitter_puddlejumper::language());
+        let mut ctx = Context::new();
+        let root = ctx.load_document(&parser).unwrap();
+        let tests = ctx.find_stable_id(root, "k3x9qa").unwrap();
+        assert_eq!(
+            ctx.find_stable_id(root, "api"),
+            ctx.parent(tests).map(|(p, _)| p)
+        );
+
+        let edits = ctx.assign_stable_ids(root);
+        assert_eq!(edits.len(), 1);
+        let text = apply_edits(text, &edits);
+        let id = ctx.stable_ids.values().find(|id| *id != "k3x9qa").unwrap();
+        assert_eq!(
+            text,
+            format!(
+                "@api: API\n    Write tests ^k3x9qa\n    Docs ^{}\n        P1\n",
+                id
+            )
+        );
+        // The printer keeps them.
+        let mut out: Vec<u8> = Vec::new();
+        ctx.print_source(root, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), text);
+        // Nothing left to assign.
+        assert_eq!(ctx.assign_stable_ids(root), vec![]);
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+
+use serde_json::{json, Map, Value};
+
+use super::attributes::DocumentSettings;
+use super::printer::PrintContext;
+use super::*;
+
+// Progress rollups per subtree: how much is under a node, how many of its tasks are done, and how
+// many of its items have each priority. Priority markers without children are annotations rather
+// than items, so they aren't counted.
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    // Nodes and blocks under the node.
+    pub descendants: usize,
+    // Open and done tasks under the node, see `tasks`.
+    pub tasks: usize,
+    pub done: usize,
+    // Items under the node by priority, indexed like the document's priorities.
+    pub priorities: Vec<usize>,
+    // How many levels the node's subtree goes down, 0 for a leaf.
+    pub depth: usize,
+}
+
+impl Stats {
+    // E.g. `[3/7, 2×P0]`, or `None` if there are no tasks or prioritized items.
+    pub fn summary(&self, settings: &DocumentSettings) -> Option<String> {
+        let mut parts: Vec<String> = Vec::new();
+        if self.tasks > 0 {
+            parts.push(format!("{}/{}", self.done, self.tasks));
+        }
+        for (priority, count) in self.priorities.iter().enumerate() {
+            if *count > 0 {
+                parts.push(format!("{}×{}", count, settings.priorities[priority]));
+            }
+        }
+        if parts.is_empty() {
+            return None;
+        }
+        Some(format!("[{}]", parts.join(", ")))
+    }
+
+    pub fn to_json(&self, settings: &DocumentSettings) -> Value {
+        let priorities: Map<String, Value> = self
+            .priorities
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(priority, count)| (settings.priorities[priority].clone(), json!(count)))
+            .collect();
+        json!({
+            "descendants": self.descendants,
+            "tasks": { "done": self.done, "total": self.tasks },
+            "priorities": priorities,
+            "depth": self.depth,
+        })
+    }
+}
+
+impl Context {
+    // Stats for `root` and every node and block under it, in one pass.
+    pub fn stats(&self, root: NodeId) -> HashMap<NodeId, Stats> {
+        let settings = self.document_settings(root);
+        let mut priorities: HashMap<NodeId, usize> = HashMap::new();
+        for (item, priority) in self.extract_priorities(root) {
+            let priority = priority as usize;
+            priorities
+                .entry(item)
+                .and_modify(|p| *p = (*p).min(priority))
+                .or_insert(priority);
+        }
+        let mut stats: HashMap<NodeId, Stats> = HashMap::new();
+        self.stats_rec(root, &settings, &priorities, &mut stats);
+        stats
+    }
+
+    fn stats_rec(
+        &self,
+        node: NodeId,
+        settings: &DocumentSettings,
+        priorities: &HashMap<NodeId, usize>,
+        stats: &mut HashMap<NodeId, Stats>,
+    ) -> Stats {
+        let children: &[NodeId] = match &self.arena[node] {
+            Node::Document { children, .. }
+            | Node::Node { children, .. }
+            | Node::Block { children, .. } => children,
+            Node::Error { .. } => &[],
+        };
+        let mut total = Stats {
+            priorities: vec![0; settings.priorities.len()],
+            ..Stats::default()
+        };
+        for child in children {
+            if self.is_counted(*child, settings) {
+                let below = self.stats_rec(*child, settings, priorities, stats);
+                total.descendants += 1 + below.descendants;
+                total.tasks += below.tasks + self.task_state(*child).is_some() as usize;
+                total.done += below.done + self.is_done(*child) as usize;
+                for (count, below) in total.priorities.iter_mut().zip(&below.priorities) {
+                    *count += below;
+                }
+                if let Some(priority) = priorities.get(child) {
+                    total.priorities[*priority] += 1;
+                }
+                total.depth = total.depth.max(below.depth + 1);
+            }
+        }
+        stats.insert(node, total.clone());
+        total
+    }
+
+    fn is_counted(&self, node: NodeId, settings: &DocumentSettings) -> bool {
+        match &self.arena[node] {
+            Node::Node {
+                content: Some(Content::Content(text)),
+                children,
+                ..
+            } => !children.is_empty() || settings.priority_of(text).is_none(),
+            Node::Node { .. } | Node::Block { .. } => true,
+            _ => false,
+        }
+    }
+
+    // `pretty_print` with each node's stats summary after it, e.g. `Backend [3/7, 2×P0]`.
+    pub fn print_stats(&self, root: NodeId, ctx: &mut PrintContext) -> Result<(), std::io::Error> {
+        let settings = self.document_settings(root);
+        let stats = self.stats(root);
+        self.pretty_print_annotated(root, ctx, &|node| stats.get(&node)?.summary(&settings))
+    }
+
+    // The stats of `root` and everything under it, nested like the outline.
+    pub fn stats_to_json(&self, root: NodeId) -> Value {
+        let settings = self.document_settings(root);
+        let stats = self.stats(root);
+        self.stats_to_json_rec(root, &settings, &stats)
+    }
+
+    fn stats_to_json_rec(
+        &self,
+        node: NodeId,
+        settings: &DocumentSettings,
+        stats: &HashMap<NodeId, Stats>,
+    ) -> Value {
+        let (label, children) = match &self.arena[node] {
+            Node::Document { children, .. } => (None, children),
+            Node::Node { children, .. } | Node::Block { children, .. } => {
+                (Some(self.label(node)), children)
+            }
+            Node::Error { .. } => return Value::Null,
+        };
+        let mut value = stats[&node].to_json(settings);
+        value["label"] = json!(label);
+        value["stable_id"] = json!(self.stable_id(node));
+        value["children"] = Value::Array(
+            children
+                .iter()
+                .filter(|child| self.is_counted(**child, settings))
+                .map(|child| self.stats_to_json_rec(*child, settings, stats))
+                .collect(),
+        );
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::printer::DEFAULT_INDENT_WIDTH;
+    use crate::parser::Parser;
+
+    const TEXT: &str = "\
+# Backend
+[x] api
+    P0
+[ ] db
+    [x] schema
+    [ ] migrate
+        P0
+notes
+    P3
+";
+
+    #[test]
+    fn test_stats() {
+        let parser = Parser::new(String::from(TEXT), tree_sitter_puddlejumper::language());
+        let mut ctx = Context::new();
+        let root = ctx.load_document(&parser).unwrap();
+        let stats = ctx.stats(root);
+        let backend = match &ctx.arena[root] {
+            Node::Document { children, .. } => children[0],
+            _ => panic!(),
+        };
+        assert_eq!(
+            stats[&backend],
+            Stats {
+                descendants: 5,
+                tasks: 4,
+                done: 2,
+                priorities: vec![2, 0, 0, 1, 0],
+                depth: 2,
+            }
+        );
+
+        let mut out: Vec<u8> = Vec::new();
+        ctx.print_stats(
+            root,
+            &mut PrintContext {
+                level: 0,
+                needs_indent: true,
+                indent_width: DEFAULT_INDENT_WIDTH,
+                out: &mut out,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\
+# Backend [2/4, 2×P0, 1×P3]
+    [x] api
+        P0
+    [ ] db [1/2, 1×P0]
+        [x] schema
+        [ ] migrate
+            P0
+    notes
+        P3
+"
+        );
+
+        let json = ctx.stats_to_json(root);
+        assert_eq!(json["children"][0]["label"], "Backend");
+        assert_eq!(
+            json["children"][0]["tasks"],
+            json!({ "done": 2, "total": 4 })
+        );
+        assert_eq!(
+            json["children"][0]["priorities"],
+            json!({ "P0": 2, "P3": 1 })
+        );
+    }
+}
@@ -0,0 +1,51 @@
+use super::*;
+
+// Nodes whose content starts with a checkbox are tasks: `[ ] open` or `[x] done`, as written by
+// the Markdown and OPML importers.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    Open,
+    Done,
+}
+
+pub fn task_state(content: &str) -> Option<TaskState> {
+    let content = content.trim_start();
+    if content.starts_with("[ ]") {
+        Some(TaskState::Open)
+    } else if content.starts_with("[x]") || content.starts_with("[X]") {
+        Some(TaskState::Done)
+    } else {
+        None
+    }
+}
+
+impl Context {
+    pub fn task_state(&self, node: NodeId) -> Option<TaskState> {
+        match &self.arena[node] {
+            Node::Node {
+                content: Some(Content::Content(text)),
+                ..
+            } => task_state(text),
+            Node::Block { header, .. } => self.task_state(*header),
+            _ => None,
+        }
+    }
+
+    pub fn is_done(&self, node: NodeId) -> bool {
+        self.task_state(node) == Some(TaskState::Done)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_state() {
+        assert_eq!(task_state("[ ] api"), Some(TaskState::Open));
+        assert_eq!(task_state("[x] auth"), Some(TaskState::Done));
+        assert_eq!(task_state("[X]"), Some(TaskState::Done));
+        assert_eq!(task_state("api [x]"), None);
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+
+use serde_json::{json, Value};
+
+use super::node::attributes::DocumentSettings;
+use super::node::json::JSON_SCHEMA_VERSION;
+use super::node::{Content, Context, Node, NodeId};
+use super::parser::*;
+use super::tree_diff::UpdateContext;
+
+// A semantic diff between two versions of an outline, for `puddlejumper diff`. Items are matched
+// up with the tree diff, so an edited item shows up as a change rather than a removal and an
+// addition. Priority markers aren't items themselves; they show up as priority changes of the
+// items they mark.
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum OutlineChange {
+    Added {
+        label: String,
+        parent: Option<String>,
+    },
+    Removed {
+        label: String,
+        parent: Option<String>,
+    },
+    TextChanged {
+        from: String,
+        to: String,
+    },
+    Moved {
+        label: String,
+        from: Option<String>,
+        to: Option<String>,
+    },
+    PriorityChanged {
+        label: String,
+        from: Option<String>,
+        to: Option<String>,
+    },
+}
+
+impl OutlineChange {
+    pub fn to_json(&self) -> Value {
+        match self {
+            OutlineChange::Added { label, parent } => {
+                json!({ "kind": "added", "label": label, "parent": parent })
+            }
+            OutlineChange::Removed { label, parent } => {
+                json!({ "kind": "removed", "label": label, "parent": parent })
+            }
+            OutlineChange::TextChanged { from, to } => {
+                json!({ "kind": "text_changed", "from": from, "to": to })
+            }
+            OutlineChange::Moved { label, from, to } => {
+                json!({ "kind": "moved", "label": label, "from": from, "to": to })
+            }
+            OutlineChange::PriorityChanged { label, from, to } => {
+                json!({ "kind": "priority_changed", "label": label, "from": from, "to": to })
+            }
+        }
+    }
+}
+
+fn parent_name(parent: &Option<String>) -> String {
+    match parent {
+        Some(parent) => format!("{:?}", parent),
+        None => String::from("the top level"),
+    }
+}
+
+impl fmt::Display for OutlineChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutlineChange::Added { label, parent } => {
+                write!(f, "+ added {:?} under {}", label, parent_name(parent))
+            }
+            OutlineChange::Removed { label, parent } => {
+                write!(f, "- removed {:?} from {}", label, parent_name(parent))
+            }
+            OutlineChange::TextChanged { from, to } => {
+                write!(f, "~ changed {:?} to {:?}", from, to)
+            }
+            OutlineChange::Moved { label, from, to } if from == to => {
+                write!(f, "> moved {:?} within {}", label, parent_name(to))
+            }
+            OutlineChange::Moved { label, from, to } => write!(
+                f,
+                "> moved {:?} from {} to {}",
+                label,
+                parent_name(from),
+                parent_name(to)
+            ),
+            OutlineChange::PriorityChanged { label, from, to } => write!(
+                f,
+                "! priority of {:?} changed from {} to {}",
+                label,
+                from.as_deref().unwrap_or("none"),
+                to.as_deref().unwrap_or("none")
+            ),
+        }
+    }
+}
+
+// `{ "version": 1, "changes": [...] }`
+pub fn changes_to_json(changes: &[OutlineChange]) -> Value {
+    json!({
+        "version": JSON_SCHEMA_VERSION,
+        "changes": changes.iter().map(OutlineChange::to_json).collect::<Vec<Value>>(),
+    })
+}
+
+#[derive(Debug)]
+struct Item {
+    range: Range<usize>,
+    label: String,
+    // Index of the enclosing item.
+    parent: Option<usize>,
+    priority: Option<String>,
+}
+
+fn outline_items(ctx: &Context, root: NodeId) -> Vec<Item> {
+    let settings = ctx.document_settings(root);
+    let priorities: HashMap<NodeId, String> = ctx
+        .extract_priorities(root)
+        .into_iter()
+        .map(|(node, p)| (node, settings.priorities[p as usize].clone()))
+        .collect();
+    let mut items: Vec<Item> = Vec::new();
+    outline_items_rec(ctx, root, None, &settings, &priorities, &mut items);
+    items
+}
+
+fn outline_items_rec(
+    ctx: &Context,
+    node: NodeId,
+    parent: Option<usize>,
+    settings: &DocumentSettings,
+    priorities: &HashMap<NodeId, String>,
+    items: &mut Vec<Item>,
+) {
+    let (label, children) = match &ctx.arena[node] {
+        Node::Document { children, .. } => (None, children),
+        Node::Node {
+            content: Some(Content::Content(content)),
+            children,
+            ..
+        } if settings.priority_of(content).is_some() => (None, children),
+        Node::Node { children, .. } | Node::Block { children, .. } => {
+            (Some(ctx.label(node)), children)
+        }
+        Node::Error { .. } => return,
+    };
+    let parent = match (label, ctx.source(node)) {
+        (Some(label), Some(source)) => {
+            items.push(Item {
+                range: source.range.byte_range(),
+                label,
+                parent,
+                priority: priorities.get(&node).cloned(),
+            });
+            Some(items.len() - 1)
+        }
+        _ => parent,
+    };
+    for child in children {
+        outline_items_rec(ctx, *child, parent, settings, priorities, items);
+    }
+}
+
+// Diffs `old_text` against `new_text`, running the new text through `Parser::update`.
+pub fn diff_outlines(old_text: &str, new_text: &str) -> Vec<OutlineChange> {
+    let mut parser = Parser::new(old_text.to_string(), tree_sitter_puddlejumper::language());
+    let (old_ctx, old_root) = match Context::from_parser(&parser) {
+        Some(loaded) => loaded,
+        None => return vec![],
+    };
+    let update = parser.update(new_text.to_string());
+    let matched = {
+        let update_context = UpdateContext::new(&update);
+        let mut matched = update_context.matched_ranges("node");
+        matched.extend(update_context.matched_ranges("block"));
+        matched
+    };
+    parser.apply_update(update);
+    let (new_ctx, new_root) = match Context::from_parser(&parser) {
+        Some(loaded) => loaded,
+        None => return vec![],
+    };
+    diff_items(
+        &outline_items(&old_ctx, old_root),
+        &outline_items(&new_ctx, new_root),
+        &matched,
+    )
+}
+
+fn diff_items(
+    old: &[Item],
+    new: &[Item],
+    matched: &HashMap<Range<usize>, Range<usize>>,
+) -> Vec<OutlineChange> {
+    let new_by_range: HashMap<&Range<usize>, usize> = new
+        .iter()
+        .enumerate()
+        .map(|(index, item)| (&item.range, index))
+        .collect();
+    // Index of the new item that each old item became.
+    let old_to_new: Vec<Option<usize>> = old
+        .iter()
+        .map(|item| {
+            matched
+                .get(&item.range)
+                .and_then(|range| new_by_range.get(range).copied())
+        })
+        .collect();
+    let mut kept: Vec<bool> = vec![false; new.len()];
+    for index in old_to_new.iter().flatten() {
+        kept[*index] = true;
+    }
+
+    let label = |items: &[Item], index: Option<usize>| index.map(|i| items[i].label.clone());
+    let mut changes: Vec<OutlineChange> = Vec::new();
+    let mut removed: Vec<usize> = Vec::new();
+    for (old_index, old_item) in old.iter().enumerate() {
+        let new_index = match old_to_new[old_index] {
+            Some(new_index) => new_index,
+            None => {
+                removed.push(old_index);
+                continue;
+            }
+        };
+        let new_item = &new[new_index];
+        if old_item.label != new_item.label {
+            changes.push(OutlineChange::TextChanged {
+                from: old_item.label.clone(),
+                to: new_item.label.clone(),
+            });
+        }
+        let same_parent = match (old_item.parent, new_item.parent) {
+            (Some(old_parent), new_parent) => old_to_new[old_parent] == new_parent,
+            (None, new_parent) => new_parent.is_none(),
+        };
+        if !same_parent {
+            changes.push(OutlineChange::Moved {
+                label: new_item.label.clone(),
+                from: label(old, old_item.parent),
+                to: label(new, new_item.parent),
+            });
+        }
+        if old_item.priority != new_item.priority {
+            changes.push(OutlineChange::PriorityChanged {
+                label: new_item.label.clone(),
+                from: old_item.priority.clone(),
+                to: new_item.priority.clone(),
+            });
+        }
+    }
+
+    // The tree diff aligns siblings in order, so an item that moved shows up as removed in one
+    // place and added in another. Pair those up by label.
+    let mut added: Vec<usize> = (0..new.len()).filter(|index| !kept[*index]).collect();
+    for old_index in removed {
+        let old_item = &old[old_index];
+        match added
+            .iter()
+            .position(|new_index| new[*new_index].label == old_item.label)
+        {
+            Some(position) => {
+                let new_item = &new[added.remove(position)];
+                changes.push(OutlineChange::Moved {
+                    label: new_item.label.clone(),
+                    from: label(old, old_item.parent),
+                    to: label(new, new_item.parent),
+                });
+                if old_item.priority != new_item.priority {
+                    changes.push(OutlineChange::PriorityChanged {
+                        label: new_item.label.clone(),
+                        from: old_item.priority.clone(),
+                        to: new_item.priority.clone(),
+                    });
+                }
+            }
+            None => changes.push(OutlineChange::Removed {
+                label: old_item.label.clone(),
+                parent: label(old, old_item.parent),
+            }),
+        }
+    }
+    for new_index in added {
+        changes.push(OutlineChange::Added {
+            label: new[new_index].label.clone(),
+            parent: label(new, new[new_index].parent),
+        });
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(old: &str, new: &str) -> Vec<String> {
+        diff_outlines(old, new)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_outlines() {
+        assert_eq!(diff("hello\nworld", "hello\nworld"), Vec::<String>::new());
+        assert_eq!(
+            diff("hello\nworld", "hello\nwarld"),
+            vec![r#"~ changed "world" to "warld""#]
+        );
+        assert_eq!(
+            diff("hello\nworld", "hello\n    foo\nworld"),
+            vec![r#"+ added "foo" under "hello""#]
+        );
+        assert_eq!(
+            diff("hello\n    foo\nworld", "hello\nworld"),
+            vec![r#"- removed "foo" from "hello""#]
+        );
+        assert_eq!(
+            diff("hello\n    P1\nworld", "hello\n    P0\nworld"),
+            vec![r#"! priority of "hello" changed from P1 to P0"#]
+        );
+        assert_eq!(
+            diff("a\n    x\nb", "a\nb\n    x"),
+            vec![r#"> moved "x" from "a" to "b""#]
+        );
+    }
+
+    #[test]
+    fn test_changes_to_json() {
+        let changes = vec![OutlineChange::Added {
+            label: String::from("foo"),
+            parent: None,
+        }];
+        assert_eq!(
+            changes_to_json(&changes),
+            json!({
+                "version": JSON_SCHEMA_VERSION,
+                "changes": [{ "kind": "added", "label": "foo", "parent": null }],
+            })
+        );
+    }
+}
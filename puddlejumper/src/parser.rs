@@ -37,10 +37,15 @@ impl Parser {
     }
 
     pub fn update(&mut self, text_new: String) -> Update {
+        let diff = text_diff::compute_diff(self.text.as_str(), text_new.as_str());
+        self.update_with_diff(text_new, diff)
+    }
+
+    // Like `update`, for callers that already know the edits, e.g. `History` replaying a patch.
+    pub fn update_with_diff(&mut self, text_new: String, diff: text_diff::Diff) -> Update {
         let text_old = self.text.clone();
         let tree_old = self.tree.clone();
         let mut tree_new = self.tree.clone();
-        let diff = text_diff::compute_diff(text_old.as_str(), text_new.as_str());
 
         for change in &diff.changes {
             let text_intermediate = format!(
@@ -65,6 +70,13 @@ impl Parser {
         }
     }
 
+    // Applies edits from the structural editing commands on `Context`, see `node::edit`.
+    pub fn edit(&mut self, edits: &[text_diff::TextEdit]) -> Update {
+        let text_new = text_diff::apply_edits(&self.text, edits);
+        let diff = text_diff::Diff::from_edits(&self.text, edits);
+        self.update_with_diff(text_new, diff)
+    }
+
     pub fn apply_update(&mut self, update: Update) {
         self.text = update.new_text;
         self.tree = update.new_tree;
@@ -0,0 +1,630 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+
+use serde_json::{json, Value};
+
+use super::node::attributes::DocumentSettings;
+use super::node::{Context, NodeId};
+use super::parser::*;
+use super::tree_diff::{OpF, UpdateContext};
+
+// The prioritized projection of a document, kept up to date from the tree diff of each update
+// instead of being rebuilt from scratch. Every item remembers the node whose children decided its
+// priority, i.e. the parent of its priority marker. An update carries unchanged items over through
+// the matched nodes, and re-extracts only the subtrees around the nodes the edit script inserted,
+// deleted or changed. Retyping one `P2` marker as `P0` moves one item between buckets. What changed
+// comes out as `ViewChange`s, so a UI can patch its rendering instead of redrawing it.
+
+pub type ViewId = usize;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ViewItem {
+    // Stays the same for as long as the item is in the view, across edits and priority changes.
+    pub id: ViewId,
+    pub range: Range<usize>,
+    // The node that the item's priority marker is a child of.
+    pub decider: Range<usize>,
+    pub label: String,
+    pub priority: usize,
+}
+
+// To apply a list of changes, first take the removed and moved items out of their buckets, then
+// relabel, then put the moved and inserted items in at `index`, in order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ViewChange {
+    Inserted {
+        id: ViewId,
+        priority: String,
+        index: usize,
+        label: String,
+    },
+    Removed {
+        id: ViewId,
+        priority: String,
+    },
+    // Either to another bucket, or to another place in the same one.
+    Moved {
+        id: ViewId,
+        from: String,
+        to: String,
+        index: usize,
+    },
+    Relabeled {
+        id: ViewId,
+        label: String,
+    },
+}
+
+impl fmt::Display for ViewChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ViewChange::Inserted {
+                id,
+                priority,
+                index,
+                label,
+            } => write!(f, "+ #{} [{}:{}] {}", id, priority, index, label),
+            ViewChange::Removed { id, priority } => write!(f, "- #{} [{}]", id, priority),
+            ViewChange::Moved {
+                id,
+                from,
+                to,
+                index,
+            } => write!(f, "~ #{} [{} -> {}:{}]", id, from, to, index),
+            ViewChange::Relabeled { id, label } => write!(f, "= #{} {}", id, label),
+        }
+    }
+}
+
+impl ViewChange {
+    pub fn to_json(&self) -> Value {
+        match self {
+            ViewChange::Inserted {
+                id,
+                priority,
+                index,
+                label,
+            } => json!({
+                "type": "inserted",
+                "id": id,
+                "priority": priority,
+                "index": index,
+                "label": label,
+            }),
+            ViewChange::Removed { id, priority } => json!({
+                "type": "removed",
+                "id": id,
+                "priority": priority,
+            }),
+            ViewChange::Moved {
+                id,
+                from,
+                to,
+                index,
+            } => json!({
+                "type": "moved",
+                "id": id,
+                "from": from,
+                "to": to,
+                "index": index,
+            }),
+            ViewChange::Relabeled { id, label } => json!({
+                "type": "relabeled",
+                "id": id,
+                "label": label,
+            }),
+        }
+    }
+
+    // Removals and relabels first, then insertions and moves by where they end up.
+    fn order(&self, settings: &DocumentSettings) -> (usize, usize, usize) {
+        let bucket = |priority: &str| settings.priority_of(priority).unwrap_or(usize::MAX);
+        match self {
+            ViewChange::Removed { .. } => (0, 0, 0),
+            ViewChange::Relabeled { .. } => (1, 0, 0),
+            ViewChange::Inserted {
+                priority, index, ..
+            } => (2, bucket(priority), *index),
+            ViewChange::Moved { to, index, .. } => (2, bucket(to), *index),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PrioritizedView {
+    pub settings: DocumentSettings,
+    // In document order.
+    items: Vec<ViewItem>,
+    next_id: ViewId,
+}
+
+// An item as extracted, before it has an id.
+struct Extracted {
+    range: Range<usize>,
+    decider: Range<usize>,
+    label: String,
+    priority: usize,
+}
+
+impl PrioritizedView {
+    pub fn new(parser: &Parser) -> Option<PrioritizedView> {
+        let (context, root) = Context::from_parser(parser)?;
+        let settings = context.document_settings(root);
+        let mut view = PrioritizedView {
+            items: Vec::new(),
+            next_id: 0,
+            settings,
+        };
+        for extracted in extract(&context, root, &view.settings) {
+            let id = view.next_id();
+            view.items.push(ViewItem {
+                id,
+                range: extracted.range,
+                decider: extracted.decider,
+                label: extracted.label,
+                priority: extracted.priority,
+            });
+        }
+        view.items.sort_by_key(|item| item.range.start);
+        Some(view)
+    }
+
+    pub fn items(&self) -> &[ViewItem] {
+        &self.items
+    }
+
+    // The non-empty buckets, from highest to lowest priority, like `make_prioritized_list`.
+    pub fn buckets(&self) -> Vec<(&str, Vec<&ViewItem>)> {
+        self.settings
+            .priorities
+            .iter()
+            .enumerate()
+            .map(|(priority, name)| {
+                let items: Vec<&ViewItem> = self
+                    .items
+                    .iter()
+                    .filter(|item| item.priority == priority)
+                    .collect();
+                (name.as_str(), items)
+            })
+            .filter(|(_, items)| !items.is_empty())
+            .collect()
+    }
+
+    // Applies an update from `Parser::update` to `parser`, and to the view.
+    pub fn apply(&mut self, parser: &mut Parser, update: Update) -> Vec<ViewChange> {
+        let (matched, regions) = {
+            let context = UpdateContext::new(&update);
+            let matched = context.matched_ranges("node");
+            let regions = dirty_regions(&context, &matched);
+            (matched, regions)
+        };
+        parser.apply_update(update);
+
+        let before: HashMap<ViewId, (String, usize)> = self.positions();
+        let old_items = std::mem::take(&mut self.items);
+        let mut removed: Vec<ViewItem> = Vec::new();
+        let mut carried: Vec<ViewItem> = Vec::new();
+        for item in old_items {
+            match (matched.get(&item.range), matched.get(&item.decider)) {
+                (Some(range), Some(decider)) => carried.push(ViewItem {
+                    range: range.clone(),
+                    decider: decider.clone(),
+                    ..item
+                }),
+                _ => removed.push(item),
+            }
+        }
+
+        // Re-extract the dirty regions, or the whole document.
+        let mut context = Context::new();
+        let mut fresh: Vec<Extracted> = Vec::new();
+        match &regions {
+            Some(regions) => {
+                for region in regions {
+                    let t_node = match node_at(&parser.tree, region) {
+                        Some(t_node) => t_node,
+                        None => continue,
+                    };
+                    if let Some(id) = context.load(&t_node, parser) {
+                        fresh.extend(extract(&context, id, &self.settings));
+                    }
+                }
+            }
+            None => {
+                if let Some(root) = context.load_document(parser) {
+                    self.settings = context.document_settings(root);
+                    fresh = extract(&context, root, &self.settings);
+                }
+            }
+        }
+        let in_regions = |range: &Range<usize>| match &regions {
+            Some(regions) => regions
+                .iter()
+                .any(|region| region.start <= range.start && range.end <= region.end),
+            None => true,
+        };
+        let (replaced, kept): (Vec<ViewItem>, Vec<ViewItem>) = carried
+            .into_iter()
+            .partition(|item| in_regions(&item.decider));
+
+        // Fresh items that were already in the view keep their id.
+        let mut seen: HashSet<Range<usize>> = kept.iter().map(|item| item.range.clone()).collect();
+        let mut replaced: HashMap<Range<usize>, ViewItem> = replaced
+            .into_iter()
+            .map(|item| (item.range.clone(), item))
+            .collect();
+        let mut changes: Vec<ViewChange> = Vec::new();
+        self.items = kept;
+        for extracted in fresh {
+            // An item under several markers is only listed once.
+            if !seen.insert(extracted.range.clone()) {
+                continue;
+            }
+            let id = match replaced.remove(&extracted.range) {
+                Some(old) => {
+                    if old.label != extracted.label {
+                        changes.push(ViewChange::Relabeled {
+                            id: old.id,
+                            label: extracted.label.clone(),
+                        });
+                    }
+                    old.id
+                }
+                None => self.next_id(),
+            };
+            self.items.push(ViewItem {
+                id,
+                range: extracted.range,
+                decider: extracted.decider,
+                label: extracted.label,
+                priority: extracted.priority,
+            });
+        }
+        removed.extend(replaced.into_values());
+        self.items.sort_by_key(|item| item.range.start);
+
+        for item in &removed {
+            changes.push(ViewChange::Removed {
+                id: item.id,
+                priority: before[&item.id].0.clone(),
+            });
+        }
+        changes.extend(self.moves(&before));
+        changes.sort_by_key(|change| change.order(&self.settings));
+        changes
+    }
+
+    fn next_id(&mut self) -> ViewId {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn priority_name(&self, priority: usize) -> String {
+        self.settings
+            .priorities
+            .get(priority)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // The bucket and index in the bucket of every item. Buckets go by name, since the front matter
+    // can reorder the priorities.
+    fn positions(&self) -> HashMap<ViewId, (String, usize)> {
+        let mut counts: HashMap<usize, usize> = HashMap::new();
+        self.items
+            .iter()
+            .map(|item| {
+                let index = counts.entry(item.priority).or_insert(0);
+                *index += 1;
+                (item.id, (self.priority_name(item.priority), *index - 1))
+            })
+            .collect()
+    }
+
+    // Insertions, and moves of items that changed buckets or order within their bucket, going by
+    // the positions from before the update.
+    fn moves(&self, before: &HashMap<ViewId, (String, usize)>) -> Vec<ViewChange> {
+        let after = self.positions();
+        let mut changes: Vec<ViewChange> = Vec::new();
+        let mut stayed: HashMap<&str, Vec<(ViewId, usize)>> = HashMap::new();
+        for item in &self.items {
+            let (priority, index) = &after[&item.id];
+            match before.get(&item.id) {
+                None => changes.push(ViewChange::Inserted {
+                    id: item.id,
+                    priority: priority.clone(),
+                    index: *index,
+                    label: item.label.clone(),
+                }),
+                Some((old_priority, _)) if old_priority != priority => {
+                    changes.push(ViewChange::Moved {
+                        id: item.id,
+                        from: old_priority.clone(),
+                        to: priority.clone(),
+                        index: *index,
+                    })
+                }
+                Some((_, old_index)) => stayed
+                    .entry(priority.as_str())
+                    .or_default()
+                    .push((item.id, *old_index)),
+            }
+        }
+        // Items that stay in their bucket keep their place, except for the fewest that have to move
+        // to get the new order.
+        for (priority, items) in stayed {
+            let old_indexes: Vec<usize> = items.iter().map(|(_, index)| *index).collect();
+            let in_place = longest_increasing(&old_indexes);
+            for (i, (id, _)) in items.iter().enumerate() {
+                if !in_place.contains(&i) {
+                    changes.push(ViewChange::Moved {
+                        id: *id,
+                        from: priority.to_string(),
+                        to: priority.to_string(),
+                        index: after[id].1,
+                    });
+                }
+            }
+        }
+        changes
+    }
+}
+
+fn extract(context: &Context, node: NodeId, settings: &DocumentSettings) -> Vec<Extracted> {
+    context
+        .extract_priorities_with(node, settings)
+        .into_iter()
+        .filter_map(|(item, decider, priority)| {
+            Some(Extracted {
+                range: context.source(item)?.range.byte_range(),
+                decider: context.source(decider)?.range.byte_range(),
+                label: context.label(item),
+                priority: priority as usize,
+            })
+        })
+        .collect()
+}
+
+// The ranges of the new tree to re-extract, none of them inside another, or `None` if the whole
+// document has to be.
+fn dirty_regions(
+    context: &UpdateContext,
+    matched: &HashMap<Range<usize>, Range<usize>>,
+) -> Option<Vec<Range<usize>>> {
+    let mut regions: Vec<Range<usize>> = Vec::new();
+    for op in context.get_root_change_path()? {
+        match &**op {
+            // `Update`s are just on the way to the changes under them.
+            OpF::UpdateScalar { new, .. } | OpF::Insert { new } | OpF::InsertSubtree { new } => {
+                if let Some(region) = affected(*new)? {
+                    regions.push(region.byte_range());
+                }
+            }
+            OpF::Delete { old } | OpF::DeleteSubtree { old } => {
+                if let Some(region) = affected(*old)? {
+                    regions.push(matched.get(&region.byte_range())?.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+    regions.sort_by_key(|region| (region.start, std::cmp::Reverse(region.end)));
+    let mut outer: Vec<Range<usize>> = Vec::new();
+    for region in regions {
+        match outer.last() {
+            Some(last) if last.start <= region.start && region.end <= last.end => {}
+            _ => outer.push(region),
+        }
+    }
+    Some(outer)
+}
+
+// The subtree whose priorities can depend on `t_node`. A node's priority is decided by its parent
+// or grandparent, so that is up to the grandparent of the nearest node. `Some(None)` if no
+// priority can depend on it, e.g. inside a block, and `None` if the whole document has to be
+// re-extracted, e.g. for a change to the settings in the front matter.
+fn affected(t_node: tree_sitter::Node) -> Option<Option<tree_sitter::Node>> {
+    let mut nodes: Vec<tree_sitter::Node> = Vec::new();
+    let mut current = Some(t_node);
+    while let Some(n) = current {
+        match n.kind() {
+            "node" => nodes.push(n),
+            // A new or removed block takes nodes in or out of the projection.
+            "block" if n == t_node => return None,
+            "block" => return Some(None),
+            "front_matter" => return None,
+            "document" if n == t_node => return None,
+            _ => {}
+        }
+        current = n.parent();
+    }
+    if nodes.is_empty() {
+        return Some(None);
+    }
+    Some(Some(nodes[nodes.len().min(3) - 1]))
+}
+
+fn node_at<'a>(tree: &'a tree_sitter::Tree, range: &Range<usize>) -> Option<tree_sitter::Node<'a>> {
+    let mut current = tree
+        .root_node()
+        .descendant_for_byte_range(range.start, range.end);
+    while let Some(n) = current {
+        if n.kind() == "node" && n.byte_range() == *range {
+            return Some(n);
+        }
+        current = n.parent();
+    }
+    None
+}
+
+// Indexes into `values` of a longest strictly increasing subsequence.
+fn longest_increasing(values: &[usize]) -> HashSet<usize> {
+    // `tails[k]` is the index of the smallest last value of an increasing run of length k + 1.
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous: Vec<Option<usize>> = vec![None; values.len()];
+    for (i, value) in values.iter().enumerate() {
+        let k = tails.partition_point(|&tail| values[tail] < *value);
+        previous[i] = k.checked_sub(1).map(|k| tails[k]);
+        if k == tails.len() {
+            tails.push(i);
+        } else {
+            tails[k] = i;
+        }
+    }
+    let mut indexes: HashSet<usize> = HashSet::new();
+    let mut current = tails.last().copied();
+    while let Some(i) = current {
+        indexes.insert(i);
+        current = previous[i];
+    }
+    indexes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Applies `changes` to `buckets` the way a UI would.
+    fn patch(buckets: &mut Vec<(String, Vec<(ViewId, String)>)>, changes: &[ViewChange]) {
+        let mut taken: HashMap<ViewId, String> = HashMap::new();
+        let mut take = |buckets: &mut Vec<(String, Vec<(ViewId, String)>)>, id: ViewId| {
+            for (_, items) in buckets.iter_mut() {
+                if let Some(index) = items.iter().position(|(other, _)| *other == id) {
+                    taken.insert(id, items.remove(index).1);
+                }
+            }
+        };
+        for change in changes {
+            match change {
+                ViewChange::Removed { id, .. } | ViewChange::Moved { id, .. } => take(buckets, *id),
+                _ => {}
+            }
+        }
+        for change in changes {
+            let (id, priority, index, label) = match change {
+                ViewChange::Relabeled { id, label } => {
+                    for (_, items) in buckets.iter_mut() {
+                        for item in items.iter_mut().filter(|(other, _)| other == id) {
+                            item.1 = label.clone();
+                        }
+                    }
+                    continue;
+                }
+                ViewChange::Removed { .. } => continue,
+                ViewChange::Inserted {
+                    id,
+                    priority,
+                    index,
+                    label,
+                } => (id, priority, index, label.clone()),
+                ViewChange::Moved { id, to, index, .. } => (id, to, index, taken[id].clone()),
+            };
+            let bucket = match buckets.iter().position(|(name, _)| name == priority) {
+                Some(bucket) => bucket,
+                None => {
+                    buckets.push((priority.clone(), Vec::new()));
+                    buckets.len() - 1
+                }
+            };
+            buckets[bucket].1.insert(*index, (*id, label));
+        }
+        buckets.retain(|(_, items)| !items.is_empty());
+        buckets.sort_by_key(|(name, _)| name.clone());
+    }
+
+    // The buckets by name, as `patch` leaves them.
+    fn snapshot(view: &PrioritizedView) -> Vec<(String, Vec<(ViewId, String)>)> {
+        let mut buckets = view
+            .buckets()
+            .into_iter()
+            .map(|(name, items)| {
+                let items = items
+                    .into_iter()
+                    .map(|item| (item.id, item.label.clone()))
+                    .collect();
+                (name.to_string(), items)
+            })
+            .collect::<Vec<_>>();
+        buckets.sort_by_key(|(name, _)| name.clone());
+        buckets
+    }
+
+    // Runs `texts` through a view, checking after each update that the changes patch the old
+    // buckets into the new ones, and that the view matches one built from scratch.
+    fn run(texts: &[&str]) -> Vec<Vec<ViewChange>> {
+        let mut parser = Parser::new(String::from(texts[0]), tree_sitter_puddlejumper::language());
+        let mut view = PrioritizedView::new(&parser).unwrap();
+        let mut all: Vec<Vec<ViewChange>> = Vec::new();
+        for text in &texts[1..] {
+            let mut buckets = snapshot(&view);
+            let update = parser.update(String::from(*text));
+            let changes = view.apply(&mut parser, update);
+            patch(&mut buckets, &changes);
+            assert_eq!(buckets, snapshot(&view), "{}", text);
+
+            let fresh = PrioritizedView::new(&parser).unwrap();
+            let summary = |view: &PrioritizedView| -> Vec<(Range<usize>, String, usize)> {
+                view.items()
+                    .iter()
+                    .map(|item| (item.range.clone(), item.label.clone(), item.priority))
+                    .collect()
+            };
+            assert_eq!(summary(&view), summary(&fresh), "{}", text);
+            all.push(changes);
+        }
+        all
+    }
+
+    #[test]
+    fn test_longest_increasing() {
+        assert_eq!(longest_increasing(&[]), HashSet::new());
+        assert_eq!(longest_increasing(&[0, 3, 1, 2]), HashSet::from([0, 2, 3]));
+    }
+
+    #[test]
+    fn test_change_marker() {
+        let changes = run(&[
+            "a\n    P1\nb\n    P2\nc\n    P2",
+            "a\n    P1\nb\n    P0\nc\n    P2",
+        ]);
+        assert_eq!(
+            changes[0],
+            vec![ViewChange::Moved {
+                id: 2,
+                from: String::from("P2"),
+                to: String::from("P0"),
+                index: 0,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_edits() {
+        let changes = run(&[
+            "a\n    P1\nb\n    P2",
+            "a!\n    P1\nb\n    P2",
+            "a!\n    P1\nb\n    P2\nc\n    P1",
+            "a!\n    P1\nc\n    P1",
+            "a!\n    P1\n        x\n        y\nc\n    P1",
+            "---\npriorities: P1, P0\n---\na!\n    P1\nc\n    P1",
+            "# block\na!\n    P1\nc\n    P1",
+        ]);
+        assert_eq!(
+            changes[0],
+            vec![ViewChange::Relabeled {
+                id: 1,
+                label: String::from("a!"),
+            }]
+        );
+        assert!(matches!(
+            changes[1][..],
+            [ViewChange::Inserted { index: 1, .. }]
+        ));
+        assert!(matches!(
+            changes[2][..],
+            [ViewChange::Removed { id: 2, .. }]
+        ));
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use super::node::attributes::DocumentSettings;
+use super::parser::*;
+
+#[cfg(feature = "native")]
+use tree_sitter;
+
+#[cfg(feature = "wasm")]
+use tree_sitter_c2rust as tree_sitter;
+
+// The queries shipped with tree-sitter-puddlejumper, compiled once and reused across parses.
+pub struct Queries {
+    pub highlights: tree_sitter::Query,
+    pub locals: tree_sitter::Query,
+    pub tags: tree_sitter::Query,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SemanticTokenType {
+    Binding,
+    Ref,
+    Header,
+    Priority,
+    Attribute,
+}
+
+impl SemanticTokenType {
+    pub fn from_capture_name(name: &str) -> Option<Self> {
+        match name {
+            "label" => Some(SemanticTokenType::Binding),
+            "variable" => Some(SemanticTokenType::Ref),
+            "markup.heading" => Some(SemanticTokenType::Header),
+            "property" => Some(SemanticTokenType::Attribute),
+            _ => None,
+        }
+    }
+
+    // Name used in an editor's semantic token legend.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SemanticTokenType::Binding => "binding",
+            SemanticTokenType::Ref => "ref",
+            SemanticTokenType::Header => "header",
+            SemanticTokenType::Priority => "priority",
+            SemanticTokenType::Attribute => "attribute",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SemanticToken {
+    pub token_type: SemanticTokenType,
+    pub byte_range: Range<usize>,
+    pub start: tree_sitter::Point,
+    pub end: tree_sitter::Point,
+}
+
+// A `@name` reference, and the identifier of the `@name:` binding it resolves to.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Reference {
+    pub name: String,
+    pub byte_range: Range<usize>,
+    pub definition: Option<Range<usize>>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Tag {
+    pub name: String,
+    // e.g. "section" or "item", from `@definition.section` or `@reference.item`.
+    pub kind: String,
+    pub is_definition: bool,
+    pub byte_range: Range<usize>,
+    pub name_range: Range<usize>,
+}
+
+impl Queries {
+    pub fn new() -> Result<Self, tree_sitter::QueryError> {
+        let language = tree_sitter_puddlejumper::language();
+        Ok(Queries {
+            highlights: tree_sitter::Query::new(
+                language,
+                tree_sitter_puddlejumper::HIGHLIGHTS_QUERY,
+            )?,
+            locals: tree_sitter::Query::new(language, tree_sitter_puddlejumper::LOCALS_QUERY)?,
+            tags: tree_sitter::Query::new(language, tree_sitter_puddlejumper::TAGS_QUERY)?,
+        })
+    }
+}
+
+impl Parser {
+    // Non-overlapping highlight spans in document order. When several patterns capture the same
+    // node, the one listed first in highlights.scm wins. Content is a priority marker if it is one
+    // of `settings.priorities`, see `Context::document_settings`.
+    pub fn semantic_tokens(
+        &self,
+        queries: &Queries,
+        settings: &DocumentSettings,
+    ) -> Vec<SemanticToken> {
+        let query = &queries.highlights;
+        let mut cursor = tree_sitter::QueryCursor::new();
+        let mut tokens: Vec<SemanticToken> = Vec::new();
+        for (m, index) in cursor.captures(query, self.tree.root_node(), self.text.as_bytes()) {
+            let capture = m.captures[index];
+            let name = &query.capture_names()[capture.index as usize];
+            let node = capture.node;
+            let is_priority = || settings.priority_of(self.get_text(node)).is_some();
+            let token_type = match SemanticTokenType::from_capture_name(name) {
+                Some(token_type) => token_type,
+                None if name == "content" && is_priority() => SemanticTokenType::Priority,
+                None => continue,
+            };
+            if let Some(last) = tokens.last() {
+                if node.start_byte() < last.byte_range.end {
+                    continue;
+                }
+            }
+            tokens.push(SemanticToken {
+                token_type,
+                byte_range: node.byte_range(),
+                start: node.start_position(),
+                end: node.end_position(),
+            });
+        }
+        tokens
+    }
+
+    // Resolves every `@name` reference against the `@name:` bindings in its scope. A reference
+    // resolves to the closest preceding binding, falling back to the first one after it.
+    pub fn references(&self, queries: &Queries) -> Vec<Reference> {
+        let query = &queries.locals;
+        let mut cursor = tree_sitter::QueryCursor::new();
+        let mut definitions: HashMap<String, Vec<Range<usize>>> = HashMap::new();
+        let mut references: Vec<Reference> = Vec::new();
+        for m in cursor.matches(query, self.tree.root_node(), self.text.as_bytes()) {
+            for capture in m.captures {
+                let node = capture.node;
+                let name = self.get_text(node).to_string();
+                match query.capture_names()[capture.index as usize].as_str() {
+                    "local.definition" => {
+                        definitions.entry(name).or_default().push(node.byte_range());
+                    }
+                    "local.reference" => references.push(Reference {
+                        name,
+                        byte_range: node.byte_range(),
+                        definition: None,
+                    }),
+                    _ => {}
+                }
+            }
+        }
+        for reference in references.iter_mut() {
+            reference.definition = definitions.get(&reference.name).and_then(|ranges| {
+                ranges
+                    .iter()
+                    .rev()
+                    .find(|range| range.start < reference.byte_range.start)
+                    .or(ranges.first())
+                    .cloned()
+            });
+        }
+        references
+    }
+
+    pub fn tags(&self, queries: &Queries) -> Vec<Tag> {
+        let query = &queries.tags;
+        let mut cursor = tree_sitter::QueryCursor::new();
+        let mut tags: Vec<Tag> = Vec::new();
+        for m in cursor.matches(query, self.tree.root_node(), self.text.as_bytes()) {
+            let mut name_node = None;
+            let mut tag_node = None;
+            for capture in m.captures {
+                let capture_name = &query.capture_names()[capture.index as usize];
+                if capture_name == "name" {
+                    name_node = Some(capture.node);
+                } else {
+                    tag_node = Some((capture_name.as_str(), capture.node));
+                }
+            }
+            if let (Some(name_node), Some((capture_name, node))) = (name_node, tag_node) {
+                let (is_definition, kind) = match capture_name.split_once('.') {
+                    Some(("definition", kind)) => (true, kind),
+                    Some(("reference", kind)) => (false, kind),
+                    _ => continue,
+                };
+                tags.push(Tag {
+                    name: self.get_text(name_node).to_string(),
+                    kind: kind.to_string(),
+                    is_definition,
+                    byte_range: node.byte_range(),
+                    name_range: name_node.byte_range(),
+                });
+            }
+        }
+        tags.sort_by_key(|tag| (tag.byte_range.start, tag.name_range.start));
+        tags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token_texts<'a>(
+        parser: &'a Parser,
+        tokens: &[SemanticToken],
+    ) -> Vec<(SemanticTokenType, &'a str)> {
+        tokens
+            .iter()
+            .map(|token| (token.token_type, &parser.text[token.byte_range.clone()]))
+            .collect()
+    }
+
+    #[test]
+    fn test_semantic_tokens() {
+        let code = String::from("# Backend\n@api: API\n    P1\n    see @api");
+        let parser = Parser::new(code, tree_sitter_puddlejumper::language());
+        let queries = Queries::new().unwrap();
+        let tokens = parser.semantic_tokens(&queries, &DocumentSettings::default());
+        assert_eq!(
+            token_texts(&parser, &tokens),
+            vec![
+                (SemanticTokenType::Header, "Backend"),
+                (SemanticTokenType::Binding, "@api:"),
+                (SemanticTokenType::Priority, "P1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_semantic_tokens_with_custom_priorities() {
+        let code = String::from("---\npriorities: high, low\n---\nfoo\n    high\n    P1");
+        let parser = Parser::new(code, tree_sitter_puddlejumper::language());
+        let (context, root) = crate::node::Context::from_parser(&parser).unwrap();
+        let queries = Queries::new().unwrap();
+        let tokens = parser.semantic_tokens(&queries, &context.document_settings(root));
+        assert_eq!(
+            token_texts(&parser, &tokens),
+            vec![
+                (SemanticTokenType::Attribute, "priorities"),
+                (SemanticTokenType::Priority, "high"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_references() {
+        let code = String::from("@a: hello\n@a");
+        let parser = Parser::new(code, tree_sitter_puddlejumper::language());
+        let queries = Queries::new().unwrap();
+        let references = parser.references(&queries);
+        assert_eq!(
+            references,
+            vec![Reference {
+                name: String::from("a"),
+                byte_range: 11..12,
+                definition: Some(1..2),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_tags() {
+        let code = String::from("@a: hello\n@a");
+        let parser = Parser::new(code, tree_sitter_puddlejumper::language());
+        let queries = Queries::new().unwrap();
+        let tags = parser
+            .tags(&queries)
+            .into_iter()
+            .map(|tag| (tag.name, tag.kind, tag.is_definition))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tags,
+            vec![
+                (String::from("a"), String::from("item"), true),
+                (String::from("a"), String::from("item"), false),
+            ]
+        );
+    }
+}
@@ -83,6 +83,81 @@ impl Diff {
     }
 }
 
+// A replacement of `range` in a text with `text`, e.g. from a structural edit. A list of edits
+// refers to the text before any of them, and must be sorted and non-overlapping.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+pub fn apply_edits(text: &str, edits: &[TextEdit]) -> String {
+    let mut result = String::new();
+    let mut end = 0;
+    for edit in edits {
+        result.push_str(&text[end..edit.range.start]);
+        result.push_str(&edit.text);
+        end = edit.range.end;
+    }
+    result.push_str(&text[end..]);
+    result
+}
+
+// The row and byte column of `byte` in `text`.
+pub fn point_at(text: &str, byte: usize) -> tree_sitter::Point {
+    let before = &text.as_bytes()[..byte];
+    match before.iter().rposition(|b| *b == b'\n') {
+        Some(newline) => tree_sitter::Point {
+            row: before.iter().filter(|b| **b == b'\n').count(),
+            column: byte - newline - 1,
+        },
+        None => tree_sitter::Point {
+            row: 0,
+            column: byte,
+        },
+    }
+}
+
+// `start` moved past `text`.
+pub fn advance_point(start: tree_sitter::Point, text: &str) -> tree_sitter::Point {
+    match text.rfind('\n') {
+        Some(newline) => tree_sitter::Point {
+            row: start.row + text.matches('\n').count(),
+            column: text.len() - newline - 1,
+        },
+        None => tree_sitter::Point {
+            row: start.row,
+            column: start.column + text.len(),
+        },
+    }
+}
+
+impl Diff {
+    // The diff that `compute_diff` would find for `edits`, so that they can be applied with
+    // `Parser::update_with_diff` without diffing the whole text.
+    pub fn from_edits(before: &str, edits: &[TextEdit]) -> Diff {
+        let after = apply_edits(before, edits);
+        // How far the edits so far have moved later text.
+        let mut shift: isize = 0;
+        let changes = edits
+            .iter()
+            .map(|edit| {
+                let start = (edit.range.start as isize + shift) as usize;
+                shift += edit.text.len() as isize - edit.range.len() as isize;
+                let start_position = point_at(&after, start);
+                Change {
+                    before_bytes: edit.range.clone(),
+                    after_bytes: start..start + edit.text.len(),
+                    start_position,
+                    old_end_position: advance_point(start_position, &before[edit.range.clone()]),
+                    new_end_position: advance_point(start_position, &edit.text),
+                }
+            })
+            .collect();
+        Diff { changes }
+    }
+}
+
 pub fn compute_diff(before: &str, after: &str) -> Diff {
     let mut changes = Vec::new();
 
@@ -144,14 +219,14 @@ pub fn compute_diff(before: &str, after: &str) -> Diff {
         let old_row_diff =
             before_token_points[before_bytes.end].row - before_token_points[before_bytes.start].row;
 
-        let old_col_diff = before_token_points[before_bytes.end].column
-            - before_token_points[before_bytes.end].column;
-
         let old_end_position = tree_sitter::Point {
             row: start_position.row + old_row_diff,
             column: if old_row_diff > 0 {
                 before_token_points[before_bytes.end].column
             } else {
+                // only on one row is the end column after the start column
+                let old_col_diff = before_token_points[before_bytes.end].column
+                    - before_token_points[before_bytes.start].column;
                 start_position.column + old_col_diff
             },
         };
@@ -251,6 +326,54 @@ world"#;
         assert_eq!(&diff._get_hunks(before, after), &vec![("\nworld", "")]);
     }
 
+    #[test]
+    fn test_text_diff_replacement() {
+        let before = r#"@a: hello"#;
+        let after = r#"@a: hi"#;
+        let diff = compute_diff(before, after);
+        let edit = diff.changes[0].input_edit();
+        assert_eq!(edit.old_end_byte - edit.start_byte, diff.changes[0].before_bytes.len());
+        assert_eq!(
+            edit.old_end_position.column - edit.start_position.column,
+            diff.changes[0].before_bytes.len()
+        );
+        assert_eq!(edit.new_end_position, tree_sitter::Point { row: 0, column: edit.new_end_byte });
+    }
+
+    #[test]
+    fn test_text_diff_multiline_deletion() {
+        for (before, after) in &[("abcdef\ng", "ag"), ("hello world\nx\n", "hello\n")] {
+            let diff = compute_diff(before, after);
+            assert!(!diff.changes.is_empty());
+            for change in &diff.changes {
+                let edit = change.input_edit();
+                assert_eq!(edit.old_end_byte - edit.start_byte, change.before_bytes.len());
+                assert_eq!(edit.start_position, point_at(before, edit.start_byte));
+                assert_eq!(edit.old_end_position, point_at(before, edit.old_end_byte));
+            }
+        }
+    }
+
+    #[test]
+    fn test_diff_from_edits() {
+        let before = "hello\nworld";
+        let edits = vec![
+            TextEdit {
+                range: 0..0,
+                text: String::from("// x\n"),
+            },
+            TextEdit {
+                range: 7..8,
+                text: String::from("a"),
+            },
+        ];
+        assert_eq!(apply_edits(before, &edits), "// x\nhello\nwarld");
+        assert_eq!(
+            Diff::from_edits(before, &edits),
+            compute_diff(before, "// x\nhello\nwarld")
+        );
+    }
+
     #[test]
     fn test_text_diff_reference() {
         let before = r#"fn foo() -> Bar {
@@ -8,11 +8,14 @@ use std::fmt::Debug;
 use std::hash::Hash;
 use std::hash::Hasher;
 use std::ops::Deref;
+use std::ops::Range;
 use std::ptr;
 use std::rc::Rc;
 
 use super::levenshtein::*;
+use super::node::json::JSON_SCHEMA_VERSION;
 use super::parser::*;
+use serde_json::{json, Value};
 use tree_sitter::Node;
 use tree_sitter::Tree;
 
@@ -68,7 +71,58 @@ impl<T> OpF<T> {
     }
 }
 
+impl<T> OpF<T> {
+    // Stable name used in serialized edit scripts.
+    pub fn name(&self) -> &'static str {
+        match self {
+            OpF::Exact { .. } => "exact",
+            OpF::Update { .. } => "update",
+            OpF::UpdateScalar { .. } => "update_scalar",
+            OpF::Delete { .. } => "delete",
+            OpF::DeleteSubtree { .. } => "delete_subtree",
+            OpF::Insert { .. } => "insert",
+            OpF::InsertSubtree { .. } => "insert_subtree",
+        }
+    }
+
+    // The node in the old tree, if any.
+    pub fn before(&self) -> Option<&T> {
+        match self {
+            OpF::Exact { old, .. }
+            | OpF::Update { old, .. }
+            | OpF::UpdateScalar { old, .. }
+            | OpF::Delete { old }
+            | OpF::DeleteSubtree { old } => Some(old),
+            OpF::Insert { .. } | OpF::InsertSubtree { .. } => None,
+        }
+    }
+
+    // The node in the new tree, if any.
+    pub fn after(&self) -> Option<&T> {
+        match self {
+            OpF::Exact { new, .. }
+            | OpF::Update { new, .. }
+            | OpF::UpdateScalar { new, .. }
+            | OpF::Insert { new }
+            | OpF::InsertSubtree { new } => Some(new),
+            OpF::Delete { .. } | OpF::DeleteSubtree { .. } => None,
+        }
+    }
+}
+
 impl<'a> OpF<Node<'a>> {
+    // e.g. { "op": "update_scalar", "old": { "kind": "content", ... }, "new": { ... } }
+    pub fn to_json(&self, context: &UpdateContext) -> Value {
+        let mut value = json!({ "op": self.name() });
+        if let Some(old) = self.before() {
+            value["old"] = syntax_node_to_json(old, &context.update.old_text);
+        }
+        if let Some(new) = self.after() {
+            value["new"] = syntax_node_to_json(new, &context.update.new_text);
+        }
+        value
+    }
+
     pub fn cost(&self, context: &UpdateContext) -> usize {
         match &self {
             OpF::Exact { .. } => 0,
@@ -337,6 +391,36 @@ impl<'a> UpdateContext<'a> {
             .collect::<Vec<_>>()
     }
 
+    // The edit script as { "version": 1, "ops": [...] }, see `OpF::to_json`.
+    pub fn to_json(&self) -> Value {
+        let ops: Vec<Value> = self
+            .get_root_change_path()
+            .map(|path| path.iter().map(|op| op.to_json(self)).collect())
+            .unwrap_or_default();
+        json!({
+            "version": JSON_SCHEMA_VERSION,
+            "ops": ops,
+        })
+    }
+
+    // Byte ranges of `kind` nodes that the edit script keeps, from the old text to the new one.
+    pub fn matched_ranges(&self, kind: &str) -> HashMap<Range<usize>, Range<usize>> {
+        let mut ranges: HashMap<Range<usize>, Range<usize>> = HashMap::new();
+        for op in self.get_root_change_path().into_iter().flatten() {
+            match &**op {
+                // Exact subtrees aren't descended into, so match up their descendants here.
+                OpF::Exact { old, new } => match_exact(*old, *new, kind, &mut ranges),
+                OpF::Update { old, new } | OpF::UpdateScalar { old, new }
+                    if old.kind() == kind && new.kind() == kind =>
+                {
+                    ranges.insert(old.byte_range(), new.byte_range());
+                }
+                _ => {}
+            }
+        }
+        ranges
+    }
+
     pub fn get_root_change_path(&self) -> Option<&Vec<OpPtr<'a>>> {
         self.search_cache
             .map
@@ -387,7 +471,7 @@ impl<'a> UpdateContext<'a> {
     pub fn add_to_frontier(&mut self, data: Rc<SearchData<'a>>) -> bool {
         if let Some(existing_data) = self.search_cache.map.get(&(data.old, data.new)) {
             if existing_data.cost <= data.cost {
-                println!(
+                log::trace!(
                     ">> Not adding to frontier (cost: {} >= existing_cost {}): {:#?}",
                     data.cost,
                     existing_data.cost,
@@ -396,7 +480,7 @@ impl<'a> UpdateContext<'a> {
                 return false;
             }
         }
-        println!(
+        log::trace!(
             ">> Adding to frontier: {:#?}",
             self.search_data_debug(&data)
         );
@@ -673,6 +757,38 @@ impl<'a> UpdateContext<'a> {
     }
 }
 
+// `old` and `new` have the same content, and so the same shape.
+fn match_exact(
+    old: Node,
+    new: Node,
+    kind: &str,
+    ranges: &mut HashMap<Range<usize>, Range<usize>>,
+) {
+    if old.kind() == kind {
+        ranges.insert(old.byte_range(), new.byte_range());
+    }
+    for (old_child, new_child) in old
+        .children(&mut old.walk())
+        .zip(new.children(&mut new.walk()))
+    {
+        match_exact(old_child, new_child, kind, ranges);
+    }
+}
+
+fn point_to_json(point: tree_sitter::Point) -> Value {
+    json!({ "row": point.row, "column": point.column })
+}
+
+pub fn syntax_node_to_json(node: &Node, text: &str) -> Value {
+    json!({
+        "kind": node.kind(),
+        "range": { "start": node.start_byte(), "end": node.end_byte() },
+        "start_point": point_to_json(node.start_position()),
+        "end_point": point_to_json(node.end_position()),
+        "text": &text[node.byte_range()],
+    })
+}
+
 pub fn is_compatible_for_scalar_update(old: Node, new: Node) -> bool {
     match (old.kind(), new.kind()) {
         (a, b) if a == b => true,
@@ -1038,4 +1154,23 @@ foo"#,
             ]
         );
     }
+
+    #[test]
+    fn test_diff_to_json() {
+        let code1 = String::from("hello\nworld");
+        let code2 = String::from("hello\nwarld");
+        let mut parser = Parser::new(code1.clone(), tree_sitter_puddlejumper::language());
+        let update = parser.update(code2.clone());
+        let update_context = UpdateContext::new(&update);
+        let json = update_context.to_json();
+        assert_eq!(json["version"], JSON_SCHEMA_VERSION);
+        let ops = json["ops"].as_array().unwrap();
+        assert_eq!(
+            ops.iter().map(|op| op["op"].as_str().unwrap()).collect::<Vec<_>>(),
+            vec!["update", "exact", "update", "update_scalar"]
+        );
+        assert_eq!(ops[3]["old"]["text"], "world");
+        assert_eq!(ops[3]["new"]["text"], "warld");
+        assert_eq!(ops[3]["new"]["start_point"], json!({ "row": 1, "column": 0 }));
+    }
 }
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use indexmap::IndexMap;
+
+use super::node::printer::{PrintContext, DEFAULT_INDENT_WIDTH};
+use super::node::{Context, Node, NodeId};
+use super::parser::*;
+use super::tree_diff::UpdateContext;
+
+// Keeps the prioritized projection of a file up to date as the file is edited, for
+// `puddlejumper watch print_prioritized <file>`.
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct PrioritizedItem {
+    // Where the item is in the source, used to match it up across edits.
+    pub range: Range<usize>,
+    pub label: String,
+    pub priority: String,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PriorityChange {
+    Added {
+        label: String,
+        priority: String,
+    },
+    Removed {
+        label: String,
+        priority: String,
+    },
+    Changed {
+        label: String,
+        from: String,
+        to: String,
+    },
+}
+
+impl fmt::Display for PriorityChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PriorityChange::Added { label, priority } => write!(f, "+ [{}] {}", priority, label),
+            PriorityChange::Removed { label, priority } => write!(f, "- [{}] {}", priority, label),
+            PriorityChange::Changed { label, from, to } => {
+                write!(f, "~ [{} -> {}] {}", from, to, label)
+            }
+        }
+    }
+}
+
+// Items of the prioritized projection of `root`, in document order.
+pub fn prioritized_items(ctx: &Context, root: NodeId) -> Vec<PrioritizedItem> {
+    let settings = ctx.document_settings(root);
+    let mut items: Vec<PrioritizedItem> = ctx
+        .extract_priorities(root)
+        .into_iter()
+        .filter_map(|(node, priority)| {
+            let source = ctx.source(node)?;
+            Some(PrioritizedItem {
+                range: source.range.byte_range(),
+                label: ctx.label(node),
+                priority: settings.priorities[priority as usize].clone(),
+            })
+        })
+        .collect();
+    items.sort_by_key(|item| item.range.start);
+    items
+}
+
+// Compares the items before and after an update. `matched` tells which old item became which
+// new one, see `UpdateContext::matched_ranges`.
+pub fn priority_changes(
+    matched: &HashMap<Range<usize>, Range<usize>>,
+    old: &[PrioritizedItem],
+    new: &[PrioritizedItem],
+) -> Vec<PriorityChange> {
+    let mut changes: Vec<PriorityChange> = Vec::new();
+    let mut kept: Vec<bool> = vec![false; new.len()];
+    for old_item in old {
+        let new_index = matched.get(&old_item.range).and_then(|range| {
+            new.iter()
+                .position(|new_item| new_item.range == *range)
+        });
+        match new_index {
+            Some(index) => {
+                kept[index] = true;
+                let new_item = &new[index];
+                if new_item.priority != old_item.priority {
+                    changes.push(PriorityChange::Changed {
+                        label: new_item.label.clone(),
+                        from: old_item.priority.clone(),
+                        to: new_item.priority.clone(),
+                    });
+                }
+            }
+            None => changes.push(PriorityChange::Removed {
+                label: old_item.label.clone(),
+                priority: old_item.priority.clone(),
+            }),
+        }
+    }
+    for (new_item, kept) in new.iter().zip(kept) {
+        if !kept {
+            changes.push(PriorityChange::Added {
+                label: new_item.label.clone(),
+                priority: new_item.priority.clone(),
+            });
+        }
+    }
+    changes
+}
+
+pub struct Watcher {
+    pub path: PathBuf,
+    pub parser: Parser,
+    pub context: Context,
+    pub root: NodeId,
+    pub items: Vec<PrioritizedItem>,
+    modified: Option<SystemTime>,
+}
+
+impl Watcher {
+    pub fn new(path: &Path) -> io::Result<Watcher> {
+        let modified = fs::metadata(path)?.modified().ok();
+        let text = fs::read_to_string(path)?;
+        let parser = Parser::new(text, tree_sitter_puddlejumper::language());
+        let (context, root) = Context::from_parser(&parser).ok_or_else(parse_error)?;
+        let items = prioritized_items(&context, root);
+        Ok(Watcher {
+            path: path.to_path_buf(),
+            parser,
+            context,
+            root,
+            items,
+            modified,
+        })
+    }
+
+    // Re-reads the file if it changed on disk, returning how the prioritized items changed.
+    pub fn poll(&mut self) -> io::Result<Option<Vec<PriorityChange>>> {
+        let modified = fs::metadata(&self.path)?.modified().ok();
+        if modified.is_some() && modified == self.modified {
+            return Ok(None);
+        }
+        self.modified = modified;
+        let text = fs::read_to_string(&self.path)?;
+        self.update(text)
+    }
+
+    // Runs `text` through `Parser::update`. Returns `None` if the text didn't change. If the new
+    // text can't be loaded, the watcher is left with an empty document, so that the next update
+    // isn't compared against items of the old text.
+    pub fn update(&mut self, text: String) -> io::Result<Option<Vec<PriorityChange>>> {
+        if text == self.parser.text {
+            return Ok(None);
+        }
+        let update = self.parser.update(text);
+        let matched = UpdateContext::new(&update).matched_ranges("node");
+        self.parser.apply_update(update);
+        let (context, root) = match Context::from_parser(&self.parser) {
+            Some(loaded) => loaded,
+            None => {
+                self.context = Context::new();
+                self.root = self.context.arena.alloc(Node::Document {
+                    attributes: IndexMap::new(),
+                    children: vec![],
+                });
+                self.items.clear();
+                return Err(parse_error());
+            }
+        };
+        let items = prioritized_items(&context, root);
+        let changes = priority_changes(&matched, &self.items, &items);
+        self.context = context;
+        self.root = root;
+        self.items = items;
+        Ok(Some(changes))
+    }
+
+    pub fn render(&mut self, out: &mut dyn io::Write) -> io::Result<()> {
+        let list = self.context.make_prioritized_list(self.root);
+        self.context.pretty_print(
+            list,
+            &mut PrintContext {
+                level: 0,
+                needs_indent: true,
+                indent_width: DEFAULT_INDENT_WIDTH,
+                out,
+            },
+        )?;
+        // The list is only needed for printing.
+        if let Some(map) = self.context.collect_garbage(&[self.root]) {
+            self.root = map[&self.root];
+        }
+        Ok(())
+    }
+}
+
+fn parse_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Error parsing file")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changes(before: &str, after: &str) -> Vec<String> {
+        let mut parser = Parser::new(String::from(before), tree_sitter_puddlejumper::language());
+        let (old_context, old_root) = Context::from_parser(&parser).unwrap();
+        let update = parser.update(String::from(after));
+        let matched = UpdateContext::new(&update).matched_ranges("node");
+        parser.apply_update(update);
+        let (new_context, new_root) = Context::from_parser(&parser).unwrap();
+        priority_changes(
+            &matched,
+            &prioritized_items(&old_context, old_root),
+            &prioritized_items(&new_context, new_root),
+        )
+        .iter()
+        .map(ToString::to_string)
+        .collect()
+    }
+
+    #[test]
+    fn test_priority_changes() {
+        assert_eq!(
+            changes("hello\n    P1\nworld", "hello\n    P0\nworld"),
+            vec!["~ [P1 -> P0] hello"]
+        );
+        assert_eq!(
+            changes("hello\n    P1\nworld", "hello\n    P1\nworld\n    P2"),
+            vec!["+ [P2] world"]
+        );
+        assert_eq!(
+            changes("hello\n    P1\nworld\n    P2", "world\n    P2"),
+            vec!["- [P1] hello"]
+        );
+    }
+
+    #[test]
+    fn test_watcher_update() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("roadmap.pj");
+        fs::write(&path, "hello\n    P1").unwrap();
+        let mut watcher = Watcher::new(&path).unwrap();
+        assert_eq!(watcher.update(String::from("hello\n    P1")).unwrap(), None);
+        assert_eq!(
+            watcher.update(String::from("hello\n    P2")).unwrap(),
+            Some(vec![PriorityChange::Changed {
+                label: String::from("hello"),
+                from: String::from("P1"),
+                to: String::from("P2"),
+            }])
+        );
+        let mut out: Vec<u8> = Vec::new();
+        watcher.render(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "P2\n    hello\n        P2\n");
+    }
+}
@@ -0,0 +1,372 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use indexmap::IndexMap;
+
+use super::node::{Content, Context, Node, NodeId};
+use super::parser::*;
+
+// A directory of `.pj` files, loaded as one project plan. Each document is named after its file
+// stem, and refs can point into other documents with `@file.name` or `@file#name`. Updating one
+// document re-resolves its own refs and the refs that point into it.
+
+pub const EXTENSION: &str = "pj";
+
+// Where a ref resolves to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub document: String,
+    pub node: NodeId,
+}
+
+pub struct WorkspaceDocument {
+    pub path: PathBuf,
+    pub parser: Parser,
+    pub context: Context,
+    pub root: NodeId,
+    // `@name:` bindings by name. The first binding of a name wins.
+    pub bindings: HashMap<String, NodeId>,
+    // Every ref in the document, in document order, with what it resolves to.
+    pub references: Vec<(NodeId, Option<Target>)>,
+}
+
+#[derive(Default)]
+pub struct Workspace {
+    pub documents: IndexMap<String, WorkspaceDocument>,
+    // The documents with a qualified ref into each document name, whether or not that document
+    // is open, so that changing a document only re-resolves the refs that can point into it.
+    referrers: HashMap<String, HashSet<String>>,
+}
+
+// The document and binding a ref names: `@file.name` and `@file#name` give `(Some("file"),
+// "name")`, and `@name` gives `(None, "name")`.
+pub fn split_ref(text: &str) -> (Option<&str>, &str) {
+    let text = text.trim().trim_start_matches('@');
+    match text.split_once(['.', '#']) {
+        Some((document, name)) => (Some(document), name),
+        None => (None, text),
+    }
+}
+
+// The `.pj` files directly in `dir`, sorted by name.
+pub fn pj_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file()
+            && path
+                .extension()
+                .is_some_and(|extension| extension == EXTENSION)
+        {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+pub fn document_name(path: &Path) -> Option<String> {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .map(String::from)
+}
+
+fn parse_error() -> io::Error {
+    io::Error::other("Error parsing file")
+}
+
+fn bindings(context: &Context, root: NodeId) -> HashMap<String, NodeId> {
+    let mut bindings: HashMap<String, NodeId> = HashMap::new();
+    context.walk(root, &mut |id, node| {
+        if let Node::Node {
+            binding: Some(binding),
+            ..
+        }
+        | Node::Block {
+            binding: Some(binding),
+            ..
+        } = node
+        {
+            bindings.entry(binding.clone()).or_insert(id);
+        }
+    });
+    bindings
+}
+
+fn refs(context: &Context, root: NodeId) -> Vec<(NodeId, String)> {
+    let mut refs: Vec<(NodeId, String)> = Vec::new();
+    context.walk(root, &mut |id, node| {
+        if let Node::Node {
+            content: Some(Content::Ref(text)),
+            ..
+        } = node
+        {
+            refs.push((id, text.clone()));
+        }
+    });
+    refs
+}
+
+impl Workspace {
+    pub fn new() -> Workspace {
+        Workspace::default()
+    }
+
+    // Loads every `.pj` file directly in `dir`.
+    pub fn load(dir: &Path) -> io::Result<Workspace> {
+        let mut workspace = Workspace::new();
+        for path in pj_files(dir)? {
+            let text = fs::read_to_string(&path)?;
+            workspace.open(&path, text).map_err(|error| {
+                io::Error::new(error.kind(), format!("{}: {}", path.display(), error))
+            })?;
+        }
+        Ok(workspace)
+    }
+
+    // Adds the document at `path`, or replaces it if it is already open.
+    pub fn open(&mut self, path: &Path, text: String) -> io::Result<String> {
+        let name = document_name(path)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Not a file name"))?;
+        let parser = Parser::new(text, tree_sitter_puddlejumper::language());
+        let (context, root) = Context::from_parser(&parser).ok_or_else(parse_error)?;
+        self.documents.insert(
+            name.clone(),
+            WorkspaceDocument {
+                path: path.to_path_buf(),
+                bindings: bindings(&context, root),
+                parser,
+                context,
+                root,
+                references: Vec::new(),
+            },
+        );
+        self.invalidate(&name);
+        Ok(name)
+    }
+
+    // Reparses a document incrementally against its previous text.
+    pub fn update(&mut self, name: &str, text: String) -> io::Result<()> {
+        let document = self.documents.get_mut(name).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("No document {:?}", name))
+        })?;
+        let update = document.parser.update(text);
+        document.parser.apply_update(update);
+        let (context, root) = Context::from_parser(&document.parser).ok_or_else(parse_error)?;
+        document.bindings = bindings(&context, root);
+        document.context = context;
+        document.root = root;
+        self.invalidate(name);
+        Ok(())
+    }
+
+    pub fn close(&mut self, name: &str) -> Option<WorkspaceDocument> {
+        let document = self.documents.shift_remove(name)?;
+        self.invalidate(name);
+        Some(document)
+    }
+
+    // Where the ref `reference`, written in `document`, points.
+    pub fn resolve(&self, document: &str, reference: &str) -> Option<Target> {
+        let (target, name) = split_ref(reference);
+        let target = target.unwrap_or(document);
+        let node = *self.documents.get(target)?.bindings.get(name)?;
+        Some(Target {
+            document: target.to_string(),
+            node,
+        })
+    }
+
+    // Refs that don't resolve, as the document they are in and the ref node.
+    pub fn unresolved(&self) -> Vec<(String, NodeId)> {
+        self.documents
+            .iter()
+            .flat_map(|(name, document)| {
+                document
+                    .references
+                    .iter()
+                    .filter(|(_, target)| target.is_none())
+                    .map(move |(node, _)| (name.clone(), *node))
+            })
+            .collect()
+    }
+
+    // Re-resolves the refs of `name` and of every document with a ref into it, after it was
+    // opened, updated or closed.
+    fn invalidate(&mut self, name: &str) {
+        // The refs of `name` may have changed, so index them again.
+        self.referrers.retain(|_, referrers| {
+            referrers.remove(name);
+            !referrers.is_empty()
+        });
+        let mut stale: Vec<String> = Vec::new();
+        if let Some(document) = self.documents.get(name) {
+            for (_, text) in refs(&document.context, document.root) {
+                if let (Some(target), _) = split_ref(&text) {
+                    self.referrers
+                        .entry(target.to_string())
+                        .or_default()
+                        .insert(name.to_string());
+                }
+            }
+            stale.push(name.to_string());
+        }
+        for referrer in self.referrers.get(name).into_iter().flatten() {
+            if referrer != name {
+                stale.push(referrer.clone());
+            }
+        }
+        for other in stale {
+            let document = &self.documents[&other];
+            let references: Vec<(NodeId, Option<Target>)> = refs(&document.context, document.root)
+                .into_iter()
+                .map(|(node, text)| (node, self.resolve(&other, &text)))
+                .collect();
+            self.documents[&other].references = references;
+        }
+    }
+
+    // Every document loaded into one context under a shared root, so that projections like the
+    // prioritized list span the whole project, see `Context::combine_documents`.
+    pub fn combined(&self) -> io::Result<(Context, NodeId)> {
+        let mut context = Context::new();
+        let mut children: Vec<NodeId> = Vec::new();
+        for (name, document) in &self.documents {
+            let root = context
+                .load_document(&document.parser)
+                .ok_or_else(|| io::Error::other(format!("{}: Error parsing file", name)))?;
+            context.document_names.insert(root, name.clone());
+            children.push(root);
+        }
+        let root = context.combine_documents(children);
+        Ok((context, root))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_ref() {
+        assert_eq!(split_ref("@api"), (None, "api"));
+        assert_eq!(split_ref("@backend.api"), (Some("backend"), "api"));
+        assert_eq!(split_ref("@backend#api"), (Some("backend"), "api"));
+    }
+
+    #[test]
+    fn test_resolve_across_documents() {
+        let mut workspace = Workspace::new();
+        workspace
+            .open(
+                Path::new("plan.pj"),
+                String::from("launch\n    @backend.api\n    @ship"),
+            )
+            .unwrap();
+        // Not there yet.
+        assert_eq!(workspace.unresolved().len(), 2);
+
+        workspace
+            .open(Path::new("backend.pj"), String::from("@api: API\n@db: DB"))
+            .unwrap();
+        assert_eq!(
+            workspace.referrers,
+            HashMap::from([(
+                String::from("backend"),
+                HashSet::from([String::from("plan")])
+            )])
+        );
+        let backend = &workspace.documents["backend"];
+        assert_eq!(
+            workspace.resolve("plan", "@backend#api"),
+            Some(Target {
+                document: String::from("backend"),
+                node: backend.bindings["api"],
+            })
+        );
+        assert_eq!(workspace.unresolved().len(), 1);
+
+        workspace
+            .update(
+                "plan",
+                String::from("launch\n    @backend.api\n@ship: ship"),
+            )
+            .unwrap();
+        assert_eq!(workspace.unresolved(), vec![]);
+
+        // Renaming the binding breaks the ref into it.
+        workspace
+            .update("backend", String::from("@apis: API\n@db: DB"))
+            .unwrap();
+        assert_eq!(workspace.unresolved().len(), 1);
+        assert_eq!(workspace.unresolved()[0].0, "plan");
+
+        workspace
+            .update("plan", String::from("launch\n@ship: ship"))
+            .unwrap();
+        assert!(workspace.referrers.is_empty());
+    }
+
+    #[test]
+    fn test_combined_projection() {
+        let mut workspace = Workspace::new();
+        workspace
+            .open(Path::new("a.pj"), String::from("x\n    P1"))
+            .unwrap();
+        workspace
+            .open(Path::new("b.pj"), String::from("y\n    P0"))
+            .unwrap();
+        let (mut context, root) = workspace.combined().unwrap();
+        let list = context.make_prioritized_list(root);
+        let mut out: Vec<u8> = Vec::new();
+        context
+            .pretty_print(
+                list,
+                &mut crate::node::printer::PrintContext {
+                    level: 0,
+                    needs_indent: true,
+                    indent_width: crate::node::printer::DEFAULT_INDENT_WIDTH,
+                    out: &mut out,
+                },
+            )
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.find("P0").unwrap() < out.find("P1").unwrap());
+        assert!(out.contains("x") && out.contains("y"));
+    }
+
+    #[test]
+    fn test_combined_keeps_each_documents_priorities() {
+        let mut workspace = Workspace::new();
+        workspace
+            .open(Path::new("a.pj"), String::from("x\n    P1\nw\n    high"))
+            .unwrap();
+        workspace
+            .open(
+                Path::new("b.pj"),
+                String::from("---\npriorities: high, low\n---\ny\n    low\nz\n    high"),
+            )
+            .unwrap();
+        let (context, root) = workspace.combined().unwrap();
+        let settings = context.document_settings(root);
+        assert_eq!(
+            settings.priorities,
+            vec!["P0", "high", "P1", "low", "P2", "P3", "P4"]
+        );
+        let labels: Vec<(String, String)> = context
+            .extract_priorities(root)
+            .into_iter()
+            .map(|(node, p)| (context.label(node), settings.priorities[p as usize].clone()))
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                (String::from("x"), String::from("P1")),
+                (String::from("y"), String::from("low")),
+                (String::from("z"), String::from("high")),
+            ]
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn run(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_puddlejumper"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_usage_errors_exit_non_zero() {
+    let output = run(&["frobnicate", "a.pj"], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert!(!output.stderr.is_empty());
+
+    let output = run(&["print-prioritized"], "");
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_missing_file_reports_on_stderr() {
+    let output = run(&["json", "does-not-exist.pj"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("does-not-exist.pj"));
+}
+
+#[test]
+fn test_print_prioritized_from_stdin() {
+    let output = run(&["print_prioritized", "-"], "hello\n    P1\nworld");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "P1\n    hello\n        P1\n"
+    );
+}
+
+#[test]
+fn test_multiple_files_share_a_root() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = dir.path().join("a.pj");
+    std::fs::write(&a, "hello\n    P1").unwrap();
+    let output = run(
+        &["print-prioritized", "--indent-width", "2", a.to_str().unwrap(), "-"],
+        "world\n    P0",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "P0\n  world\n    P0\nP1\n  hello\n    P1\n"
+    );
+}
+
+#[test]
+fn test_multiple_files_keep_their_settings() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = dir.path().join("a.pj");
+    std::fs::write(
+        &a,
+        "---\npriorities: high, low\nindent_width: 2\n---\nhello\n  low\n",
+    )
+    .unwrap();
+    let output = run(
+        &["print-prioritized", a.to_str().unwrap(), "-"],
+        "---\nindent_width: 2\n---\nworld\n  P0\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "P0\n  world\n    P0\nlow\n  hello\n    low\n"
+    );
+}
+
+#[test]
+fn test_diff() {
+    let dir = tempfile::tempdir().unwrap();
+    let old = dir.path().join("old.pj");
+    std::fs::write(&old, "hello\n    P1\nworld").unwrap();
+    let old = old.to_str().unwrap();
+
+    let output = run(&["diff", "--exit-code", old, "-"], "hello\n    P0\nworld\n    foo");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "! priority of \"hello\" changed from P1 to P0\n+ added \"foo\" under \"world\"\n"
+    );
+
+    let output = run(&["diff", "--json", old, "-"], "hello\n    P1\nworld");
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["changes"], serde_json::json!([]));
+}
+
+#[test]
+fn test_export() {
+    let output = run(&["export", "--format", "md", "-"], "@a: a\n    @a");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "- <a id=\"a\"></a>a\n  - [@a](#a)\n"
+    );
+
+    let output = run(&["export", "--format", "pdf", "-"], "a");
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_import() {
+    let output = run(&["import", "--from", "md", "-"], "# Plan\n- [ ] a\n  - b\n");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "# Plan\n[ ] a\n    b\n"
+    );
+
+    let output = run(&["import", "--from", "opml", "-"], "<opml><body>");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "puddlejumper: -: Invalid OPML: unclosed <body>\n"
+    );
+}
+
+#[test]
+fn test_merge_driver() {
+    let dir = tempfile::tempdir().unwrap();
+    let write = |name: &str, text: &str| {
+        let path = dir.path().join(name);
+        std::fs::write(&path, text).unwrap();
+        path.to_str().unwrap().to_string()
+    };
+    let base = write("base.pj", "hello\nworld\n");
+    let ours = write("ours.pj", "hello\n    P1\nworld\n");
+    let theirs = write("theirs.pj", "hello\nworld\n    P2\n");
+    let output = run(&["merge-driver", &base, &ours, &theirs], "");
+    assert!(output.status.success());
+    assert_eq!(
+        std::fs::read_to_string(&ours).unwrap(),
+        "hello\n    P1\nworld\n    P2\n"
+    );
+
+    let ours = write("ours.pj", "hello\nwarld\n");
+    let theirs = write("theirs.pj", "hello\nwurld\n");
+    let output = run(&["merge-driver", &base, &ours, &theirs], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(std::fs::read_to_string(&ours).unwrap().contains("<<<<<<< ours"));
+}
+
+#[test]
+fn test_assign_ids() {
+    let output = run(&["assign-ids", "-"], "@api: API\n    Write tests ^k3x9qa\n    Docs\n");
+    assert!(output.status.success());
+    let out = String::from_utf8(output.stdout).unwrap();
+    assert!(out.starts_with("@api: API\n    Write tests ^k3x9qa\n    Docs ^"));
+
+    // The ids are derived from the content, so assigning again gives the same output.
+    let again = run(&["assign-ids", "-"], "@api: API\n    Write tests ^k3x9qa\n    Docs\n");
+    assert_eq!(String::from_utf8(again.stdout).unwrap(), out);
+    assert_eq!(run(&["assign-ids", "-"], &out).stdout, out.as_bytes());
+
+    let output = run(&["assign-ids", "--in-place", "-"], "");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_agenda() {
+    let input = "a due:2026-10-25\nb\n    due: 2026-10-18\nc\n";
+    let output = run(&["agenda", "--today", "2026-10-19", "-"], input);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Overdue\n    b\n        due: 2026-10-18\nThis week\n    a due:2026-10-25\n"
+    );
+
+    let output = run(&["agenda", "--today", "2026-10-19", "--json", "-"], input);
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json[0]["group"], "Overdue");
+    assert_eq!(json[0]["due"], "2026-10-18");
+
+    let output = run(&["agenda", "--today", "tomorrow", "-"], input);
+    assert_eq!(output.status.code(), Some(2));
+
+    let input = "---\npriorities: high, low\n---\na due:2026-10-25\n    low\n";
+    let output = run(&["agenda", "--today", "2026-10-19", "--json", "-"], input);
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json[0]["priority"], "low");
+}
+
+#[test]
+fn test_deps_and_ready() {
+    let input = "@deploy: [ ] Deploy\n    blocked_by\n        @api\n@api: [x] API\n";
+    let output = run(&["deps", "-"], input);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "[x] API\n[ ] Deploy\n"
+    );
+    let output = run(&["deps", "--format", "mermaid", "-"], input);
+    assert!(String::from_utf8(output.stdout).unwrap().contains(" --> "));
+    let output = run(&["ready", "-"], input);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "[ ] Deploy\n    blocked_by\n        @api\n"
+    );
+
+    let output = run(&["deps", "-"], "@a: a\n    depends\n        @a\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "puddlejumper: Dependency cycle: a -> a\n"
+    );
+}
+
+#[test]
+fn test_stats() {
+    let input = "Backend\n    [x] API\n        P0\n    [ ] DB\n";
+    let output = run(&["stats", "-"], input);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Backend [1/2, 1×P0]\n    [x] API\n        P0\n    [ ] DB\n"
+    );
+    let output = run(&["stats", "--json", "-"], input);
+    let stats: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let backend = &stats["children"][0];
+    assert_eq!(backend["label"], "Backend");
+    assert_eq!(backend["descendants"], 2);
+    assert_eq!(backend["tasks"]["done"], 1);
+    assert_eq!(backend["priorities"]["P0"], 1);
+}
+
+#[test]
+fn test_search() {
+    let input = "@api: API\n    Write tests\nDeploy\n    Tests for deploy\n";
+    let output = run(&["search", "tests", "-"], input);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Deploy > Tests for deploy\nAPI > Write tests\n"
+    );
+    let output = run(&["search", "--json", "--limit", "1", "dploy", "-"], input);
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(results.as_array().unwrap().len(), 1);
+    assert_eq!(results[0]["label"], "Deploy");
+    assert_eq!(results[0]["distance"], 1);
+    assert_eq!(results[0]["start"]["row"], 2);
+}
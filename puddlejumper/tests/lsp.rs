@@ -0,0 +1,124 @@
+use std::io::BufReader;
+use std::process::{ChildStdin, ChildStdout, Command, Stdio};
+
+use puddlejumper::lsp::{read_message, write_message};
+use serde_json::{json, Value};
+
+// Drives `puddlejumper-lsp` over pipes the way an editor would.
+struct Client {
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: u64,
+}
+
+impl Client {
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+        loop {
+            let message = self.receive();
+            if message["id"] == json!(id) {
+                return message;
+            }
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    fn send(&mut self, message: Value) {
+        write_message(&mut self.stdin, &message).unwrap();
+    }
+
+    fn receive(&mut self) -> Value {
+        read_message(&mut self.stdout).unwrap().unwrap().unwrap()
+    }
+}
+
+fn position(line: u64, character: u64) -> Value {
+    json!({ "line": line, "character": character })
+}
+
+#[test]
+fn test_lsp_session() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_puddlejumper-lsp"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut client = Client {
+        stdin: child.stdin.take().unwrap(),
+        stdout: BufReader::new(child.stdout.take().unwrap()),
+        next_id: 0,
+    };
+    let uri = "file:///roadmap.pj";
+    let document = json!({ "uri": uri });
+
+    let initialize = client.request("initialize", json!({ "capabilities": {} }));
+    assert_eq!(initialize["result"]["capabilities"]["renameProvider"], true);
+    client.notify("initialized", json!({}));
+
+    client.notify(
+        "textDocument/didOpen",
+        json!({ "textDocument": {
+            "uri": uri,
+            "languageId": "puddlejumper",
+            "version": 1,
+            "text": "# Backend\n@api: API\n    P1\n    see\n        @api",
+        } }),
+    );
+    let diagnostics = client.receive();
+    assert_eq!(diagnostics["method"], "textDocument/publishDiagnostics");
+    assert_eq!(diagnostics["params"]["diagnostics"], json!([]));
+
+    let definition = client.request(
+        "textDocument/definition",
+        json!({ "textDocument": document, "position": position(4, 9) }),
+    );
+    assert_eq!(definition["result"]["range"]["start"], position(1, 1));
+
+    let references = client.request(
+        "textDocument/references",
+        json!({
+            "textDocument": document,
+            "position": position(1, 2),
+            "context": { "includeDeclaration": true },
+        }),
+    );
+    assert_eq!(references["result"].as_array().unwrap().len(), 2);
+
+    let symbols = client.request("textDocument/documentSymbol", json!({ "textDocument": document }));
+    assert_eq!(symbols["result"][0]["name"], "Backend");
+    assert_eq!(symbols["result"][0]["children"][0]["name"], "@api: API");
+
+    let folding = client.request("textDocument/foldingRange", json!({ "textDocument": document }));
+    assert!(folding["result"]
+        .as_array()
+        .unwrap()
+        .contains(&json!({ "startLine": 1, "endLine": 4 })));
+
+    let rename = client.request(
+        "textDocument/rename",
+        json!({ "textDocument": document, "position": position(4, 9), "newName": "backend" }),
+    );
+    assert_eq!(rename["result"]["changes"][uri].as_array().unwrap().len(), 2);
+
+    client.notify(
+        "textDocument/didChange",
+        json!({
+            "textDocument": { "uri": uri, "version": 2 },
+            "contentChanges": [{ "text": "# Backend\n@api: API\n@@@ broken" }],
+        }),
+    );
+    let diagnostics = client.receive();
+    let diagnostics = diagnostics["params"]["diagnostics"].as_array().unwrap();
+    assert!(!diagnostics.is_empty());
+    assert_eq!(diagnostics[0]["range"]["start"]["line"], 2);
+
+    let shutdown = client.request("shutdown", Value::Null);
+    assert_eq!(shutdown["result"], Value::Null);
+    client.notify("exit", Value::Null);
+    assert!(child.wait().unwrap().success());
+}
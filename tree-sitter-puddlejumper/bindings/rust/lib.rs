@@ -26,12 +26,14 @@ pub fn language() -> tree_sitter::Language {
 /// [`node-types.json`]: https://tree-sitter.github.io/tree-sitter/using-parsers#static-node-types
 pub const NODE_TYPES: &'static str = include_str!("../../src/node-types.json");
 
-// Uncomment these to include any queries that this grammar contains
+/// The syntax highlighting query for this language.
+pub const HIGHLIGHTS_QUERY: &'static str = include_str!("../../queries/highlights.scm");
 
-// pub const HIGHLIGHTS_QUERY: &'static str = include_str!("../../queries/highlights.scm");
-// pub const INJECTIONS_QUERY: &'static str = include_str!("../../queries/injections.scm");
-// pub const LOCALS_QUERY: &'static str = include_str!("../../queries/locals.scm");
-// pub const TAGS_QUERY: &'static str = include_str!("../../queries/tags.scm");
+/// The local-variable query for this language, linking `@name` refs to `@name:` bindings.
+pub const LOCALS_QUERY: &'static str = include_str!("../../queries/locals.scm");
+
+/// The symbol tagging query for this language.
+pub const TAGS_QUERY: &'static str = include_str!("../../queries/tags.scm");
 
 #[cfg(test)]
 mod tests {
@@ -52,4 +54,15 @@ mod tests {
             .set_language(super::language())
             .expect("Error loading puddlejumper language");
     }
+
+    #[test]
+    fn test_can_load_queries() {
+        for source in [
+            super::HIGHLIGHTS_QUERY,
+            super::LOCALS_QUERY,
+            super::TAGS_QUERY,
+        ] {
+            tree_sitter::Query::new(super::language(), source).expect("Error loading query");
+        }
+    }
 }
@@ -1,28 +1,93 @@
 /// <reference types="tree-sitter-cli/dsl" />
 // @ts-check
+
+// Blocks nest like Markdown headings: a `## header` block belongs to the closest preceding
+// `# header` block. Each level gets its own set of rules, all aliased to `block`.
+const MAX_BLOCK_LEVEL = 6;
+const BLOCK_LEVELS = Array.from({ length: MAX_BLOCK_LEVEL }, (_, i) => i + 1);
+
+// Sections that may appear inside a block of the given level (0 for the top level).
+const sectionsBelow = ($, level) =>
+  choice(
+    ...BLOCK_LEVELS.filter((l) => l > level).map((l) => $[`_block_section_${l}`])
+  );
+
+const blockRules = () => {
+  const rules = {};
+  for (const level of BLOCK_LEVELS) {
+    rules[`_block_section_${level}`] = ($) =>
+      seq(alias($[`_block_${level}`], $.block), optional($._newline));
+
+    // Right precedence makes a block take every deeper section that follows it, rather than
+    // ending early and leaving that section to its parent.
+    rules[`_block_${level}`] = ($) =>
+      prec.right(
+        seq(
+          optional(seq(field("binding", $.binding), $._newline)),
+          field("header", alias($[`_block_header_${level}`], $.block_header)),
+          optional(
+            seq(optional($._newline), field("children", $[`_block_body_${level}`]))
+          )
+        )
+      );
+
+    rules[`_block_body_${level}`] = ($) =>
+      level === MAX_BLOCK_LEVEL
+        ? repeat1($._node_line)
+        : choice(
+            seq(repeat1($._node_line), repeat(sectionsBelow($, level))),
+            repeat1(sectionsBelow($, level))
+          );
+
+    rules[`_block_header_${level}`] = ($) =>
+      seq($[`_block_begin_${level}`], /[\s]+/, $.node);
+
+    rules[`_block_begin_${level}`] = ($) => token(prec(1, "#".repeat(level)));
+  }
+  return rules;
+};
+
 module.exports = grammar({
   name: "puddlejumper",
   externals: ($) => [$._newline, $._indent, $._dedent],
-  conflicts: ($) => [[$._node_line], [$.block]],
+  conflicts: ($) => [
+    [$._node_line],
+    ...BLOCK_LEVELS.map((level) => [$[`_block_${level}`]]),
+  ],
   rules: {
-    document: ($) => optional(field("children", $._body)),
+    document: ($) =>
+      seq(
+        optional(field("front_matter", $.front_matter)),
+        optional(field("children", $._body))
+      ),
+
+    // Document-level `key: value` attributes between `---` fences, at the very start of the file.
+    front_matter: ($) =>
+      seq(
+        $._front_matter_fence,
+        $._newline,
+        repeat(seq($.attribute, $._newline)),
+        $._front_matter_fence,
+        optional($._newline)
+      ),
+    _front_matter_fence: ($) => token(prec(2, "---")),
+    attribute: ($) =>
+      seq(
+        field("name", $.attribute_name),
+        $._assignment,
+        optional(field("value", $.attribute_value))
+      ),
+    attribute_name: ($) => /[a-zA-Z_][a-zA-Z0-9_-]*/,
+    attribute_value: ($) => /[^\n]+/,
+
     _body: ($) =>
       choice(
-        seq(repeat1($._node_line), repeat($._block_node_section)),
-        seq(repeat1($._block_node_section))
+        seq(repeat1($._node_line), repeat(sectionsBelow($, 0))),
+        seq(repeat1(sectionsBelow($, 0)))
       ),
     _node_line: ($) => seq($.node, optional($._newline)),
 
-    _block_node_section: ($) => seq($.block, optional($._newline)),
-    block: ($) =>
-      seq(
-        optional(seq(field("binding", $.binding), $._newline)),
-        field("header", $.block_header),
-        optional(seq(optional($._newline), field("children", $._block_body)))
-      ),
-    _block_body: ($) => repeat1($._node_line),
-    block_header: ($) => seq($._block_begin, /[\s]+/, $.node),
-    _block_begin: ($) => token(prec(1, "#")),
+    ...blockRules(),
 
     children: ($) => seq($._indent, $._body, $._dedent),
 
@@ -32,22 +97,33 @@ module.exports = grammar({
           field("binding", $.binding),
           $._newline,
           field("content", $._node_content),
+          optional(field("id", $.id_marker)),
           optional(field("children", $.children))
         ),
         seq(
           field("binding", $.binding),
           /\s*/,
           field("content", $._node_content),
+          optional(field("id", $.id_marker)),
           optional(field("children", $.children))
         ),
         seq(
           field("content", $._node_content),
+          optional(field("id", $.id_marker)),
           optional(field("children", $.children))
         ),
         seq(field("binding", $.binding), /\s*/, field("children", $.children))
       ),
     _node_content: ($) => choice($.content, $.ref),
-    ref: ($) => $._binder,
+    // `@name`, or `@file.name` / `@file#name` for a binding in another document of a workspace.
+    ref: ($) =>
+      seq(
+        token("@"),
+        optional(field("document", $.document_name)),
+        field("identifier", $.identifier)
+      ),
+    // Includes the separator so that, as the longer match, it wins over `identifier`.
+    document_name: ($) => token(prec(-1, /[a-zA-Z0-9_]+[.#]/)),
     identifier: ($) => token(prec(-1, /[a-zA-Z0-9_]+/)),
     _binder: ($) => seq(token("@"), field("identifier", $.identifier)),
     _anonymous_binder: ($) => token("@"),
@@ -59,6 +135,13 @@ module.exports = grammar({
     // This needs to bind higher than content, otherwise the colon in a binding (e.g. `@foo: bar`)
     // will be parsed as the start of a content token.
     _assignment: ($) => token(prec(1, ":")),
-    content: ($) => token(/[^@# \n][^\n]*/),
+    // Runs to the end of the line, except for trailing spaces and a trailing ` ^id` marker. A
+    // ` ^word` with more text after it on the line is part of the content, e.g. `Fix ^bug in parser`.
+    content: ($) =>
+      token(
+        /[^@# \n]([^ \n]| +[^ ^\n]| +\^[^a-zA-Z0-9\n]|( +\^[a-zA-Z0-9]+)+([^a-zA-Z0-9 \n]| +[^ ^\n]| +\^[^a-zA-Z0-9\n]))*/
+      ),
+    // A stable id for a node without a binding, e.g. `Write tests ^k3x9qa`.
+    id_marker: ($) => token(/\^[a-zA-Z0-9]+/),
   },
 });
@@ -0,0 +1,22 @@
+; Content, which may be a priority marker. A document's `priorities:` front matter decides
+; which content is one, so `Parser::semantic_tokens` classifies these rather than a predicate
+; here. Listed first so that priority markers win over other captures.
+(content) @content
+
+; `@name:` and `@:` bindings.
+(binding) @label
+
+; `@name` references.
+(ref) @variable
+
+; `^id` stable ids.
+(id_marker) @comment
+
+; The node that follows `#` in a block header.
+(block_header
+  (node
+    content: (_) @markup.heading))
+
+; Front matter `key: value` attributes.
+(attribute_name) @property
+(attribute_value) @string
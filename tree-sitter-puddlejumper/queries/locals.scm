@@ -0,0 +1,10 @@
+; Bindings are visible anywhere in the document, including before their definition.
+(document) @local.scope
+
+(binding
+  (identifier) @local.definition)
+
+; Qualified refs like `@file.name` point into other documents.
+(ref
+  !document
+  (identifier) @local.reference)
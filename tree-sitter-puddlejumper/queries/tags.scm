@@ -0,0 +1,15 @@
+(block
+  binding: (binding
+    (identifier) @name)) @definition.section
+
+(block
+  header: (block_header
+    (node
+      content: (content) @name))) @definition.section
+
+(node
+  binding: (binding
+    (identifier) @name)) @definition.item
+
+(ref
+  (identifier) @name) @reference.item
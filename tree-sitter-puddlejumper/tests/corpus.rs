@@ -0,0 +1,172 @@
+// Runs the examples in `test/corpus/*.txt` through the Rust binding, mirroring `tree-sitter test`.
+
+#[cfg(feature = "native")]
+use tree_sitter;
+
+#[cfg(feature = "wasm")]
+use tree_sitter_c2rust as tree_sitter;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+struct Example {
+    name: String,
+    input: String,
+    output: String,
+}
+
+fn corpus_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("test").join("corpus")
+}
+
+// Header and divider lines may carry a suffix (e.g. `===|` / `---|`), so that examples whose
+// input contains `---` can still be delimited. Returns the suffix if `line` is a delimiter.
+fn delimiter_suffix(line: &str, c: char) -> Option<&str> {
+    let len = line.len() - line.trim_start_matches(c).len();
+    if len < 3 {
+        return None;
+    }
+    Some(line[len..].trim_end())
+}
+
+// Splits a corpus file into examples. Each example looks like:
+//
+// ===
+// name
+// ===
+// input
+// ---
+// expected s-expression
+fn parse_corpus(text: &str) -> Vec<Example> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut examples = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let suffix = match delimiter_suffix(lines[i], '=') {
+            Some(suffix) => suffix,
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+        let name = lines.get(i + 1).map(|s| s.trim()).unwrap_or("").to_string();
+        i += 3;
+
+        let input_start = i.min(lines.len());
+        while i < lines.len() && delimiter_suffix(lines[i], '-') != Some(suffix) {
+            i += 1;
+        }
+        // Like `tree-sitter test`, only the newline right before the divider is dropped.
+        let input = lines[input_start..i.min(lines.len())].join("\n");
+        i += 1;
+
+        let output_start = i.min(lines.len());
+        while i < lines.len() && delimiter_suffix(lines[i], '=').is_none() {
+            i += 1;
+        }
+        let output = lines[output_start..i.min(lines.len())].join("\n");
+
+        examples.push(Example {
+            name,
+            input,
+            output,
+        });
+    }
+    examples
+}
+
+// Removes `field_name: ` prefixes, since the corpus files don't spell out fields.
+fn strip_fields(sexp: &str) -> String {
+    let mut out = String::new();
+    for token in sexp.split_whitespace() {
+        if token.ends_with(':') && !token.starts_with('(') {
+            continue;
+        }
+        if !out.is_empty() {
+            out.push(' ');
+        }
+        out.push_str(token);
+    }
+    out
+}
+
+// Collapses whitespace so that expected and actual trees can be compared textually.
+fn normalize_sexp(sexp: &str) -> String {
+    strip_fields(sexp).replace("( ", "(").replace(" )", ")")
+}
+
+fn corpus_files() -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(corpus_dir())
+        .expect("Error reading corpus directory")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "txt"))
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn test_corpus() {
+    let mut parser = tree_sitter::Parser::new();
+    parser
+        .set_language(tree_sitter_puddlejumper::language())
+        .expect("Error loading puddlejumper language");
+
+    let mut failures: Vec<String> = Vec::new();
+    let mut count = 0;
+    for path in corpus_files() {
+        let text = fs::read_to_string(&path).unwrap();
+        for example in parse_corpus(&text) {
+            count += 1;
+            let tree = parser.parse(&example.input, None).unwrap();
+            let actual = normalize_sexp(&tree.root_node().to_sexp());
+            let expected = normalize_sexp(&example.output);
+            if actual != expected {
+                failures.push(format!(
+                    "{} ({}):\n  expected: {}\n  actual:   {}",
+                    example.name,
+                    path.file_name().unwrap().to_string_lossy(),
+                    expected,
+                    actual
+                ));
+            }
+        }
+    }
+    assert!(count > 0, "No corpus examples found in {:?}", corpus_dir());
+    assert!(
+        failures.is_empty(),
+        "{} of {} corpus examples failed:\n{}",
+        failures.len(),
+        count,
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn test_parse_corpus() {
+    let examples = parse_corpus("===\nfoo\n===\nhello\n\n---\n\n(document (node (content)))\n");
+    assert_eq!(examples.len(), 1);
+    assert_eq!(examples[0].name, "foo");
+    assert_eq!(examples[0].input, "hello\n");
+    assert_eq!(
+        normalize_sexp(&examples[0].output),
+        "(document (node (content)))"
+    );
+}
+
+#[test]
+fn test_parse_corpus_with_suffix() {
+    let examples = parse_corpus("===|\nfoo\n===|\n---\nhello\n---|\n(document)\n");
+    assert_eq!(examples.len(), 1);
+    assert_eq!(examples[0].input, "---\nhello");
+    assert_eq!(normalize_sexp(&examples[0].output), "(document)");
+}
+
+#[test]
+fn test_normalize_sexp_strips_fields() {
+    assert_eq!(
+        normalize_sexp("(document\n    children: (node content: (content)) )"),
+        "(document (node (content)))"
+    );
+}